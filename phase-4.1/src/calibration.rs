@@ -0,0 +1,280 @@
+/*
+評価値の較正
+NNの出力は任意スケールなので、読み切った局面を使って
+空きマス数ごとに「最終石差」と「勝率」へ変換する係数を当てはめる
+評価関数ごとにスケールが違うので、係数も評価関数(EvalKind)ごとに持つ
+*/
+
+use crate::play::{EvalKind, TimeManager, WIN_SCORE, LOSE_SCORE};
+use crate::selfplay::play_game;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Serialize, Deserialize};
+use std::sync::LazyLock;
+
+pub const STAGE_COUNT: usize = 61;
+
+// 係数はそれぞれの評価関数の重みと同じ場所に置く
+pub const CALIBRATION_PATH: &str = "src/othello_model_pattern_alt.calibration.json";
+pub const LINEAR_CALIBRATION_PATH: &str = "src/eval_linear.calibration.json";
+
+static NETWORK_CALIBRATION: LazyLock<Calibration> = LazyLock::new(|| {
+    Calibration::from_json(include_str!("othello_model_pattern_alt.calibration.json"))
+        .expect("Failed to parse calibration file.")
+});
+
+static LINEAR_CALIBRATION: LazyLock<Calibration> = LazyLock::new(|| {
+    Calibration::from_json(include_str!("eval_linear.calibration.json"))
+        .expect("Failed to parse linear calibration file.")
+});
+
+// 評価関数に合わせた係数
+pub fn for_eval(kind: EvalKind) -> &'static Calibration {
+    match kind {
+        EvalKind::Network => &NETWORK_CALIBRATION,
+        EvalKind::Linear => &LINEAR_CALIBRATION,
+    }
+}
+
+pub fn path_for_eval(kind: EvalKind) -> &'static str {
+    match kind {
+        EvalKind::Network => CALIBRATION_PATH,
+        EvalKind::Linear => LINEAR_CALIBRATION_PATH,
+    }
+}
+
+// 空きマス数1つ分の係数
+// 石差 = disc_slope * eval + disc_intercept
+// 勝率 = sigmoid(win_slope * eval + win_intercept)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageCoefficients {
+    pub disc_slope: f32,
+    pub disc_intercept: f32,
+    pub win_slope: f32,
+    pub win_intercept: f32,
+}
+
+impl Default for StageCoefficients {
+    fn default() -> Self {
+        Self {
+            disc_slope: 1.0,
+            disc_intercept: 0.0,
+            win_slope: 0.2,
+            win_intercept: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    // 空きマス数でインデックス(0..=60)
+    pub stages: Vec<StageCoefficients>,
+}
+
+// 手番側から見た較正済みの評価
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibratedScore {
+    pub raw: f32,
+    pub disc_diff: f32,
+    pub win_prob: f32,
+}
+
+// 当てはめ用のサンプル、evalとdisc_diffはどちらも手番側から見た値
+#[derive(Clone, Copy, Debug)]
+pub struct CalibrationSample {
+    pub empties: u8,
+    pub eval: f32,
+    pub disc_diff: i8,
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl Calibration {
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    fn stage(&self, empties: usize) -> &StageCoefficients {
+        &self.stages[empties.min(self.stages.len() - 1)]
+    }
+
+    // 探索値(Board::evalのスケール)を較正する
+    // 終局のスコア(WIN_SCORE/LOSE_SCORE付き)はそのまま石差に戻す
+    pub fn calibrate(&self, empties: usize, score: f32) -> CalibratedScore {
+        if score >= WIN_SCORE {
+            return CalibratedScore { raw: score, disc_diff: score - WIN_SCORE, win_prob: 1.0 };
+        }
+        if score <= LOSE_SCORE {
+            return CalibratedScore { raw: score, disc_diff: score - LOSE_SCORE, win_prob: 0.0 };
+        }
+
+        let c = self.stage(empties);
+        let disc_diff = (c.disc_slope * score + c.disc_intercept).clamp(-64.0, 64.0);
+        let win_prob = sigmoid((c.win_slope * score + c.win_intercept) as f64) as f32;
+        CalibratedScore { raw: score, disc_diff, win_prob }
+    }
+
+    // 空きマス数ごとに最小二乗(石差)とロジスティック回帰(勝率)で当てはめる
+    // サンプルが少ない段階のために前後WINDOWマスのサンプルもまとめて使う
+    pub fn fit(samples: &[CalibrationSample]) -> Self {
+        const WINDOW: usize = 2;
+        const MIN_SAMPLES: usize = 8;
+
+        let mut fitted: Vec<Option<StageCoefficients>> = vec![None; STAGE_COUNT];
+        for (empties, slot) in fitted.iter_mut().enumerate() {
+            let stage_samples: Vec<&CalibrationSample> = samples.iter()
+                .filter(|s| (s.empties as usize).abs_diff(empties) <= WINDOW)
+                .collect();
+            if stage_samples.len() < MIN_SAMPLES {
+                continue;
+            }
+
+            let (disc_slope, disc_intercept) = fit_linear(&stage_samples);
+            let (win_slope, win_intercept) = fit_logistic(&stage_samples);
+            *slot = Some(StageCoefficients {
+                disc_slope: disc_slope as f32,
+                disc_intercept: disc_intercept as f32,
+                win_slope: win_slope as f32,
+                win_intercept: win_intercept as f32,
+            });
+        }
+
+        // 当てはめられなかった段階は最も近い段階の係数を使う
+        let stages = (0..STAGE_COUNT).map(|empties| {
+            (0..STAGE_COUNT)
+                .filter_map(|e| fitted[e].map(|c| (e.abs_diff(empties), c)))
+                .min_by_key(|&(d, _)| d)
+                .map(|(_, c)| c)
+                .unwrap_or_default()
+        }).collect();
+
+        Self { stages }
+    }
+}
+
+fn fit_linear(samples: &[&CalibrationSample]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean_x = samples.iter().map(|s| s.eval as f64).sum::<f64>() / n;
+    let mean_y = samples.iter().map(|s| s.disc_diff as f64).sum::<f64>() / n;
+    let mut sxx = 0.0;
+    let mut sxy = 0.0;
+    for s in samples {
+        let dx = s.eval as f64 - mean_x;
+        sxx += dx * dx;
+        sxy += dx * (s.disc_diff as f64 - mean_y);
+    }
+    if sxx < 1e-9 {
+        return (0.0, mean_y);
+    }
+    let slope = sxy / sxx;
+    (slope, mean_y - slope * mean_x)
+}
+
+// Newton法によるロジスティック回帰、引き分けは0.5として扱う
+fn fit_logistic(samples: &[&CalibrationSample]) -> (f64, f64) {
+    const ITERATIONS: usize = 50;
+    const RIDGE: f64 = 1e-3;
+
+    let mut a = 0.0;
+    let mut b = 0.0;
+    for _ in 0..ITERATIONS {
+        let (mut ga, mut gb) = (0.0, 0.0);
+        let (mut haa, mut hab, mut hbb) = (RIDGE, 0.0, RIDGE);
+        for s in samples {
+            let x = s.eval as f64;
+            let y = match s.disc_diff.signum() {
+                1 => 1.0,
+                -1 => 0.0,
+                _ => 0.5,
+            };
+            let p = sigmoid(a * x + b);
+            let w = p * (1.0 - p);
+            ga += (y - p) * x;
+            gb += y - p;
+            haa += w * x * x;
+            hab += w * x;
+            hbb += w;
+        }
+        ga -= RIDGE * a;
+        gb -= RIDGE * b;
+
+        let det = haa * hbb - hab * hab;
+        if det.abs() < 1e-12 {
+            break;
+        }
+        let da = (hbb * ga - hab * gb) / det;
+        let db = (haa * gb - hab * ga) / det;
+        a += da;
+        b += db;
+        if da.abs() < 1e-9 && db.abs() < 1e-9 {
+            break;
+        }
+    }
+    (a, b)
+}
+
+// 自己対戦で当てはめ用のサンプルを集める、評価値は既定のエンジンの評価関数の値
+pub fn collect_samples(games: usize, random_plies: usize, solve_empties: usize, seed: u64) -> Vec<CalibrationSample> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let time_manager = TimeManager::new(u64::MAX / 2);
+    let mut samples = Vec::new();
+
+    for _ in 0..games {
+        for (b, disc_diff) in play_game(&mut rng, random_plies, solve_empties, &time_manager) {
+            samples.push(CalibrationSample {
+                empties: (64 - b.get_turn()) as u8,
+                eval: b.get_eval(),
+                disc_diff,
+            });
+        }
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_calibration() {
+        // disc_diff = 2 * eval + 1 となるサンプル
+        let samples: Vec<CalibrationSample> = (0..STAGE_COUNT as u8)
+            .flat_map(|empties| (-10..=10).map(move |e| CalibrationSample {
+                empties,
+                eval: e as f32,
+                disc_diff: (2 * e + 1) as i8,
+            }))
+            .collect();
+        let calibration = Calibration::fit(&samples);
+        assert_eq!(calibration.stages.len(), STAGE_COUNT);
+
+        let score = calibration.calibrate(30, 5.0);
+        assert!((score.disc_diff - 11.0).abs() < 1e-3, "{score:?}");
+        assert!(score.win_prob > 0.9, "{score:?}");
+        assert!(calibration.calibrate(30, -5.0).win_prob < 0.1);
+
+        // 終局スコアは石差に戻る
+        assert_eq!(calibration.calibrate(0, WIN_SCORE + 6.0).disc_diff, 6.0);
+        assert_eq!(calibration.calibrate(0, LOSE_SCORE - 4.0).disc_diff, -4.0);
+
+        let restored = Calibration::from_json(&calibration.to_json()).unwrap();
+        assert_eq!(restored, calibration);
+    }
+
+    #[test]
+    fn test_calibration_file() {
+        for kind in [EvalKind::Network, EvalKind::Linear] {
+            assert_eq!(for_eval(kind).stages.len(), STAGE_COUNT);
+        }
+        // 評価関数ごとにスケールが違うので係数も別になる
+        assert_ne!(for_eval(EvalKind::Network), for_eval(EvalKind::Linear));
+    }
+}
@@ -5,9 +5,11 @@ Boardは全てxが縦、yが横で管理し、(0, 0)が左上である
 
 use crate::play;
 
-use std::sync::LazyLock;
+use crate::engine;
+
+use std::sync::{Arc, OnceLock};
 use std::sync::Once;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::fs::File;
 use std::io::Read;
 
@@ -172,6 +174,9 @@ static mut ROW_TURNTABLE: [u64; 524288] = [0; 524288];
 static mut COL_TURNTABLE: [u64; 524288] = [0; 524288];
 static mut DIAG_TURNTABLE_1: [u64; 524288] = [0; 524288];
 static mut DIAG_TURNTABLE_2: [u64; 524288] = [0; 524288];
+
+// 辺の8マスだけで確定する自分の石、(自分のビット << 8) | 相手のビット で引く
+static mut EDGE_STABILITY: [u8; 65536] = [0; 65536];
 static INIT: Once = Once::new();
 
 // 初期化関数
@@ -535,6 +540,14 @@ pub fn initialize_tables() {
                 }
             }
 
+            // EDGE_STABILITY の初期化
+            for p in 0..256u32 {
+                for o in 0..256u32 {
+                    if p & o == 0 {
+                        EDGE_STABILITY[(p << 8 | o) as usize] = find_edge_stable(p as u8, o as u8, p as u8);
+                    }
+                }
+            }
 
 
         }
@@ -836,45 +849,31 @@ pub fn get_flipper(board: &play::Board, i: usize) -> u64 {
     return flipper;
 }
 
-// グローバルカウンター
-static EVAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
-static NODE_COUNTER: AtomicUsize = AtomicUsize::new(0);
-
-// バックグラウンド思考専用カウンター
-static BACKGROUND_NODE_COUNTER: AtomicUsize = AtomicUsize::new(0);
-
-// カウンター操作関数
-pub fn increment_eval_count() {
-    EVAL_COUNTER.fetch_add(1, Ordering::Relaxed);
-}
-
-pub fn increment_node_count() {
-    NODE_COUNTER.fetch_add(1, Ordering::Relaxed);
-}
-
-pub fn increment_background_node_count() -> usize {
-    BACKGROUND_NODE_COUNTER.fetch_add(1, Ordering::Relaxed) + 1
-}
-
-pub fn get_eval_count() -> usize {
-    EVAL_COUNTER.load(Ordering::Relaxed)
-}
-
-pub fn get_node_count() -> usize {
-    NODE_COUNTER.load(Ordering::Relaxed)
-}
-
-pub fn get_background_node_count() -> usize {
-    BACKGROUND_NODE_COUNTER.load(Ordering::Relaxed)
-}
-
-pub fn reset_counters() {
-    EVAL_COUNTER.store(0, Ordering::Relaxed);
-    NODE_COUNTER.store(0, Ordering::Relaxed);
-}
+// get_directional_flipsの方向の順(北はa1〜h1の側)
+pub const DIRECTIONS: [&str; 8] = ["n", "ne", "e", "se", "s", "sw", "w", "nw"];
 
-pub fn reset_background_counter() {
-    BACKGROUND_NODE_COUNTER.store(0, Ordering::Relaxed);
+// 置いたマスから見た方向ごとの返る石(DIRECTIONSの順)
+// 縦・横・斜めの列の返る石を、置いたマスより添字の大きい側と小さい側に分ける
+// すべて合わせるとget_flipperと同じ
+pub fn get_directional_flips(board: &play::Board, i: usize) -> [u64; 8] {
+    let x = i >> 3;
+    let y = i & 7;
+    let after = u64::MAX << i;
+    let row = get_row_flip(board, x, y);
+    let col = get_col_flip(board, x, y);
+    // 斜め1は右上から左下、斜め2は左上から右下
+    let diag1 = get_diag_flip_1(board, x, y);
+    let diag2 = get_diag_flip_2(board, x, y);
+    [
+        col & !after,
+        diag1 & !after,
+        row & after,
+        diag2 & after,
+        col & after,
+        diag1 & after,
+        row & !after,
+        diag2 & !after,
+    ]
 }
 
 // (自分, 相手)の石の平均自由度を計算する
@@ -905,6 +904,112 @@ pub fn calc_freedom(board: &play::Board) -> (f32, f32) {
     return (my_freedom, opponent_freedom);
 }
 
+// 辺の上で、どう打ち進めてもstableのうち自分の石のまま残るものを探す
+// 辺以外の方向から返される場合も含めるため、何も返さない着手も考える
+fn find_edge_stable(p: u8, o: u8, stable: u8) -> u8 {
+    let stable = stable & p;
+    let empty = !(p | o);
+    if stable == 0 || empty == 0 {
+        return stable;
+    }
+
+    // 打った側(mover)がxに置いたとき、辺の上で返る石
+    fn flips(mover: u8, other: u8, x: u32) -> u8 {
+        let mut result = 0;
+        for dir in [-1i32, 1] {
+            let mut line = 0u8;
+            let mut y = x as i32 + dir;
+            while (0..8).contains(&y) && other & (1 << y) != 0 {
+                line |= 1 << y;
+                y += dir;
+            }
+            if (0..8).contains(&y) && mover & (1 << y) != 0 {
+                result |= line;
+            }
+        }
+        result
+    }
+
+    let mut stable = stable;
+    for x in 0..8 {
+        if empty & (1 << x) == 0 {
+            continue;
+        }
+        let f = flips(p, o, x);
+        stable = find_edge_stable(p | 1 << x | f, o ^ f, stable);
+        if stable == 0 {
+            return 0;
+        }
+        let f = flips(o, p, x);
+        stable = find_edge_stable(p ^ f, o | 1 << x | f, stable);
+        if stable == 0 {
+            return 0;
+        }
+    }
+    stable
+}
+
+// 4辺それぞれで確定する自分の石
+fn get_edge_stable(board: &play::Board) -> u64 {
+    initialize_tables();
+    const FILE_A: u64 = 0x0101010101010101;
+    const FILE_H: u64 = 0x8080808080808080;
+    // 列を8ビットに詰める/戻す
+    let pack_col = |b: u64, shift: u32| ((((b >> shift) & FILE_A).wrapping_mul(0x0102040810204080)) >> 56) as usize;
+    let unpack_col = |v: u8, shift: u32| (0..8).filter(|&i| v & (1 << i) != 0).fold(0u64, |acc, i| acc | 1 << (i * 8 + shift));
+    let lookup = |p: usize, o: usize| unsafe { EDGE_STABILITY[p << 8 | o] };
+
+    let (p, o) = (board.my_board, board.opponent_board);
+    let mut stable = lookup((p & 0xff) as usize, (o & 0xff) as usize) as u64;
+    stable |= (lookup((p >> 56) as usize, (o >> 56) as usize) as u64) << 56;
+    stable |= unpack_col(lookup(pack_col(p, 0), pack_col(o, 0)), 0) & FILE_A;
+    stable |= unpack_col(lookup(pack_col(p, 7), pack_col(o, 7)), 7) & FILE_H;
+    stable
+}
+
+// 埋まっている直線上のマス (横, 縦, 斜め(7方向), 斜め(9方向))
+fn get_full_lines(filled: u64) -> [u64; 4] {
+    let mut full = [0u64; 4];
+    let lines = ROW_MASK.iter().map(|&m| (0, m))
+        .chain(COL_MASK.iter().map(|&m| (1, m)))
+        .chain(DIAG_MASK_1.iter().map(|&m| (2, m)))
+        .chain(DIAG_MASK_2.iter().map(|&m| (3, m)));
+    for (dir, mask) in lines {
+        if filled & mask == mask {
+            full[dir] |= mask;
+        }
+    }
+    full
+}
+
+// 自分の確定石(以後どう打っても返らない石)
+// 辺の確定石と、全方向が埋まった直線上の石から始めて、
+// 4方向それぞれについて「直線が埋まっている・盤端・隣が確定石」のいずれかを満たす石を広げていく
+pub fn get_stable(board: &play::Board) -> u64 {
+    // 盤端にある石はその方向には返らない
+    const FILES_AH: u64 = 0x8181818181818181;
+    const RANKS_18: u64 = 0xff000000000000ff;
+    const BORDER: u64 = FILES_AH | RANKS_18;
+    let p = board.my_board;
+    let full = get_full_lines(p | board.opponent_board);
+
+    let mut stable = get_edge_stable(board) | (p & full[0] & full[1] & full[2] & full[3]);
+    if stable == 0 {
+        return 0;
+    }
+    loop {
+        let horizontal = full[0] | FILES_AH | stable << 1 | stable >> 1;
+        let vertical = full[1] | RANKS_18 | stable << 8 | stable >> 8;
+        let diag_7 = full[2] | BORDER | stable << 7 | stable >> 7;
+        let diag_9 = full[3] | BORDER | stable << 9 | stable >> 9;
+        let next = stable | (p & horizontal & vertical & diag_7 & diag_9);
+        if next == stable {
+            return stable;
+        }
+        stable = next;
+    }
+}
+
 // パターンマッチ用
 // const PATTERN_1 = 0b11111111_01000010_00000000_00000000_00000000_00000000_00000000_00000000;
 // const PATTERN_2 = 0b10000000_11000000_10000000_10000000_10000000_10000000_11000000_10000000;
@@ -934,118 +1039,388 @@ fn print_board(board : &play::Board){
     }
 }
 
+// 置換表の値の種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    None = 0,   // 静的評価など、枝刈りには使わない値
+    Lower = 1,
+    Upper = 2,
+    Exact = 3,
+}
+
+impl Bound {
+    // 探索窓(alpha, beta)に対する値の種類
+    pub fn from_window(value: f32, alpha: f32, beta: f32) -> Self {
+        if value <= alpha {
+            Bound::Upper
+        } else if value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        }
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        match bits & 3 {
+            1 => Bound::Lower,
+            2 => Bound::Upper,
+            3 => Bound::Exact,
+            _ => Bound::None,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct CacheElement {
-    pub my_board: u64,
-    pub opponent_board: u64,
     pub depth: u8,
     pub next_move: u8,
     pub value: f32,
-    pub exact: bool,
+    pub bound: Bound,
     pub complete: bool,
-    pub lower_bound: bool,
-    pub upper_bound: bool
+    generation: u8,
 }
 
-impl CacheElement {
-    pub const fn new() -> Self {
-        CacheElement {
-            my_board: 0,
-            opponent_board: 0,
-            depth: 0,
-            value: 0.0,
-            next_move: 255,
-            exact: false,
-            complete: false,
-            lower_bound: false,
-            upper_bound: false
-        }
-    }
+// 置換表
+// 探索を複数スレッドで行うため、ロックを取らずに読み書きする
+// 1エントリは(自分の盤面^データ, 相手の盤面^データ, データ)の3語で、読み出し時に盤面を照合して
+// 書き込み途中のエントリや別の局面のエントリを捨てる
+// データの上位ビットにはハッシュ値の一部を入れ、別々の書き込みが混ざった場合も照合で弾けるようにする
+#[derive(Default)]
+struct CacheSlot {
+    key1: AtomicU64,
+    key2: AtomicU64,
+    data: AtomicU64,
+}
+
+// 1つのハッシュ値に対してBUCKET_SIZE個のエントリを持ち、置き換えるエントリを選べるようにする
+const BUCKET_SIZE: usize = 4;
+
+#[derive(Default)]
+struct Bucket {
+    slots: [CacheSlot; BUCKET_SIZE],
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const DEFAULT_CACHE_MB: usize = 64;
+#[cfg(target_arch = "wasm32")]
+pub const DEFAULT_CACHE_MB: usize = 32;
+
+// 置換表の大きさの上限
+// WebAssemblyのメモリは4GBまでで、評価関数や完全読みの置換表、複数のエンジンの分も残す
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_CACHE_MB: usize = 4096;
+#[cfg(target_arch = "wasm32")]
+pub const MAX_CACHE_MB: usize = 1024;
+
+// 世代は探索(1手の思考)ごとに進め、古い世代のエントリを優先して置き換える
+const GENERATION_MASK: u8 = 0x3f;
+
+// データの並び: 評価値(32bit) | 深さ(8bit) | 次の手(8bit) | 値の種類(2bit) | complete | 世代(6bit) | 有効 | 照合用(6bit)
+const DATA_VALID: u64 = 1 << 57;
+const DATA_CHECK_SHIFT: u32 = 58;
+
+// 置換表の統計
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct CacheStats {
+    pub size_mb: usize,
+    // 直前の探索の参照とヒット(探索がスレッドごとに数えて最後に記録する)
+    pub probes: u64,
+    pub hits: u64,
+    pub hit_rate: f64,
+    // 現在の世代のエントリが占める割合
+    pub fill: f64,
 }
 
 pub struct Cache {
-    elements: Box<[CacheElement]>  // Boxを使用してスタックオーバーフロー防止
+    buckets: Box<[Bucket]>,  // Boxを使用してスタックオーバーフロー防止
+    generation: AtomicU8,
+    // 参照ごとには数えず、record_probesで探索ごとに足す
+    probes: AtomicU64,
+    hits: AtomicU64,
 }
 
 impl Cache {
-    pub fn new() -> Self {
-        const SIZE : usize = 1 << 22;
-        let elements = (0..SIZE)
-            .map(|_| CacheElement::new())
+    // size_mb以下に収まるだけのバケットを確保する
+    pub fn new(size_mb: usize) -> Self {
+        let count = (size_mb.max(1) << 20) / std::mem::size_of::<Bucket>();
+        let buckets = (0..count)
+            .map(|_| Bucket::default())
             .collect::<Vec<_>>()
             .into_boxed_slice();
-        
-        Cache {elements}
+
+        Cache {
+            buckets,
+            generation: AtomicU8::new(0),
+            probes: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn size_mb(&self) -> usize {
+        (self.buckets.len() * std::mem::size_of::<Bucket>() + (1 << 19)) >> 20
+    }
+
+    // splitmix64の混ぜ方
+    // バケットは上位ビットで選ぶので、xorだけでなく掛け算で全ビットを混ぜる
+    fn key(board : &play::Board) -> u64 {
+        let mut key = board.my_board ^ board.opponent_board.rotate_left(32).wrapping_mul(0x9e3779b97f4a7c15);
+        key = (key ^ (key >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        key = (key ^ (key >> 27)).wrapping_mul(0x94d049bb133111eb);
+        key ^ (key >> 31)
     }
 
-    // ハッシュ関数、安全だがあまり高速でないことに注意
-    // 掛け算をxorに変更したが、安全性?
-    pub fn hash(&self, board : &play::Board) -> usize{
-        let mut key1 = board.my_board;
-        key1 = (key1 ^ (key1 >> 30)) ^ 0xbf58476d1ce4e5b9;
-        key1 = (key1 ^ (key1 >> 27)) ^ 0x94d049bb133111eb;
-        key1 ^= key1 >> 31;
-        let mut key2 = board.opponent_board;
-        key2 = (key2 ^ (key2 >> 30)) ^ 0xbf58476d1ce4e5b9;
-        key2 = (key2 ^ (key2 >> 27)) ^ 0x94d049bb133111eb;
-        key2 ^= key2 >> 31;
-        return ((key1 ^ key2) & 0x3FFFFF) as usize;
+    // バケット数は2冪とは限らないので、剰余の代わりに掛け算の上位ビットで選ぶ
+    fn bucket(&self, key: u64) -> &Bucket {
+        &self.buckets[((key as u128 * self.buckets.len() as u128) >> 64) as usize]
     }
 
-    // getメソッドの修正 - 借用を返すように変更
-    pub fn get(&self, board: &play::Board) -> Option<&CacheElement> {
-        let index = self.hash(board);
-        let element = &self.elements[index];
-        if element.my_board == board.my_board && element.opponent_board == board.opponent_board {
-            Some(element)
+    fn pack(element: &CacheElement, check: u64) -> u64 {
+        element.value.to_bits() as u64
+            | (element.depth as u64) << 32
+            | (element.next_move as u64) << 40
+            | (element.bound as u64) << 48
+            | (element.complete as u64) << 50
+            | ((element.generation & GENERATION_MASK) as u64) << 51
+            | DATA_VALID
+            | check << DATA_CHECK_SHIFT
+    }
+
+    fn unpack(data: u64) -> CacheElement {
+        CacheElement {
+            depth: (data >> 32) as u8,
+            next_move: (data >> 40) as u8,
+            value: f32::from_bits(data as u32),
+            bound: Bound::from_bits(data >> 48),
+            complete: (data >> 50) & 1 != 0,
+            generation: (data >> 51) as u8 & GENERATION_MASK,
+        }
+    }
+
+    // スロットに盤面が入っていればそのデータを返す
+    fn load(slot: &CacheSlot, board: &play::Board) -> Option<u64> {
+        let data = slot.data.load(Ordering::Relaxed);
+        if data & DATA_VALID != 0
+            && slot.key1.load(Ordering::Relaxed) ^ data == board.my_board
+            && slot.key2.load(Ordering::Relaxed) ^ data == board.opponent_board {
+            Some(data)
         } else {
             None
         }
     }
 
-    pub fn set(&mut self, board: &play::Board, depth: u8, next_move: u8, value: f32, exact: bool, complete: bool, lower_bound: bool, upper_bound: bool) {
-        let index = self.hash(board);
-        let element = &mut self.elements[index];
-        if element.my_board == board.my_board && element.opponent_board == board.opponent_board && depth < element.depth {
-            return;
+    // 新しい探索を始めるときに呼び、世代を進めて統計をリセットする
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.probes.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+    }
+
+    fn current_generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed) & GENERATION_MASK
+    }
+
+    // 書き込み中の値を読まないよう、エントリの複製を返す
+    pub fn get(&self, board: &play::Board) -> Option<CacheElement> {
+        let bucket = self.bucket(Self::key(board));
+        let data = bucket.slots.iter().find_map(|slot| Self::load(slot, board))?;
+        Some(Self::unpack(data))
+    }
+
+    // 探索で数えた参照とヒットを統計に足す
+    pub fn record_probes(&self, probes: u64, hits: u64) {
+        self.probes.fetch_add(probes, Ordering::Relaxed);
+        self.hits.fetch_add(hits, Ordering::Relaxed);
+    }
+
+    // 同じ局面があれば、今の世代のより深いエントリでない限り上書きする
+    // なければ空きか、深さが浅く世代の古いエントリを置き換える
+    pub fn set(&self, board: &play::Board, depth: u8, next_move: u8, value: f32, bound: Bound, complete: bool) {
+        let key = Self::key(board);
+        let bucket = self.bucket(key);
+        let generation = self.current_generation();
+
+        let mut victim = &bucket.slots[0];
+        let mut victim_priority = i32::MAX;
+        for slot in bucket.slots.iter() {
+            if let Some(data) = Self::load(slot, board) {
+                let old = Self::unpack(data);
+                if old.generation == generation && depth < old.depth {
+                    return;
+                }
+                victim = slot;
+                break;
+            }
+            let data = slot.data.load(Ordering::Relaxed);
+            let priority = if data & DATA_VALID == 0 {
+                i32::MIN
+            } else {
+                let old = Self::unpack(data);
+                let age = generation.wrapping_sub(old.generation) & GENERATION_MASK;
+                old.depth as i32 - 8 * age as i32
+            };
+            if priority < victim_priority {
+                victim = slot;
+                victim_priority = priority;
+            }
+        }
+
+        let element = CacheElement { depth, next_move, value, bound, complete, generation };
+        let data = Self::pack(&element, key >> DATA_CHECK_SHIFT);
+        victim.key1.store(board.my_board ^ data, Ordering::Relaxed);
+        victim.key2.store(board.opponent_board ^ data, Ordering::Relaxed);
+        victim.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            for slot in bucket.slots.iter() {
+                slot.data.store(0, Ordering::Relaxed);
+            }
+        }
+        self.probes.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+    }
+
+    // 充填率は先頭の1000バケットから見積もる
+    pub fn stats(&self) -> CacheStats {
+        let generation = self.current_generation();
+        let sample = &self.buckets[..self.buckets.len().min(1000)];
+        let used = sample.iter()
+            .flat_map(|bucket| bucket.slots.iter())
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                data & DATA_VALID != 0 && Self::unpack(data).generation == generation
+            })
+            .count();
+        let probes = self.probes.load(Ordering::Relaxed);
+        let hits = self.hits.load(Ordering::Relaxed);
+        CacheStats {
+            size_mb: self.size_mb(),
+            probes,
+            hits,
+            hit_rate: if probes > 0 { hits as f64 / probes as f64 } else { 0.0 },
+            fill: used as f64 / (sample.len() * BUCKET_SIZE) as f64,
         }
-        element.my_board = board.my_board;
-        element.opponent_board = board.opponent_board;
-        element.depth = depth;
-        element.next_move = next_move;
-        element.value = value;
-        element.exact = exact;
-        element.complete = complete;
-        element.lower_bound = lower_bound;
-        element.upper_bound = upper_bound;
     }
 }
-static CACHE: LazyLock<std::sync::Mutex<Cache>> = LazyLock::new(|| {
-    std::sync::Mutex::new(Cache::new())
-});
-pub fn get_cache() -> std::sync::MutexGuard<'static, Cache> {
-    CACHE.lock().unwrap()
+
+// 既定のエンジンの置換表
+// 探索中は置換表をArcで持つので、大きさを変えても探索中の置換表はそのまま使われる
+pub fn get_cache() -> Arc<Cache> {
+    engine::default_engine().cache_handle()
+}
+
+pub fn set_cache_size_mb(size_mb: usize) {
+    engine::update_default_engine(|engine| engine.set_cache_size_mb(size_mb));
+}
+
+pub fn clear_cache() {
+    engine::default_engine().clear_cache();
 }
 
 use hashbrown::hash_map as base;
-static BOOK: LazyLock<std::sync::Mutex<base::HashMap<play::Board, u8>>> = LazyLock::new(|| {
-    std::sync::Mutex::new(base::HashMap::new())
-});
 
-static INIT_BOOK: Once = Once::new();
+// 定石
+// 読み込んだ後は書き換えないので、エンジン間ではArcで共有する
+#[derive(Default)]
+pub struct Book {
+    moves: base::HashMap<play::Board, u8>,
+}
+
+static BOOK: OnceLock<Arc<Book>> = OnceLock::new();
+
+// 読み込んだ定石(init_bookの前なら空の定石)
+pub fn get_book() -> Arc<Book> {
+    BOOK.get().cloned().unwrap_or_default()
+}
+
+// 定石のファイル(ツールで作り直すときの出力先)
+pub const BOOK_PATH: &str = "src/book.bin";
+
 pub fn init_book() {
-    INIT_BOOK.call_once(|| {
+    BOOK.get_or_init(|| {
         #[cfg(target_arch = "wasm32")]
         let buffer = include_bytes!("book.bin").to_vec();
         
         #[cfg(not(target_arch = "wasm32"))]
         let buffer = {
-            let mut file = File::open("src/book.bin").expect("Failed to open book.bin");
+            let mut file = File::open(BOOK_PATH).expect("Failed to open book.bin");
             let mut buffer = Vec::new();
             file.read_to_end(&mut buffer).expect("Failed to read book.bin");
             buffer
         };
 
+        Arc::new(Book::from_bytes(&buffer))
+    });
+}
+
+// 対称な8通りの向きのうち最も小さい盤面と、手をその向きに移したマス
+// 盤面が対称なら、移したマスも最も小さい向きを選ぶ(対称な手を同じ手として数える)
+pub fn canonical_move(board: &play::Board, mv: u8) -> (play::Board, u8) {
+    let boards = get_eq_board(board);
+    let moves = get_eq_board(&play::Board { my_board: 1 << mv, opponent_board: 0 });
+    let i = (0..8)
+        .min_by_key(|&i| (boards[i].my_board, boards[i].opponent_board, moves[i].my_board.trailing_zeros()))
+        .unwrap();
+    (boards[i], moves[i].my_board.trailing_zeros() as u8)
+}
+
+fn get_eq_board(board: &play::Board) -> [play::Board; 8] {
+    fn turn_board(board: &play::Board) -> play::Board {
+		let mut new_board = play::Board {
+			my_board: 0,
+			opponent_board: 0,
+		};
+		for i in 0..64 {
+			let x = i % 8;
+			let y = i / 8;
+			if board.check_mine(x, y) {
+				new_board.my_board |= 1 << ((7 - x) * 8 + y);
+			} else if board.check_opponent(x, y) {
+				new_board.opponent_board |= 1 << ((7 - x) * 8 + y);
+			}
+		}
+		new_board
+	}
+
+	fn flip_board(board: &play::Board) -> play::Board {
+		let mut new_board = play::Board {
+			my_board: 0,
+			opponent_board: 0,
+		};
+		for i in 0..64 {
+			let x = i % 8;
+			let y = i / 8;
+			if board.check_mine(x, y) {
+				new_board.my_board |= 1 << (y * 8 + (7 - x));
+			} else if board.check_opponent(x, y) {
+				new_board.opponent_board |= 1 << (y * 8 + (7 - x));
+			}
+		}
+		new_board
+	}
+
+	let mut eq_boards = [play::Board {
+		my_board: 0,
+		opponent_board: 0,
+	}; 8];
+	eq_boards[0] = board.clone();
+	eq_boards[1] = turn_board(&eq_boards[0]);
+	eq_boards[2] = turn_board(&eq_boards[1]);
+	eq_boards[3] = turn_board(&eq_boards[2]);
+	eq_boards[4] = flip_board(&eq_boards[0]);
+	eq_boards[5] = turn_board(&eq_boards[4]);
+	eq_boards[6] = turn_board(&eq_boards[5]);
+	eq_boards[7] = turn_board(&eq_boards[6]);
+
+	eq_boards
+}
+
+impl Book {
+    // book.binの形式
+    // 局面ごとに64マスを2ビットずつ((0, 0): 空き, (1, 0): my_board, (0, 1): opponent_board、(1, 1)の後の5ビットは続く空きマスの数)、
+    // その後に6ビットで手を書く(どれも下位ビットから、盤面は打つ側と入れ替えたもの)
+    pub fn from_bytes(buffer: &[u8]) -> Self {
         let mut i = 0;
         let mut j = 0;
         fn succ(i: usize, j: usize) -> (usize, usize) {
@@ -1057,7 +1432,10 @@ pub fn init_book() {
             }
         }
         
-        let mut book = BOOK.lock().unwrap();
+        let mut book = Book::default();
+        if buffer.is_empty() {
+            return book;
+        }
         
         loop {
             let mut seen = 0;
@@ -1103,99 +1481,94 @@ pub fn init_book() {
                 (i, j) = succ(i, j);
             }
 
-            unsafe{
-                book.insert(board, mv);
-            }
+            book.moves.insert(board, mv);
             //println!("Inserted board:\n{}\n, move: {}", board, mv);
 
             if i >= buffer.len() - 1 {
                 break;
             }
         }
-    });
-}
+        book
+    }
 
-fn get_eq_board(board: &play::Board) -> [play::Board; 8] {
-    fn turn_board(board: &play::Board) -> play::Board {
-		let mut new_board = play::Board {
-			my_board: 0,
-			opponent_board: 0,
-		};
-		for i in 0..64 {
-			let x = i % 8;
-			let y = i / 8;
-			if board.check_mine(x, y) {
-				new_board.my_board |= 1 << ((7 - x) * 8 + y);
-			} else if board.check_opponent(x, y) {
-				new_board.opponent_board |= 1 << ((7 - x) * 8 + y);
-			}
-		}
-		new_board
-	}
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn push(value: u64, count: usize, bits: &mut Vec<bool>) {
+            for k in 0..count {
+                bits.push(value >> k & 1 != 0);
+            }
+        }
+        let mut bits: Vec<bool> = Vec::new();
+        // 書く順を決めておく(同じ定石なら同じファイルになる)
+        let mut entries: Vec<_> = self.moves.iter().collect();
+        entries.sort_by_key(|(board, _)| (board.my_board, board.opponent_board));
+        for (board, &mv) in entries {
+            let mut seen = 0;
+            while seen < 64 {
+                let empty = !(board.my_board | board.opponent_board) >> seen;
+                let run = (empty.trailing_ones() as usize).min(31);
+                if run >= 2 {
+                    push(0b11, 2, &mut bits);
+                    push(run as u64, 5, &mut bits);
+                    seen += run;
+                    continue;
+                }
+                let code = if board.my_board >> seen & 1 != 0 { 0b01 } else if board.opponent_board >> seen & 1 != 0 { 0b10 } else { 0b00 };
+                push(code, 2, &mut bits);
+                seen += 1;
+            }
+            push(mv as u64, 6, &mut bits);
+        }
+        bits.chunks(8)
+            .map(|byte| byte.iter().enumerate().fold(0u8, |acc, (k, &b)| acc | (b as u8) << k))
+            .collect()
+    }
 
-	fn flip_board(board: &play::Board) -> play::Board {
-		let mut new_board = play::Board {
-			my_board: 0,
-			opponent_board: 0,
-		};
-		for i in 0..64 {
-			let x = i % 8;
-			let y = i / 8;
-			if board.check_mine(x, y) {
-				new_board.my_board |= 1 << (y * 8 + (7 - x));
-			} else if board.check_opponent(x, y) {
-				new_board.opponent_board |= 1 << (y * 8 + (7 - x));
-			}
-		}
-		new_board
-	}
+    // 手番側から見た盤面で打つ手を加える
+    pub fn insert(&mut self, board: &play::Board, mv: u8) {
+        let mut board = *board;
+        board.change_turn();
+        self.moves.insert(board, mv);
+    }
 
-	let mut eq_boards = [play::Board {
-		my_board: 0,
-		opponent_board: 0,
-	}; 8];
-	eq_boards[0] = board.clone();
-	eq_boards[1] = turn_board(&eq_boards[0]);
-	eq_boards[2] = turn_board(&eq_boards[1]);
-	eq_boards[3] = turn_board(&eq_boards[2]);
-	eq_boards[4] = flip_board(&eq_boards[0]);
-	eq_boards[5] = turn_board(&eq_boards[4]);
-	eq_boards[6] = turn_board(&eq_boards[5]);
-	eq_boards[7] = turn_board(&eq_boards[6]);
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
 
-	eq_boards
-}
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
 
-pub fn lookup_book(input_board: &play::Board) -> Option<u8> {
-    let book = BOOK.lock().unwrap();
-    let mut board = input_board.clone();
-    board.change_turn();
+    pub fn lookup(&self, input_board: &play::Board) -> Option<u8> {
+        let book = &self.moves;
+        let mut board = input_board.clone();
+        board.change_turn();
 
-    fn conv(x: u8, y: u8) -> u8 {
-        y * 8 + x
-    }
+        fn conv(x: u8, y: u8) -> u8 {
+            y * 8 + x
+        }
 
-    let eq_boards = get_eq_board(&board);
-    for i in 0..8 {
-        if let Some(&mv) = book.get(&eq_boards[i]) {
-            let x = mv % 8;
-            let y = mv / 8;
-
-            return match i {
-                0 => Some(conv(x, y)), 
-                1 => Some(conv(7 - y, x)),
-                2 => Some(conv(7 - x, 7 - y)),
-                3 => Some(conv(y, 7 - x)),
-                4 => Some(conv(7 - x, y)),
-                5 => Some(conv(y, x)),
-                6 => Some(conv(x, 7 - y)),
-                7 => Some(conv(7 - y, 7 - x)),
-                _ => unreachable!(),
-            };
+        let eq_boards = get_eq_board(&board);
+        for i in 0..8 {
+            if let Some(&mv) = book.get(&eq_boards[i]) {
+                let x = mv % 8;
+                let y = mv / 8;
+
+                return match i {
+                    0 => Some(conv(x, y)), 
+                    1 => Some(conv(7 - y, x)),
+                    2 => Some(conv(7 - x, 7 - y)),
+                    3 => Some(conv(y, 7 - x)),
+                    4 => Some(conv(7 - x, y)),
+                    5 => Some(conv(y, x)),
+                    6 => Some(conv(x, 7 - y)),
+                    7 => Some(conv(7 - y, 7 - x)),
+                    _ => unreachable!(),
+                };
+            }
         }
-    }
 
-    None
+        None
+    }
 }
 
 use std::io::Write;
@@ -1308,11 +1681,117 @@ mod tests {
         print_board_u64(get_flipper(&test_board_3, 19));
     }
 
+    #[test]
+    fn test_cache() {
+        let cache = Cache::new(1);
+        assert_eq!(cache.size_mb(), 1);
+        let board = play::Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        assert!(cache.get(&board).is_none());
+
+        cache.set(&board, 5, 19, -1.5, Bound::Lower, true);
+        let node = cache.get(&board).unwrap();
+        assert_eq!((node.depth, node.next_move, node.value, node.bound, node.complete), (5, 19, -1.5, Bound::Lower, true));
+
+        // 同じ世代では浅い結果で上書きしない、次の探索からは上書きする
+        cache.set(&board, 3, 26, 2.0, Bound::Exact, false);
+        assert_eq!(cache.get(&board).unwrap().depth, 5);
+        cache.new_search();
+        cache.set(&board, 3, 26, 2.0, Bound::Exact, false);
+        assert_eq!(cache.get(&board).unwrap().bound, Bound::Exact);
+
+        // 参照は数えず、記録した分だけが統計に入る
+        cache.record_probes(4, 3);
+        let stats = cache.stats();
+        assert_eq!((stats.probes, stats.hits, stats.hit_rate), (4, 3, 0.75));
+        for i in 0..50000 {
+            cache.set(&play::Board { my_board: i, opponent_board: 1 << 63 }, 1, 0, 0.0, Bound::None, false);
+        }
+        assert!(cache.stats().fill > 0.5);
+        cache.clear();
+        assert!(cache.get(&board).is_none());
+        assert_eq!(cache.stats().fill, 0.0);
+
+        // 複数スレッドから同時に書いても、読めた値は必ずその局面のもの
+        let cache = Cache::new(1);
+        std::thread::scope(|scope| {
+            for t in 0..4u64 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    let mut x = t + 1;
+                    for _ in 0..100000 {
+                        x ^= x << 13;
+                        x ^= x >> 7;
+                        x ^= x << 17;
+                        let board = play::Board { my_board: x & 0xffff, opponent_board: x >> 48 };
+                        let value = (board.my_board ^ board.opponent_board) as u16 as f32;
+                        cache.set(&board, (x >> 20) as u8 & 63, 0, value, Bound::Exact, false);
+                        if let Some(node) = cache.get(&board) {
+                            assert_eq!(node.value, value);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_stable() {
+        use rand::{Rng, SeedableRng};
+
+        let initial = play::Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        assert_eq!(get_stable(&initial), 0);
+
+        // 隅とそれにつながる辺の石
+        let board = play::Board { my_board: 0x0000000000000107, opponent_board: 0x0000000000000200 };
+        assert_eq!(get_stable(&board), 0x0000000000000107);
+
+        // 埋まった盤面の石は全て確定
+        let full = play::Board { my_board: 0x00ff00ff00ff00ff, opponent_board: 0xff00ff00ff00ff00 };
+        assert_eq!(get_stable(&full), full.my_board);
+
+        // ランダムに打ち進めても、確定石と判定した石は返らない
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let random_move = |rng: &mut rand::rngs::StdRng, moves: u64| {
+            let n = rng.gen_range(0..moves.count_ones());
+            (0..64u8).filter(|&m| (moves >> m) & 1 != 0).nth(n as usize).unwrap()
+        };
+        for _ in 0..200 {
+            let mut board = initial;
+            let mut side = false;
+            let mut stable = (0u64, 0u64); // (先手, 後手)の確定石
+            loop {
+                let moves = board.get_valid_moves();
+                if moves == 0 {
+                    let mut t = board;
+                    t.change_turn();
+                    if t.get_valid_moves() == 0 {
+                        break;
+                    }
+                    board = t;
+                    side = !side;
+                    continue;
+                }
+                board.do_move(random_move(&mut rng, moves));
+                board.change_turn();
+                side = !side;
+
+                let mut t = board;
+                t.change_turn();
+                let (mine, theirs) = if side { (t.my_board, board.my_board) } else { (board.my_board, t.my_board) };
+                assert_eq!(stable.0 & !mine, 0, "stable disc flipped");
+                assert_eq!(stable.1 & !theirs, 0, "stable disc flipped");
+                let (s_mine, s_theirs) = (get_stable(&board), get_stable(&t));
+                assert_eq!(s_mine & !board.my_board, 0);
+                if side { stable.1 |= s_mine; stable.0 |= s_theirs; } else { stable.0 |= s_mine; stable.1 |= s_theirs; }
+            }
+        }
+    }
+
     #[test]
     fn test_book() {
         init_book();
 
-        let book = BOOK.lock().unwrap();
+        let book = &get_book().moves;
         let mut file = std::fs::File::create("log.txt").expect("Failed to create log.txt");
 
         writeln!(file, "Book contents ({} entries):", book.len()).unwrap();
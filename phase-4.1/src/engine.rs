@@ -0,0 +1,501 @@
+/*
+探索エンジン
+置換表、完全読みの置換表、評価関数の種類、定石、ProbCutの係数、乱数、探索の統計、ログを1つにまとめて持つ
+エンジン同士は状態を共有しない(定石とProbCutの係数は読み込んだものを共有するが書き換えない)ので、
+対局の両方のプレイヤーにそれぞれエンジンを持たせても互いの置換表や設定に影響しない
+Board::decide_moveなどの従来の関数は既定のエンジンを使う
+*/
+
+use crate::calibration::{self, CalibratedScore, Calibration};
+use crate::database::{self, Book, Cache};
+use crate::options::{self, EngineOptions, OptionValue};
+use crate::play::{self, Board, EvalKind, Hint, SearchLimits, SearchStats, WIN_SCORE, LOSE_SCORE};
+use crate::probcut::{self, ProbCut};
+use crate::solver::{SolverTable, SOLVER_TABLE_BITS};
+
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+// 直前の探索の統計
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineStats {
+    pub nodes: u64,
+    pub evals: u64,
+    // 置換表の値で打ち切ったノード数
+    pub tt_cutoffs: u64,
+}
+
+// ログの出力先
+pub enum Logger {
+    // ファイルに追記する(ネイティブ版の既定はlog.txt)
+    #[cfg(not(target_arch = "wasm32"))]
+    File(PathBuf),
+    // 貯めておいて後で取り出す(WebAssembly版の既定、コンソールにも出す)
+    Buffer(Mutex<Vec<String>>),
+    Silent,
+}
+
+impl Default for Logger {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn default() -> Self {
+        Logger::File(PathBuf::from("log.txt"))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn default() -> Self {
+        Logger::Buffer(Mutex::new(Vec::new()))
+    }
+}
+
+impl Logger {
+    pub fn write(&self, message: &str) {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Logger::File(path) => {
+                if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+                    let _ = writeln!(file, "{}", message);
+                }
+            }
+            Logger::Buffer(buffer) => {
+                if let Ok(mut buffer) = buffer.lock() {
+                    buffer.push(message.to_string());
+                }
+                #[cfg(target_arch = "wasm32")]
+                web_sys::console::log_1(&message.into());
+            }
+            Logger::Silent => (),
+        }
+    }
+
+    // 貯めたログ(Buffer以外は空)
+    pub fn contents(&self) -> String {
+        match self {
+            Logger::Buffer(buffer) => buffer.lock().map(|b| b.join("\n")).unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Logger::Buffer(buffer) = self {
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.clear();
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn random_seed() -> u64 {
+    // WebAssembly環境では現在時刻とランダム値を組み合わせてシード生成
+    let timestamp = js_sys::Date::now() as u64;
+    let random_js = (js_sys::Math::random() * (u32::MAX as f64)) as u64;
+    timestamp ^ random_js
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn random_seed() -> u64 {
+    // ネイティブ環境ではjs_sysが使えないためシステム時刻からシード生成
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+pub struct Engine {
+    cache: Arc<Cache>,
+    solver_table: Arc<SolverTable>,
+    book: Arc<Book>,
+    probcut: Arc<ProbCut>,
+    options: EngineOptions,
+    // xorshiftの状態(0にはしない)
+    rng: AtomicU64,
+    background_nodes: AtomicU64,
+    last_search: Mutex<SearchStats>,
+    logger: Arc<Logger>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    // 定石はinit_bookで読み込んだものを使う(その前に作ったエンジンは定石を使わない)
+    pub fn new() -> Self {
+        Self::with_options(EngineOptions::default())
+    }
+
+    pub fn with_cache_size_mb(size_mb: usize) -> Self {
+        Self::with_options(EngineOptions { hash_mb: size_mb, ..EngineOptions::default() })
+    }
+
+    pub fn with_options(options: EngineOptions) -> Self {
+        let engine = Self {
+            cache: Arc::new(Cache::new(options.hash_mb)),
+            solver_table: Arc::new(SolverTable::new(SOLVER_TABLE_BITS)),
+            book: database::get_book(),
+            probcut: probcut::for_eval(options.evaluator),
+            options,
+            rng: AtomicU64::new(1),
+            background_nodes: AtomicU64::new(0),
+            last_search: Mutex::new(SearchStats::default()),
+            logger: Arc::new(Logger::default()),
+        };
+        engine.set_seed(random_seed());
+        engine
+    }
+
+    // 置換表・定石・ProbCutの係数・ログを共有し、設定と乱数の状態を写したエンジン(統計は0から数える)
+    pub fn duplicate(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            solver_table: self.solver_table.clone(),
+            book: self.book.clone(),
+            probcut: self.probcut.clone(),
+            options: self.options,
+            rng: AtomicU64::new(self.rng.load(Ordering::Relaxed)),
+            background_nodes: AtomicU64::new(0),
+            last_search: Mutex::new(SearchStats::default()),
+            logger: self.logger.clone(),
+        }
+    }
+
+    // 置換表の大きさを変える、内容は消える
+    pub fn set_cache_size_mb(&mut self, size_mb: usize) {
+        self.options.hash_mb = size_mb;
+        self.cache = Arc::new(Cache::new(size_mb));
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    // 完全読みに使うスレッド数(設定solver_threadsが0なら利用できるコア数)
+    pub fn solver_threads(&self) -> usize {
+        options::thread_count(self.options.solver_threads)
+    }
+
+    // 名前と文字列で設定する(options::OPTIONSの名前)
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let (hash_mb, evaluator) = (self.options.hash_mb, self.options.evaluator);
+        self.options.set(name, value)?;
+        if self.options.hash_mb != hash_mb {
+            self.set_cache_size_mb(self.options.hash_mb);
+        }
+        if self.options.evaluator != evaluator {
+            self.set_eval_kind(self.options.evaluator);
+        }
+        Ok(())
+    }
+
+    pub fn get_option(&self, name: &str) -> Option<OptionValue> {
+        self.options.get(name)
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    pub fn cache_handle(&self) -> Arc<Cache> {
+        self.cache.clone()
+    }
+
+    // 置換表と完全読みの置換表を空にする
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+        self.solver_table.clear();
+    }
+
+    pub fn solver_table(&self) -> &SolverTable {
+        &self.solver_table
+    }
+
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    // 定石を差し替える(空の定石なら定石を使わない)
+    pub fn set_book(&mut self, book: Arc<Book>) {
+        self.book = book;
+    }
+
+    // Multi-ProbCutと選択的読み切りの係数
+    pub fn probcut(&self) -> &ProbCut {
+        &self.probcut
+    }
+
+    // 係数を差し替える(打ち切るかどうかと確からしさは設定で変える、評価関数を切り替えると戻る)
+    pub fn set_probcut(&mut self, probcut: Arc<ProbCut>) {
+        self.probcut = probcut;
+    }
+
+    // ProbCutの係数も評価関数に合わせて同梱のものに戻す
+    pub fn set_eval_kind(&mut self, kind: EvalKind) {
+        self.options.evaluator = kind;
+        self.probcut = probcut::for_eval(kind);
+    }
+
+    pub fn eval_kind(&self) -> EvalKind {
+        self.options.evaluator
+    }
+
+    // 解析用にMulti-ProbCutを止める
+    pub fn set_selectivity(&mut self, selectivity: bool) {
+        self.options.selectivity = selectivity;
+    }
+
+    // 予測値の何σ外なら打ち切るか、大きいほど慎重になる
+    pub fn set_probcut_confidence(&mut self, t: f32) {
+        self.options.probcut_confidence = t;
+    }
+
+    // 同じシードなら評価値に加える乱れも同じになる
+    pub fn set_seed(&self, seed: u64) {
+        self.rng.store(if seed == 0 { 0x123456789ABCDEF } else { seed }, Ordering::Relaxed);
+    }
+
+    fn xorshift(&self) -> u64 {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.store(x, Ordering::Relaxed);
+        x
+    }
+
+    fn rand(&self, l: f32, r: f32) -> f32 {
+        let rand_val = self.xorshift() as f32 / (u64::MAX as f32);
+        l + (r - l) * rand_val
+    }
+
+    // 直前のsearchの統計の一部(last_search_statsから取り出す)
+    pub fn stats(&self) -> EngineStats {
+        let stats = self.last_search_stats();
+        EngineStats { nodes: stats.nodes, evals: stats.evals, tt_cutoffs: stats.tt_cutoffs }
+    }
+
+    // 直前のsearch(decide_moveを含む)の統計
+    pub fn last_search_stats(&self) -> SearchStats {
+        *self.last_search.lock().unwrap()
+    }
+
+    // バックグラウンド思考のノード数は別に数える
+    pub(crate) fn count_background_node(&self) -> u64 {
+        self.background_nodes.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub(crate) fn reset_background_nodes(&self) {
+        self.background_nodes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn background_nodes(&self) -> u64 {
+        self.background_nodes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = Arc::new(logger);
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    pub fn log(&self, message: &str) {
+        self.logger.write(message);
+    }
+
+    // 手番側から見た評価値に、±disturbanceの一様な乱れを加える
+    pub fn eval(&self, board: &Board, disturbance: f32) -> f32 {
+        let my_piece_count = board.my_board.count_ones() as i32;
+        let op_piece_count = board.opponent_board.count_ones() as i32;
+        let turn = (my_piece_count + op_piece_count) as usize;
+
+        if turn == 64 {
+            let diff = my_piece_count - op_piece_count;
+            return if my_piece_count > op_piece_count {
+                WIN_SCORE + diff as f32
+            }
+            else if my_piece_count < op_piece_count {
+                LOSE_SCORE + diff as f32
+            }
+            else {
+                0.0
+            };
+        }
+
+        let my_placeable = board.get_valid_moves();
+        let mut t = board.clone();
+        t.change_turn();
+        let op_placeable = t.get_valid_moves();
+
+        if my_placeable == 0 && op_placeable == 0 {
+            let diff = my_piece_count - op_piece_count;
+            return if my_piece_count > op_piece_count {
+                WIN_SCORE + diff as f32
+            }
+            else if my_piece_count < op_piece_count {
+                LOSE_SCORE + diff as f32
+            }
+            else {
+                0.0
+            };
+        }
+
+        // 線形評価関数は手番側から見た値を返す
+        if self.eval_kind() == EvalKind::Linear {
+            let base_eval = crate::linear_eval::LINEAR_EVAL.eval(board);
+            return base_eval + self.rand(-disturbance, disturbance);
+        }
+
+        // WebAssembly環境では専用の評価関数を使用
+        #[cfg(target_arch = "wasm32")]
+        let base_eval = -crate::eval_wasm::EVAL_FUNCTION.eval(board);
+        #[cfg(not(target_arch = "wasm32"))]
+        let base_eval = -crate::eval::EVAL_FUNCTION.eval(board);
+        base_eval + self.rand(-disturbance, disturbance)
+    }
+
+    // 評価関数に合わせた較正係数
+    pub fn calibration(&self) -> &'static Calibration {
+        calibration::for_eval(self.eval_kind())
+    }
+
+    // 石差と勝率に較正した評価値
+    pub fn calibrated_eval(&self, board: &Board) -> CalibratedScore {
+        self.calibration().calibrate(64 - board.get_turn(), self.eval(board, 0.0))
+    }
+
+    // 時間だけを指定し、読み切りを始める空きマス数は設定に従う
+    pub fn decide_move(&self, board: &Board, assigned_time_ms: u64, disturbance: f32) -> usize {
+        let limits = SearchLimits {
+            solve_empties: self.options.solve_empties,
+            ..SearchLimits::time(assigned_time_ms)
+        };
+        self.search(board, &limits, disturbance).0
+    }
+
+    // 制限つきで手を決める、(手, 探索の統計)を返す
+    // 統計はlast_search_statsでも取り出せる
+    pub fn search(&self, board: &Board, limits: &SearchLimits, disturbance: f32) -> (usize, SearchStats) {
+        let (mv, stats) = play::search(self, board, limits, disturbance);
+        *self.last_search.lock().unwrap() = stats;
+        (mv, stats)
+    }
+
+    // 各合法手を反復深化で評価し、較正済みの値で良い順に返す
+    pub fn hints(&self, board: &Board, assigned_time_ms: u64) -> Vec<Hint> {
+        play::hints(self, board, assigned_time_ms)
+    }
+
+    // 反復深化で深さ1..=max_depthの探索値を順に返す(時間制限なし)
+    pub fn search_values(&self, board: &Board, max_depth: u8) -> Vec<f32> {
+        play::search_values(self, board, max_depth)
+    }
+
+    // 中断可能な浅い探索
+    pub fn decide_move_background(&self, board: &Board, stop_flag: &AtomicBool) -> usize {
+        play::search_background(self, board, stop_flag)
+    }
+}
+
+// 既定のエンジン
+// 設定を変えるときは写しを作って差し替えるので、探索中のエンジンは元の設定のまま最後まで読む
+static DEFAULT_ENGINE: LazyLock<RwLock<Arc<Engine>>> = LazyLock::new(|| RwLock::new(Arc::new(Engine::new())));
+
+pub fn default_engine() -> Arc<Engine> {
+    DEFAULT_ENGINE.read().unwrap().clone()
+}
+
+pub fn update_default_engine<R>(f: impl FnOnce(&mut Engine) -> R) -> R {
+    let mut engine = DEFAULT_ENGINE.write().unwrap();
+    let mut next = engine.duplicate();
+    let result = f(&mut next);
+    *engine = Arc::new(next);
+    result
+}
+
+pub fn set_default_option(name: &str, value: &str) -> Result<(), String> {
+    update_default_engine(|engine| engine.set_option(name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 片方のエンジンの設定や置換表は、もう片方の探索に影響しない
+    #[test]
+    fn test_independent_engines() {
+        database::initialize_tables();
+        let board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let limits = SearchLimits { max_depth: Some(4), ..SearchLimits::default() };
+        let engine = |kind| {
+            let mut engine = Engine::with_options(EngineOptions { hash_mb: 1, threads: 1, ..EngineOptions::default() });
+            engine.set_logger(Logger::Silent);
+            engine.set_book(Arc::new(Book::default()));
+            engine.set_eval_kind(kind);
+            engine
+        };
+
+        // 時間は毎回変わるので、手とノード数だけ比べる
+        let search = |engine: &Engine, limits: &SearchLimits| {
+            let (mv, stats) = engine.search(&board, limits, 0.0);
+            (mv, stats.nodes)
+        };
+        let reference = search(&engine(EvalKind::Linear), &limits);
+
+        let linear = engine(EvalKind::Linear);
+        let network = engine(EvalKind::Network);
+        linear.search(&board, &limits, 0.0);
+        network.search(&board, &SearchLimits { max_depth: Some(2), ..limits }, 0.0);
+        assert_eq!(network.eval_kind(), EvalKind::Network);
+        assert_ne!(network.stats(), linear.stats());
+        // 較正係数も評価関数ごと
+        assert_eq!(linear.calibration(), calibration::for_eval(EvalKind::Linear));
+        assert_eq!(network.calibration(), calibration::for_eval(EvalKind::Network));
+        // 2回目は自分の置換表が残っている分だけ変わりうるので、置換表を空にして比べる
+        linear.clear_cache();
+        assert_eq!(search(&linear, &limits), reference);
+        assert_eq!(linear.last_search_stats().nodes, reference.1);
+
+        // 同じシードなら同じ乱れ
+        let (a, b) = (engine(EvalKind::Linear), engine(EvalKind::Linear));
+        a.set_seed(42);
+        b.set_seed(42);
+        assert_eq!(a.eval(&board, 10.0), b.eval(&board, 10.0));
+    }
+
+    // 2つのエンジンを別々のスレッドで同時に探索しても、1つずつ探索した場合と同じ手・同じノード数になる
+    #[test]
+    fn test_concurrent_engines() {
+        database::initialize_tables();
+        let board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let engine = |kind, selectivity| {
+            let mut engine = Engine::with_options(EngineOptions { hash_mb: 1, threads: 1, selectivity, ..EngineOptions::default() });
+            engine.set_logger(Logger::Silent);
+            engine.set_book(Arc::new(Book::default()));
+            engine.set_eval_kind(kind);
+            engine
+        };
+        let search = |engine: &Engine, depth| {
+            let (mv, stats) = engine.search(&board, &SearchLimits { max_depth: Some(depth), ..SearchLimits::default() }, 0.0);
+            (mv, stats.nodes, stats.evals)
+        };
+
+        let expected = (search(&engine(EvalKind::Linear, true), 5), search(&engine(EvalKind::Network, false), 2));
+        let (linear, network) = (engine(EvalKind::Linear, true), engine(EvalKind::Network, false));
+        let results = std::thread::scope(|scope| {
+            let a = scope.spawn(|| search(&linear, 5));
+            let b = scope.spawn(|| search(&network, 2));
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        assert_eq!(results, expected);
+        assert_eq!(linear.last_search_stats().nodes, expected.0.1);
+        assert_eq!(network.last_search_stats().nodes, expected.1.1);
+    }
+}
@@ -0,0 +1,193 @@
+/*
+評価関数のステージ(空きマス数による区分)管理
+ネイティブ(ONNX)とWebAssembly(重みファイル)の両方の評価関数で共有する
+
+重みファイル(eval_stages.bin)の形式 (リトルエンディアン)
+    b"RVST", version: u32, stage_count: u32, interpolate: u32
+    ステージごとにSTAGE_TENSORSの順で (要素数: u32, f32 * 要素数)
+
+同梱の重み(eval_stages.binとstages.json)はステージに分ける前のネットワーク1つだけを持つ．
+1ステージの場合は補間の指定によらず全ての空きマス数で同じネットワークを使い，
+ステージごとの重みはtrain_stages.pyで学習して置き換える
+*/
+
+use serde::{Serialize, Deserialize};
+
+pub const STAGE_MAGIC: &[u8; 4] = b"RVST";
+pub const STAGE_VERSION: u32 = 1;
+
+// 1ステージ分のテンソルの並び
+pub const STAGE_TENSORS: [&str; 7] = [
+    "embedding_layers.0.weight",
+    "embedding_layers.1.weight",
+    "embedding_layers.2.weight",
+    "dense_layers.0.weight",
+    "dense_layers.0.bias",
+    "dense_layers.2.weight",
+    "dense_layers.2.bias",
+];
+
+// ネイティブ版のステージ定義、モデルのパスはsrc/からの相対パス
+pub const STAGE_MANIFEST_PATH: &str = "src/othello_model_pattern_alt.stages.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageManifest {
+    pub interpolate: bool,
+    pub models: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StageLayout {
+    pub stage_count: usize,
+    pub interpolate: bool,
+}
+
+impl StageLayout {
+    pub fn new(stage_count: usize, interpolate: bool) -> Self {
+        assert!(stage_count > 0, "stage_count must be positive");
+        Self { stage_count, interpolate }
+    }
+
+    // 全局面で1つのネットワークを使う(ステージごとの重みがない場合)
+    pub fn single() -> Self {
+        Self::new(1, false)
+    }
+
+    // ステージ数から決める、1ステージなら補間しない
+    pub fn from_count(stage_count: usize, interpolate: bool) -> Self {
+        if stage_count == 1 {
+            Self::single()
+        } else {
+            Self::new(stage_count, interpolate)
+        }
+    }
+
+    // 空きマス数(0..=60)からステージ番号へ
+    pub fn stage_of(&self, empties: usize) -> usize {
+        (empties.min(60) * self.stage_count / 61).min(self.stage_count - 1)
+    }
+
+    // ステージに属する空きマス数の中央
+    fn center(&self, stage: usize) -> f32 {
+        let members: Vec<usize> = (0..=60).filter(|&e| self.stage_of(e) == stage).collect();
+        members.iter().sum::<usize>() as f32 / members.len().max(1) as f32
+    }
+
+    // (下側ステージ, 上側ステージ, 上側の重み)を返す
+    // 補間しない場合は上側の重みが常に0
+    pub fn select(&self, empties: usize) -> (usize, usize, f32) {
+        let stage = self.stage_of(empties);
+        if !self.interpolate || self.stage_count == 1 {
+            return (stage, stage, 0.0);
+        }
+
+        let e = empties as f32;
+        let lower = if e < self.center(stage) { stage.saturating_sub(1) } else { stage };
+        let upper = (lower + 1).min(self.stage_count - 1);
+        let (c_lower, c_upper) = (self.center(lower), self.center(upper));
+        if upper == lower || e <= c_lower {
+            return (lower, lower, 0.0);
+        }
+        if e >= c_upper {
+            return (upper, upper, 0.0);
+        }
+        (lower, upper, (e - c_lower) / (c_upper - c_lower))
+    }
+}
+
+pub struct StagedWeights {
+    pub layout: StageLayout,
+    // stages[stage][tensor]
+    pub stages: Vec<Vec<Vec<f32>>>,
+}
+
+pub fn parse_staged_weights(bytes: &[u8]) -> Result<StagedWeights, String> {
+    let mut pos = 0;
+    let read_u32 = |pos: &mut usize| -> Result<u32, String> {
+        let chunk = bytes.get(*pos..*pos + 4).ok_or("Unexpected end of weight file")?;
+        *pos += 4;
+        Ok(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+    };
+
+    if bytes.get(0..4) != Some(&STAGE_MAGIC[..]) {
+        return Err("Invalid weight file magic".to_string());
+    }
+    pos += 4;
+    let version = read_u32(&mut pos)?;
+    if version != STAGE_VERSION {
+        return Err(format!("Unsupported weight file version: {}", version));
+    }
+    let stage_count = read_u32(&mut pos)? as usize;
+    let interpolate = read_u32(&mut pos)? != 0;
+    if stage_count == 0 {
+        return Err("Weight file has no stages".to_string());
+    }
+
+    let mut stages = Vec::with_capacity(stage_count);
+    for _ in 0..stage_count {
+        let mut tensors = Vec::with_capacity(STAGE_TENSORS.len());
+        for _ in 0..STAGE_TENSORS.len() {
+            let len = read_u32(&mut pos)? as usize;
+            let data = bytes.get(pos..pos + len * 4).ok_or("Unexpected end of weight file")?;
+            pos += len * 4;
+            tensors.push(data
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect());
+        }
+        stages.push(tensors);
+    }
+
+    Ok(StagedWeights {
+        layout: StageLayout::from_count(stage_count, interpolate),
+        stages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_layout() {
+        let layout = StageLayout::new(6, false);
+        assert_eq!(layout.stage_of(0), 0);
+        assert_eq!(layout.stage_of(60), 5);
+        assert_eq!(layout.select(30), (layout.stage_of(30), layout.stage_of(30), 0.0));
+
+        let layout = StageLayout::new(6, true);
+        assert_eq!(layout.select(0), (0, 0, 0.0));
+        assert_eq!(layout.select(60), (5, 5, 0.0));
+        let mut prev = 0.0;
+        for e in 0..=60 {
+            let (lower, upper, w) = layout.select(e);
+            assert!(upper == lower || upper == lower + 1);
+            assert!((0.0..1.0).contains(&w));
+            // 空きマス数に対して単調に上側へ移る
+            let pos = lower as f32 + w;
+            assert!(pos >= prev, "empties {e}: {pos} < {prev}");
+            prev = pos;
+        }
+
+        assert_eq!(StageLayout::from_count(1, true), StageLayout::single());
+        assert_eq!(StageLayout::single().select(30), (0, 0, 0.0));
+    }
+
+    #[test]
+    fn test_parse_staged_weights() {
+        let weights = parse_staged_weights(include_bytes!("eval_stages.bin")).unwrap();
+        assert_eq!(weights.stages.len(), weights.layout.stage_count);
+        for tensors in &weights.stages {
+            let sizes: Vec<usize> = tensors.iter().map(|t| t.len()).collect();
+            assert_eq!(sizes, vec![6561 * 8, 19683 * 8, 59049 * 8, 128 * 416, 128, 128, 1]);
+        }
+
+        // 同梱の重みは1ステージで、ネイティブ版のモデルの並びと揃っている
+        let manifest: StageManifest = serde_json::from_str(include_str!("othello_model_pattern_alt.stages.json")).unwrap();
+        assert_eq!(weights.stages.len(), manifest.models.len());
+        assert_eq!(weights.layout, StageLayout::single());
+
+        assert!(parse_staged_weights(b"XXXX").is_err());
+        assert!(parse_staged_weights(&include_bytes!("eval_stages.bin")[..100]).is_err());
+    }
+}
@@ -1,26 +1,55 @@
 # extract_weights.py
+# ステージ定義(stages.json)に並んだONNXモデルから重みを取り出し、
+# WebAssembly版の評価関数が読む eval_stages.bin にまとめる
+# 形式は eval_stage.rs を参照
+import json
+import os
+import struct
+
 import onnx
 import numpy as np
 
+STAGE_MAGIC = b"RVST"
+STAGE_VERSION = 1
+
+# 1ステージ分のテンソルの並び（eval_stage.rsのSTAGE_TENSORSと同じ）
+STAGE_TENSORS = [
+    "embedding_layers.0.weight",
+    "embedding_layers.1.weight",
+    "embedding_layers.2.weight",
+    "dense_layers.0.weight",
+    "dense_layers.0.bias",
+    "dense_layers.2.weight",
+    "dense_layers.2.bias",
+]
+
 def extract_weights_from_onnx(model_path):
     model = onnx.load(model_path)
-    
+
     weights = {}
     for initializer in model.graph.initializer:
         # Tensorから重みデータを取得
         tensor_data = np.frombuffer(initializer.raw_data, dtype=np.float32)
         weights[initializer.name] = tensor_data.reshape(initializer.dims)
-    
+
     return weights
 
-# 重みを抽出
-model_path = "src/othello_model_pattern_alt.onnx"
-weights = extract_weights_from_onnx(model_path)
-
-# 重みをバイナリファイルとして保存
-for name, weight in weights.items():
-    filename = f"src/{name.replace('/', '_')}.bin"
-    weight.astype(np.float32).tobytes()
-    with open(filename, 'wb') as f:
-        f.write(weight.astype(np.float32).tobytes())
-    print(f"Saved {name} to {filename}, shape: {weight.shape}")
\ No newline at end of file
+manifest_path = "src/othello_model_pattern_alt.stages.json"
+with open(manifest_path) as f:
+    manifest = json.load(f)
+
+out = bytearray(STAGE_MAGIC)
+out += struct.pack("<III", STAGE_VERSION, len(manifest["models"]), int(manifest["interpolate"]))
+
+for stage, name in enumerate(manifest["models"]):
+    model_path = os.path.join(os.path.dirname(manifest_path), name)
+    weights = extract_weights_from_onnx(model_path)
+    for tensor_name in STAGE_TENSORS:
+        data = weights[tensor_name].astype(np.float32)
+        out += struct.pack("<I", data.size)
+        out += data.tobytes()
+        print(f"Stage {stage}: {tensor_name} from {model_path}, shape: {data.shape}")
+
+with open("src/eval_stages.bin", "wb") as f:
+    f.write(out)
+print(f"Saved {len(manifest['models'])} stages to src/eval_stages.bin")
@@ -0,0 +1,401 @@
+/*
+対局の状態
+盤面(黒白のビットボード)、手番、打った手の列、パス、待ったとやり直し、勝敗と終局の理由をまとめて持つ
+打てる手がなければ自動でパスし、パスも手の列に残す
+待ったの後に別の手を打つとそこから分岐し、やり直せる手の列は捨てる(同じ手なら残す)
+*/
+
+use crate::notation::{self, PositionError};
+use crate::play::{Board, MoveFlips};
+use crate::transcript;
+
+use serde::{Serialize, Deserialize};
+use std::fmt::{self, Display, Formatter};
+
+pub const PASS: u8 = 64;
+
+// マスの名前(a1〜h8、パスはpass)
+pub fn square_name(mv: u8) -> String {
+    if mv < 64 {
+        format!("{}{}", (b'a' + mv % 8) as char, mv / 8 + 1)
+    } else {
+        "pass".to_string()
+    }
+}
+
+// a1〜h8(大文字も可)とpassを読む
+pub fn parse_square(s: &str) -> Option<u8> {
+    if s.eq_ignore_ascii_case("pass") {
+        return Some(PASS);
+    }
+    match s.as_bytes() {
+        &[col, row] => {
+            let col = col.to_ascii_lowercase();
+            if (b'a'..=b'h').contains(&col) && (b'1'..=b'8').contains(&row) {
+                Some((row - b'1') * 8 + (col - b'a'))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Color {
+    Black,
+    White,
+}
+
+impl Color {
+    pub fn opponent(self) -> Self {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+        }
+    }
+}
+
+// 黒白で持った盤面と手番
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Position {
+    pub black: u64,
+    pub white: u64,
+    pub to_move: Color,
+}
+
+impl Position {
+    // 石が重ならず、4個以上あるか確かめて作る
+    pub fn new(black: u64, white: u64, to_move: Color) -> Result<Self, PositionError> {
+        notation::validate(black, white)?;
+        Ok(Self { black, white, to_move })
+    }
+
+    pub fn initial() -> Self {
+        Self { black: 0x0000000810000000, white: 0x0000001008000000, to_move: Color::Black }
+    }
+
+    // 手番側から見た盤面
+    pub fn board(&self) -> Board {
+        match self.to_move {
+            Color::Black => Board { my_board: self.black, opponent_board: self.white },
+            Color::White => Board { my_board: self.white, opponent_board: self.black },
+        }
+    }
+
+    pub fn from_board(board: &Board, to_move: Color) -> Self {
+        match to_move {
+            Color::Black => Self { black: board.my_board, white: board.opponent_board, to_move },
+            Color::White => Self { black: board.opponent_board, white: board.my_board, to_move },
+        }
+    }
+
+    pub fn legal_moves(&self) -> u64 {
+        self.board().get_valid_moves()
+    }
+
+    fn passed(&self) -> Self {
+        Self { to_move: self.to_move.opponent(), ..*self }
+    }
+}
+
+// 盤面の文字列(B: 黒, W: 白, .: 空き)
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for i in 0..64 {
+            let c = if self.black >> i & 1 != 0 { 'B' } else if self.white >> i & 1 != 0 { 'W' } else { '.' };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// 打った手(mvがPASSならパス、flippedは返した石)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub color: Color,
+    pub mv: u8,
+    pub flipped: u64,
+}
+
+impl MoveRecord {
+    pub fn is_pass(&self) -> bool {
+        self.mv == PASS
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameState {
+    Ongoing,
+    BlackWin,
+    WhiteWin,
+    Draw,
+}
+
+// 終局の理由
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EndReason {
+    // 盤面が埋まった
+    BoardFull,
+    // 片方の石がなくなった
+    Wipeout,
+    // 空きマスはあるが両者とも打てない
+    NoMoves,
+}
+
+#[derive(Clone, Debug)]
+pub struct Game {
+    // positions[i]はmoves[i]を打つ前の局面
+    positions: Vec<Position>,
+    moves: Vec<MoveRecord>,
+    // 今の局面の添字(待ったで戻る)
+    current: usize,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::from_position(Position::initial())
+    }
+
+    // 手番側が打てず相手が打てる局面なら、相手の手番から始める
+    pub fn from_position(position: Position) -> Self {
+        let position = if position.legal_moves() == 0 && position.passed().legal_moves() != 0 {
+            position.passed()
+        } else {
+            position
+        };
+        Self { positions: vec![position], moves: Vec::new(), current: 0 }
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.positions[self.current]
+    }
+
+    // 最初の局面(始めに自動でパスした場合はパスした後)
+    pub fn start_position(&self) -> &Position {
+        &self.positions[0]
+    }
+
+    pub fn board(&self) -> Board {
+        self.position().board()
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.position().to_move
+    }
+
+    // 打った手の数(パスを含む)
+    pub fn ply(&self) -> usize {
+        self.current
+    }
+
+    pub fn legal_moves(&self) -> u64 {
+        if self.is_over() { 0 } else { self.position().legal_moves() }
+    }
+
+    pub fn is_legal(&self, mv: u8) -> bool {
+        mv < 64 && self.legal_moves() & (1 << mv) != 0
+    }
+
+    // 今の局面までに打った手(パスを含む)
+    pub fn moves(&self) -> &[MoveRecord] {
+        &self.moves[..self.current]
+    }
+
+    // 最後に石を置いたマス
+    pub fn last_move(&self) -> Option<u8> {
+        self.moves().iter().rev().find(|r| !r.is_pass()).map(|r| r.mv)
+    }
+
+    // 最後に石を置いた手の方向ごとの返した石(アニメーション用)
+    pub fn last_flips(&self) -> Option<MoveFlips> {
+        let i = self.moves().iter().rposition(|r| !r.is_pass())?;
+        Some(self.positions[i].board().get_move_flips(self.moves[i].mv))
+    }
+
+    // 手を打ち、相手が打てなければパスする
+    // 相手がパスしたらtrue
+    pub fn play(&mut self, mv: u8) -> Result<bool, String> {
+        if self.is_over() {
+            return Err("Game is over".to_string());
+        }
+        if !self.is_legal(mv) {
+            return Err(format!("Illegal move: {}", mv));
+        }
+
+        // やり直せる手と同じならその手の列を残す
+        if self.moves.get(self.current).is_some_and(|r| r.mv == mv) {
+            self.redo();
+            return Ok(self.moves[self.current - 1].is_pass());
+        }
+        self.positions.truncate(self.current + 1);
+        self.moves.truncate(self.current);
+
+        let position = *self.position();
+        let mut board = position.board();
+        let flipped = board.make_move(mv).flipped;
+        self.push(MoveRecord { color: position.to_move, mv, flipped }, Position::from_board(&board, position.to_move).passed());
+
+        let next = *self.position();
+        if next.legal_moves() == 0 && next.passed().legal_moves() != 0 {
+            self.push(MoveRecord { color: next.to_move, mv: PASS, flipped: 0 }, next.passed());
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn push(&mut self, record: MoveRecord, next: Position) {
+        self.moves.push(record);
+        self.positions.push(next);
+        self.current += 1;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.current < self.moves.len()
+    }
+
+    // 直前の手(続くパスを含む)を戻す
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        self.current -= 1;
+        // パスは打てる手がないときだけ入るので、その前の手も戻す
+        while self.current > 0 && self.moves[self.current].is_pass() {
+            self.current -= 1;
+        }
+        true
+    }
+
+    // 戻した手(続くパスを含む)を打ち直す
+    pub fn redo(&mut self) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+        self.current += 1;
+        if self.moves.get(self.current).is_some_and(|r| r.is_pass()) {
+            self.current += 1;
+        }
+        true
+    }
+
+    pub fn count(&self, color: Color) -> u32 {
+        let position = self.position();
+        match color {
+            Color::Black => position.black.count_ones(),
+            Color::White => position.white.count_ones(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        let position = self.position();
+        position.legal_moves() == 0 && position.passed().legal_moves() == 0
+    }
+
+    pub fn state(&self) -> GameState {
+        if !self.is_over() {
+            return GameState::Ongoing;
+        }
+        let (black, white) = (self.count(Color::Black), self.count(Color::White));
+        match black.cmp(&white) {
+            std::cmp::Ordering::Greater => GameState::BlackWin,
+            std::cmp::Ordering::Less => GameState::WhiteWin,
+            std::cmp::Ordering::Equal => GameState::Draw,
+        }
+    }
+
+    pub fn end_reason(&self) -> Option<EndReason> {
+        if !self.is_over() {
+            return None;
+        }
+        let position = self.position();
+        Some(if position.black | position.white == u64::MAX {
+            EndReason::BoardFull
+        } else if position.black == 0 || position.white == 0 {
+            EndReason::Wipeout
+        } else {
+            EndReason::NoMoves
+        })
+    }
+
+    // 棋譜(f5d6...、パスは書かない)
+    pub fn transcript(&self) -> String {
+        let moves: Vec<u8> = self.moves().iter().map(|r| r.mv).collect();
+        transcript::write(&moves, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    #[test]
+    fn test_game() {
+        database::initialize_tables();
+        let mut game = Game::new();
+        assert_eq!(game.state(), GameState::Ongoing);
+        assert!(!game.can_undo());
+        assert!(game.play(0).is_err());
+
+        // f5を打つ
+        assert_eq!(game.play(37), Ok(false));
+        assert_eq!((game.side_to_move(), game.count(Color::Black), game.count(Color::White)), (Color::White, 4, 1));
+        assert_eq!(game.last_move(), Some(37));
+        assert_eq!(game.last_flips().map(|f| f.flipped()), Some(1 << 36));
+        game.play(43).unwrap(); // d6
+        let after_d6 = *game.position();
+
+        // 待ったしてやり直す
+        assert!(game.undo());
+        assert!(game.can_redo());
+        assert!(game.redo());
+        assert_eq!(*game.position(), after_d6);
+
+        // 待ったして別の手(f6)を打つと分岐し、やり直せる手はなくなる
+        game.undo();
+        game.play(45).unwrap();
+        assert!(!game.can_redo());
+        assert_eq!(game.transcript(), "f5f6");
+
+        // 同じ手を打ち直すとやり直せる手の列は残る
+        game.undo();
+        game.undo();
+        game.play(37).unwrap();
+        assert!(game.can_redo());
+        assert_eq!(game.ply(), 1);
+
+        // 白がe1でf1を返すと黒はe2しか残らず打てないので、黒は自動でパスする
+        let position = Position { black: 0x1020, white: 0x0040, to_move: Color::White };
+        let mut game = Game::from_position(position);
+        assert_eq!(game.play(4), Ok(true));
+        assert_eq!((game.side_to_move(), game.ply()), (Color::White, 2));
+        assert!(game.moves()[1].is_pass());
+        assert_eq!(game.last_move(), Some(4));
+        // 待ったはパスごと戻す
+        game.undo();
+        assert_eq!((game.side_to_move(), game.ply()), (Color::White, 0));
+        game.redo();
+        assert_eq!(game.ply(), 2);
+        game.play(20).unwrap();
+        assert_eq!((game.state(), game.end_reason()), (GameState::WhiteWin, Some(EndReason::Wipeout)));
+        assert!(game.play(19).is_err());
+        assert_eq!(game.transcript(), "e1e3");
+
+        for mv in 0..=PASS {
+            assert_eq!(parse_square(&square_name(mv)), Some(mv));
+        }
+        assert_eq!(parse_square("F5"), Some(37));
+        assert_eq!(parse_square("i1"), None);
+    }
+}
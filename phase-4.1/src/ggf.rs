@@ -0,0 +1,415 @@
+/*
+GGF(Generic Game Format)の棋譜の読み書き
+(;GM[Othello]PC[場所]DT[日時]PB[黒]PW[白]RB[黒のレーティング]RW[白のレーティング]TI[持ち時間]TY[8]RE[+18.000]
+  BO[8 -------- -------- -------- ---O*--- ---*O--- -------- -------- -------- *]B[d3/-1.50/2.3]W[c5]...;)
+BOは盤の大きさの後に *(黒) O(白) -(空き)の64マスと手番、手は"マス/評価値/消費時間(秒)"で評価値と時間は省略できる
+パスはPA、8x8以外の盤と反転(TYのa)・同時(TYのs)の対局は読まない、上以外のプロパティは残して書き出す
+読むときは開始局面から棋譜(transcript)として再生し、不正な手や手番の違う手があればエラーにする
+*/
+
+use crate::game::{parse_square, square_name, Color, Game, Position, PASS};
+use crate::notation::{self, PositionError};
+use crate::transcript::{self, TranscriptError};
+
+use serde::{Serialize, Deserialize};
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum GgfError {
+    #[error("no game record found")]
+    NoGame,
+    #[error("game record is not terminated with ';)'")]
+    Unterminated,
+    #[error("syntax error at character {0}")]
+    Syntax(usize),
+    #[error("unsupported game: {0}")]
+    UnsupportedGame(String),
+    #[error("unsupported board size: {0}")]
+    UnsupportedBoardSize(String),
+    #[error("invalid value for {key}: {value}")]
+    InvalidValue { key: String, value: String },
+    #[error("invalid start position: {0}")]
+    InvalidPosition(#[from] PositionError),
+    #[error(transparent)]
+    Transcript(#[from] TranscriptError),
+    // plyは何手目か(1から)
+    #[error("move {ply} is played by the wrong color")]
+    WrongColor { ply: usize },
+}
+
+// 手と評価値・消費時間(秒)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GgfMove {
+    pub color: Color,
+    pub mv: u8,
+    pub eval: Option<f32>,
+    pub time: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GgfGame {
+    pub place: Option<String>,
+    pub date: Option<String>,
+    pub black_player: Option<String>,
+    pub white_player: Option<String>,
+    pub black_rating: Option<f32>,
+    pub white_rating: Option<f32>,
+    pub time_control: Option<String>,
+    pub game_type: Option<String>,
+    // 黒から見た石差("+18.000"など)、投了などの印がつくこともある
+    pub result: Option<String>,
+    pub start: Position,
+    pub moves: Vec<GgfMove>,
+    // 上以外のプロパティ
+    pub other: Vec<(String, String)>,
+}
+
+impl Default for GgfGame {
+    fn default() -> Self {
+        Self {
+            place: None,
+            date: None,
+            black_player: None,
+            white_player: None,
+            black_rating: None,
+            white_rating: None,
+            time_control: None,
+            game_type: None,
+            result: None,
+            start: Position::initial(),
+            moves: Vec::new(),
+            other: Vec::new(),
+        }
+    }
+}
+
+impl GgfGame {
+    // 対局の開始局面と手(パスを含む)から作る、終局していれば結果も書く
+    pub fn from_game(game: &Game) -> Self {
+        let result = game.is_over().then(|| match transcript::final_score(game) {
+            0 => "0.000".to_string(),
+            score => format!("{:+.3}", score as f32),
+        });
+        Self {
+            game_type: Some("8".to_string()),
+            result,
+            start: *game.start_position(),
+            moves: game.moves().iter().map(|r| GgfMove { color: r.color, mv: r.mv, eval: None, time: None }).collect(),
+            ..Self::default()
+        }
+    }
+
+    // 手ごとの評価値と消費時間(秒)を書く(パスを含む手の順、Noneや足りない分は書かない)
+    pub fn set_annotations(&mut self, evals: &[Option<f32>], times: &[Option<f32>]) {
+        for (i, m) in self.moves.iter_mut().enumerate() {
+            m.eval = evals.get(i).copied().flatten();
+            m.time = times.get(i).copied().flatten();
+        }
+    }
+
+    // 開始局面から手を再生した対局
+    pub fn to_game(&self) -> Result<Game, GgfError> {
+        let moves: Vec<u8> = self.moves.iter().map(|m| m.mv).collect();
+        let game = transcript::replay(self.start, &moves)?;
+
+        // パス以外の手は書かれた色と打った色が同じ
+        let played = game.moves().iter().filter(|r| !r.is_pass());
+        let written = self.moves.iter().enumerate().filter(|(_, m)| m.mv != PASS);
+        for (record, (i, m)) in played.zip(written) {
+            if record.color != m.color {
+                return Err(GgfError::WrongColor { ply: i + 1 });
+            }
+        }
+        Ok(game)
+    }
+
+    pub fn transcript(&self) -> String {
+        let moves: Vec<u8> = self.moves.iter().map(|m| m.mv).collect();
+        transcript::write(&moves, false)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), GgfError> {
+        let invalid = || GgfError::InvalidValue { key: key.to_string(), value: value.to_string() };
+        let text = || Some(value.to_string());
+        match key {
+            "GM" => {
+                if !value.eq_ignore_ascii_case("othello") {
+                    return Err(GgfError::UnsupportedGame(value.to_string()));
+                }
+            }
+            "PC" => self.place = text(),
+            "DT" => self.date = text(),
+            "PB" => self.black_player = text(),
+            "PW" => self.white_player = text(),
+            "RB" => self.black_rating = Some(value.trim().parse().map_err(|_| invalid())?),
+            "RW" => self.white_rating = Some(value.trim().parse().map_err(|_| invalid())?),
+            "TI" => self.time_control = text(),
+            "TY" => {
+                check_game_type(value)?;
+                self.game_type = text();
+            }
+            "RE" => self.result = text(),
+            "BO" => self.start = parse_board(value)?,
+            "B" | "W" => {
+                let color = if key == "B" { Color::Black } else { Color::White };
+                self.moves.push(parse_move(color, value).ok_or_else(invalid)?);
+            }
+            _ => self.other.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+}
+
+// "8"、"8r20"、"s8"など、数字が盤の大きさで英字が変則の種類
+fn check_game_type(value: &str) -> Result<(), GgfError> {
+    let size: String = value.chars().skip_while(|c| !c.is_ascii_digit()).take_while(|c| c.is_ascii_digit()).collect();
+    if size != "8" {
+        return Err(GgfError::UnsupportedBoardSize(size));
+    }
+    if value.contains(['a', 's']) {
+        return Err(GgfError::UnsupportedGame(format!("TY[{}]", value)));
+    }
+    Ok(())
+}
+
+// "8 -------- ... *"
+fn parse_board(value: &str) -> Result<Position, GgfError> {
+    let mut tokens = value.split_whitespace();
+    let size = tokens.next().unwrap_or("");
+    if size != "8" {
+        return Err(GgfError::UnsupportedBoardSize(size.to_string()));
+    }
+    let squares: String = tokens.collect();
+    Ok(notation::parse(&squares)?)
+}
+
+fn write_board(position: &Position) -> String {
+    let mut s = "8".to_string();
+    for i in 0..64 {
+        if i % 8 == 0 {
+            s.push(' ');
+        }
+        s.push(if position.black >> i & 1 != 0 { '*' } else if position.white >> i & 1 != 0 { 'O' } else { '-' });
+    }
+    s.push(' ');
+    s.push(if position.to_move == Color::Black { '*' } else { 'O' });
+    s
+}
+
+// "d3/-1.50/2.3"、時間は"分:秒"でもよい
+fn parse_move(color: Color, value: &str) -> Option<GgfMove> {
+    let mut parts = value.split('/').map(str::trim);
+    let square = parts.next()?;
+    let mv = if square.eq_ignore_ascii_case("pa") { PASS } else { parse_square(square)? };
+    let eval = match parts.next() {
+        None | Some("") => None,
+        Some(s) => Some(s.parse().ok()?),
+    };
+    let time = match parts.next() {
+        None | Some("") => None,
+        Some(s) => Some(parse_time(s)?),
+    };
+    Some(GgfMove { color, mv, eval, time })
+}
+
+fn parse_time(s: &str) -> Option<f32> {
+    s.split(':').try_fold(0.0, |acc, part| Some(acc * 60.0 + part.parse::<f32>().ok()?))
+}
+
+fn write_move(m: &GgfMove) -> String {
+    let mut s = if m.mv == PASS { "PA".to_string() } else { square_name(m.mv) };
+    if m.eval.is_some() || m.time.is_some() {
+        s.push('/');
+        if let Some(eval) = m.eval {
+            s += &format!("{:.2}", eval);
+        }
+        if let Some(time) = m.time {
+            s += &format!("/{}", time);
+        }
+    }
+    s
+}
+
+// ]と\は\を前につける
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+impl Display for GgfGame {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "(;GM[Othello]")?;
+        let texts = [
+            ("PC", &self.place),
+            ("DT", &self.date),
+            ("PB", &self.black_player),
+            ("PW", &self.white_player),
+        ];
+        for (key, value) in texts {
+            if let Some(value) = value {
+                write!(f, "{}[{}]", key, escape(value))?;
+            }
+        }
+        if let Some(rating) = self.black_rating {
+            write!(f, "RB[{}]", rating)?;
+        }
+        if let Some(rating) = self.white_rating {
+            write!(f, "RW[{}]", rating)?;
+        }
+        let texts = [
+            ("TI", &self.time_control),
+            ("TY", &self.game_type),
+            ("RE", &self.result),
+        ];
+        for (key, value) in texts {
+            if let Some(value) = value {
+                write!(f, "{}[{}]", key, escape(value))?;
+            }
+        }
+        for (key, value) in &self.other {
+            write!(f, "{}[{}]", key, escape(value))?;
+        }
+        write!(f, "BO[{}]", write_board(&self.start))?;
+        for m in &self.moves {
+            write!(f, "{}[{}]", if m.color == Color::Black { "B" } else { "W" }, write_move(m))?;
+        }
+        write!(f, ";)")
+    }
+}
+
+// "(;"の後から1局の中身(KEY[VALUE]の並び)を";)"まで読み、(プロパティ, ";)"の次の位置)を返す
+// 値の中の";)"は終わりと見なさない
+fn scan_body(s: &str, body: usize) -> Result<(Vec<(String, String)>, usize), GgfError> {
+    let mut properties = Vec::new();
+    let mut chars = s[body..].char_indices().map(|(i, c)| (body + i, c)).peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let Some(&(start, _)) = chars.peek() else {
+            return Err(GgfError::Unterminated);
+        };
+        if s[start..].starts_with(";)") {
+            return Ok((properties, start + 2));
+        }
+        let mut key = String::new();
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+            key.push(c.to_ascii_uppercase());
+        }
+        if key.is_empty() || chars.next().map(|(_, c)| c) != Some('[') {
+            return Err(GgfError::Syntax(start));
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '\\')) => value.extend(chars.next().map(|(_, c)| c)),
+                Some((_, ']')) => break,
+                Some((_, c)) => value.push(c),
+                None => return Err(GgfError::Unterminated),
+            }
+        }
+        properties.push((key, value));
+    }
+}
+
+fn parse_properties(properties: &[(String, String)]) -> Result<GgfGame, GgfError> {
+    let mut game = GgfGame::default();
+    for (key, value) in properties {
+        game.set(key, value)?;
+    }
+    game.to_game()?;
+    Ok(game)
+}
+
+// ファイルに並んだ対局を一つずつ読む
+// 読めない対局(8x8以外の盤、不正な手など)はその対局だけエラーにして、続きの対局を読む
+// 構文の誤りがあれば次の"(;"から読み直し、終わりの";)"がなければそこで止める
+pub fn parse_all(s: &str) -> Vec<Result<GgfGame, GgfError>> {
+    let mut games = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = s[offset..].find("(;") {
+        let body = offset + start + 2;
+        match scan_body(s, body) {
+            Ok((properties, end)) => {
+                games.push(parse_properties(&properties));
+                offset = end;
+            }
+            Err(GgfError::Unterminated) => {
+                games.push(Err(GgfError::Unterminated));
+                break;
+            }
+            Err(e) => {
+                games.push(Err(e));
+                offset = body;
+            }
+        }
+    }
+    games
+}
+
+// 最初の対局を読む
+pub fn parse(s: &str) -> Result<GgfGame, GgfError> {
+    parse_all(s).into_iter().next().unwrap_or(Err(GgfError::NoGame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    #[test]
+    fn test_ggf() {
+        database::initialize_tables();
+        let s = "(;GM[Othello]PC[NIOS]DT[2003.12.15_13:24:03.MST]PB[Saio1200]PW[Saio3000]RB[2197.01]RW[2199.09]\
+                 TI[05:00//02:00]TY[8]RE[+18.000]KM[0]\
+                 BO[8 -------- -------- -------- ---O*--- ---*O--- -------- -------- -------- *]\
+                 B[F5//0.01]W[d6/-1.50/1:02.5]B[c3];)\n(;GM[Othello]BO[8 -------- -------- -------- ---O*--- ---*O--- -------- -------- -------- O]W[e3];)";
+        let games: Vec<GgfGame> = parse_all(s).into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(games.len(), 2);
+        let game = &games[0];
+        assert_eq!((game.black_player.as_deref(), game.white_rating), (Some("Saio1200"), Some(2199.09)));
+        assert_eq!(game.time_control.as_deref(), Some("05:00//02:00"));
+        assert_eq!(game.moves[1], GgfMove { color: Color::White, mv: 43, eval: Some(-1.5), time: Some(62.5) });
+        assert_eq!(game.transcript(), "f5d6c3");
+        assert_eq!(game.other, vec![("KM".to_string(), "0".to_string())]);
+        // 白から始まる局面
+        assert_eq!((games[1].start.to_move, games[1].to_game().unwrap().ply()), (Color::White, 1));
+
+        // 書き出して読み直すと同じになる
+        assert_eq!(parse(&game.to_string()).unwrap(), *game);
+
+        // 対局から作る(パスはPA)
+        let start = Position { black: 0x1020, white: 0x0040, to_move: Color::White };
+        let mut g = Game::from_position(Position { black: start.black | 0x8000_0000_0000_0000, ..start });
+        g.play(4).unwrap();
+        g.play(20).unwrap();
+        let ggf = GgfGame::from_game(&g);
+        assert!(ggf.to_string().contains("W[e1]B[PA]W[e3]"), "{}", ggf);
+        assert_eq!(ggf.result.as_deref(), Some("-62.000"));
+        assert_eq!(parse(&ggf.to_string()).unwrap().to_game().unwrap().moves(), g.moves());
+
+        // 評価値と時間は手の順に書き、足りない分は書かない
+        let mut ggf = GgfGame::from_game(&g);
+        ggf.set_annotations(&[Some(-3.5), None], &[Some(1.5), None, Some(0.25)]);
+        assert!(ggf.to_string().contains("W[e1/-3.50/1.5]B[PA]W[e3//0.25]"), "{}", ggf);
+        assert_eq!(parse(&ggf.to_string()).unwrap(), ggf);
+
+        // 読めない対局があっても続きの対局は読む、値の中の";)"は終わりではない
+        let archive = "(;GM[Othello]TY[10]BO[10 *];)(;GM[Othello]TY[8a]B[f5];)(;GM[Othello]C[;)]B[f5];)\
+                       (;GM[Othello]B f5;)(;GM[Othello]W[e6];)(;GM[Othello]B[f5]";
+        let results = parse_all(archive);
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0], Err(GgfError::UnsupportedBoardSize("10".to_string())));
+        assert_eq!(results[1], Err(GgfError::UnsupportedGame("TY[8a]".to_string())));
+        let game = results[2].as_ref().unwrap();
+        assert_eq!((game.other.clone(), game.transcript()), (vec![("C".to_string(), ";)".to_string())], "f5".to_string()));
+        assert!(matches!(results[3], Err(GgfError::Syntax(_))));
+        assert_eq!(results[4], Err(GgfError::WrongColor { ply: 1 }));
+        assert_eq!(results[5], Err(GgfError::Unterminated));
+        assert_eq!(parse(""), Err(GgfError::NoGame));
+
+        assert_eq!(parse("(;GM[Othello]B[a1];)").unwrap_err(), GgfError::Transcript(TranscriptError::IllegalMove { ply: 1, mv: "a1".to_string() }));
+        assert_eq!(parse("(;GM[Othello]W[f5];)").unwrap_err(), GgfError::WrongColor { ply: 1 });
+        assert_eq!(parse("(;GM[Othello]BO[10 *];)").unwrap_err(), GgfError::UnsupportedBoardSize("10".to_string()));
+        assert_eq!(parse("(;GM[Othello]B[f5]"), Err(GgfError::Unterminated));
+        assert!(matches!(parse("(;GM[Othello]BO[8 ---];)"), Err(GgfError::InvalidPosition(_))));
+    }
+}
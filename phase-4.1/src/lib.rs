@@ -1,14 +1,29 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
 
-mod play;
+pub mod play;
 mod proto;
-mod database;
-mod solver;
+pub mod database;
+pub mod solver;
+pub mod calibration;
+pub mod selfplay;
+pub mod eval_stage;
+pub mod pattern;
+pub mod parity;
+pub mod linear_eval;
+pub mod probcut;
+pub mod ordering;
+pub mod engine;
+pub mod options;
+pub mod protocol;
+pub mod game;
+pub mod notation;
+pub mod transcript;
+pub mod ggf;
+pub mod wthor;
 mod test_wasm;
 
 use crate::play::Board;
-use crate::proto::Move;
 
 // WebAssembly環境では専用の評価関数を使用
 #[cfg(target_arch = "wasm32")]
@@ -16,64 +31,227 @@ mod eval_wasm;
 
 // WebAssembly以外の環境では通常の評価関数
 #[cfg(not(target_arch = "wasm32"))]
-mod eval;
+pub mod eval;
 
-// 複雑な返り値用の構造体
-#[derive(Serialize, Deserialize)]
-pub struct GameResult {
-    pub state: String,  // "BlackWin", "WhiteWin", "Draw", "Ongoing"
+// JavaScriptに返す値の型(TypeScriptの定義)
+// マスは"a1"〜"h8"の名前で渡し、盤面は64文字(B: 黒, W: 白, .: 空き)で渡す
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &'static str = r#"
+export type Square = `${"a" | "b" | "c" | "d" | "e" | "f" | "g" | "h"}${1 | 2 | 3 | 4 | 5 | 6 | 7 | 8}`;
+export type Move = Square | "pass";
+export type Color = "Black" | "White";
+export type GameState = "Ongoing" | "BlackWin" | "WhiteWin" | "Draw";
+export type EndReason = "BoardFull" | "Wipeout" | "NoMoves";
+
+export interface GameResult {
+    state: GameState;
+    black: number;
+    white: number;
+    reason?: EndReason;
+}
+
+export interface TranscriptResult {
+    moves: Move[];
+    result: GameResult;
+    score: number;
+}
+
+export interface GgfInfo {
+    place?: string;
+    date?: string;
+    black_player?: string;
+    white_player?: string;
+    black_rating?: number;
+    white_rating?: number;
+    time_control?: string;
+}
+
+export interface GgfExportInfo extends GgfInfo {
+    evals?: (number | null)[];
+    times?: (number | null)[];
+}
+
+export interface GgfReadError {
+    game: number;
+    message: string;
+}
+
+export interface GgfReadResult {
+    games: GgfRecord[];
+    errors: GgfReadError[];
+}
+
+export interface GgfMove {
+    color: Color;
+    mv: Move;
+    eval?: number;
+    time?: number;
+}
+
+export interface GgfRecord extends GgfInfo {
+    game_type?: string;
+    result?: string;
+    start: string;
+    moves: GgfMove[];
+    transcript: string;
+}
+
+export interface StoneCounts {
+    black: number;
+    white: number;
+}
+
+// 方向ごとの返した石(北はa1〜h1の側)、それぞれ置いたマスに近い順
+export interface DirectionalFlips {
+    n: Square[];
+    ne: Square[];
+    e: Square[];
+    se: Square[];
+    s: Square[];
+    sw: Square[];
+    w: Square[];
+    nw: Square[];
+}
+
+export interface MoveFlips {
+    square: Square;
+    directions: DirectionalFlips;
+}
+
+export interface UndoToken {
+    color: Color;
+    square: Move;
+    flipped: Square[];
+}
+
+export interface MoveResult {
+    board: string;
+    square: Move;
+    flipped: Square[];
+    directions: DirectionalFlips;
+    black: number;
+    white: number;
+    undo: UndoToken;
 }
 
+export interface MoveRecord {
+    color: Color;
+    mv: Move;
+    flipped: Square[];
+}
+
+export interface CalibratedScore {
+    raw: number;
+    disc_diff: number;
+    win_prob: number;
+}
+
+export interface Hint {
+    mv: Square;
+    depth: number;
+    score: CalibratedScore;
+}
+
+export interface EndgameResult {
+    score: number;
+    mv: Move;
+    confidence: number;
+}
+
+export interface ParityRegion {
+    squares: Square[];
+    odd: boolean;
+}
+
+export interface SearchLimits {
+    max_depth?: number;
+    max_nodes?: number;
+    max_time_ms?: number;
+    solve_empties?: number;
+}
+
+export interface SearchStats {
+    nodes: number;
+    helper_nodes: number;
+    evals: number;
+    tt_probes: number;
+    tt_hits: number;
+    tt_cutoffs: number;
+    solver_nodes: number;
+    depth: number;
+    selective_depth: number;
+    time_ms: number;
+    nps: number;
+    book: boolean;
+    solver: boolean;
+    selective_solver: boolean;
+    solver_confidence?: number;
+    score?: number;
+}
+
+export interface SearchResult {
+    mv: Move;
+    stats: SearchStats;
+}
+
+export interface EngineStats {
+    nodes: number;
+    evals: number;
+    tt_cutoffs: number;
+}
+
+export interface HashStats {
+    size_mb: number;
+    probes: number;
+    hits: number;
+    hit_rate: number;
+    fill: number;
+}
+
+export type OptionValue = boolean | number | string;
+
+export interface OptionSpec {
+    name: string;
+    type: "check" | "spin" | "float" | "combo";
+    min?: number;
+    max?: number;
+    values?: string[];
+    default: OptionValue;
+    description: string;
+}
+"#;
+
+// 勝敗と石の数、終局の理由(終わっていなければなし)
 #[derive(Serialize, Deserialize)]
-pub struct StoneCounts {
+pub struct GameResult {
+    pub state: game::GameState,
     pub black: u32,
     pub white: u32,
+    pub reason: Option<game::EndReason>,
 }
 
-fn format_move(mv: usize) -> Move {
-    if mv == 64 {
-        Move::Pass
-    } else {
-        let x = (mv % 8) + 1;
-        let y = (mv / 8) + 1;
-        Move::Mv { x_ah: x as u32, y_18: y as u32 }
+impl GameResult {
+    fn of(game: &game::Game) -> Self {
+        Self {
+            state: game.state(),
+            black: game.count(game::Color::Black),
+            white: game.count(game::Color::White),
+            reason: game.end_reason(),
+        }
     }
 }
 
-// 文字列から盤面を作成
-fn string_to_boards(board_str: &str) -> (u64, u64) {
-    let mut black_board = 0u64;
-    let mut white_board = 0u64;
-    
-    for (i, c) in board_str.chars().enumerate() {
-        if i >= 64 { break; }
-        
-        match c {
-            'B' => black_board |= 1u64 << i,
-            'W' => white_board |= 1u64 << i,
-            _ => {} // '.' or other characters are treated as empty
-        }
-    }
-    
-    (black_board, white_board)
+#[derive(Serialize, Deserialize)]
+pub struct StoneCounts {
+    pub black: u32,
+    pub white: u32,
 }
 
-// 盤面を文字列に変換
-fn boards_to_string(black_board: u64, white_board: u64) -> String {
-    let mut result = String::with_capacity(64);
-    
-    for i in 0..64 {
-        let mask = 1u64 << i;
-        if black_board & mask != 0 {
-            result.push('B');
-        } else if white_board & mask != 0 {
-            result.push('W');
-        } else {
-            result.push('.');
-        }
-    }
-    
-    result
+// 盤面の文字列を(黒, 白)にする
+// B/W/.のほかEdax/OBF(X: 黒, O: 白, -: 空き)も読む(notation)、正しくなければエラー
+fn parse_board(board_str: &str) -> Result<(u64, u64), JsError> {
+    let position = notation::parse(board_str)?;
+    Ok((position.black, position.white))
 }
 
 fn make_board(black_board: u64, white_board: u64, turn: bool) -> Board {
@@ -91,103 +269,775 @@ fn make_board(black_board: u64, white_board: u64, turn: bool) -> Board {
     }
 }
 
+// 盤面の文字列を手番側から見た盤面にする
+fn parse_board_for(board_str: &str, turn: bool) -> Result<Board, JsError> {
+    let (black_board, white_board) = parse_board(board_str)?;
+    Ok(make_board(black_board, white_board, turn))
+}
+
+// "a1"〜"h8"または"pass"
+fn parse_move(mv: &str) -> Result<u8, JsError> {
+    game::parse_square(mv).ok_or_else(|| JsError::new(&format!("Invalid square: {}", mv)))
+}
+
+// ビットボードをマスの名前の配列にする
+fn squares(bits: u64) -> Vec<String> {
+    (0..64).filter(|&i| bits >> i & 1 != 0).map(game::square_name).collect()
+}
+
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    serde_wasm_bindgen::to_value(value).unwrap()
+}
+
+// 省略したもの(undefinedも)は制限しない、型が違えばエラー
+fn parse_limits(limits: JsValue) -> Result<play::SearchLimits, JsError> {
+    if limits.is_undefined() || limits.is_null() {
+        return Ok(play::SearchLimits::default());
+    }
+    serde_wasm_bindgen::from_value(limits).map_err(|e| JsError::new(&format!("Invalid search limits: {}", e)))
+}
+
+// GGFに書く対局の情報、省略したもの(undefinedも)は書かない
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct GgfInfo {
+    place: Option<String>,
+    date: Option<String>,
+    black_player: Option<String>,
+    white_player: Option<String>,
+    black_rating: Option<f32>,
+    white_rating: Option<f32>,
+    time_control: Option<String>,
+    // 手ごとの評価値(手番側から見た石差)と消費時間(秒)、パスを含む手の順
+    evals: Vec<Option<f32>>,
+    times: Vec<Option<f32>>,
+}
+
+// GGFの対局、開始局面はEdax/OBFの形式で渡す
+#[derive(Serialize)]
+struct GgfRecord {
+    place: Option<String>,
+    date: Option<String>,
+    black_player: Option<String>,
+    white_player: Option<String>,
+    black_rating: Option<f32>,
+    white_rating: Option<f32>,
+    time_control: Option<String>,
+    game_type: Option<String>,
+    result: Option<String>,
+    start: String,
+    moves: Vec<GgfMoveView>,
+    transcript: String,
+}
+
+#[derive(Serialize)]
+struct GgfMoveView {
+    color: game::Color,
+    mv: String,
+    eval: Option<f32>,
+    time: Option<f32>,
+}
+
+impl GgfRecord {
+    fn new(record: ggf::GgfGame) -> Self {
+        let moves = record.moves.iter()
+            .map(|m| GgfMoveView { color: m.color, mv: game::square_name(m.mv), eval: m.eval, time: m.time })
+            .collect();
+        Self {
+            start: notation::format(&record.start, notation::Notation::Obf),
+            transcript: record.transcript(),
+            moves,
+            place: record.place,
+            date: record.date,
+            black_player: record.black_player,
+            white_player: record.white_player,
+            black_rating: record.black_rating,
+            white_rating: record.white_rating,
+            time_control: record.time_control,
+            game_type: record.game_type,
+            result: record.result,
+        }
+    }
+}
+
+// 方向ごとの返した石、それぞれ置いたマスに近い順
+#[derive(Serialize)]
+struct DirectionalFlips {
+    n: Vec<String>,
+    ne: Vec<String>,
+    e: Vec<String>,
+    se: Vec<String>,
+    s: Vec<String>,
+    sw: Vec<String>,
+    w: Vec<String>,
+    nw: Vec<String>,
+}
+
+impl DirectionalFlips {
+    fn new(flips: &play::MoveFlips) -> Self {
+        // 置いたマスより添字の小さい側は大きい順に並べる
+        let ray = |bits: u64| {
+            let mut ray = squares(bits);
+            if bits & (u64::MAX << flips.square) == 0 {
+                ray.reverse();
+            }
+            ray
+        };
+        let [n, ne, e, se, s, sw, w, nw] = flips.directions.map(ray);
+        Self { n, ne, e, se, s, sw, w, nw }
+    }
+}
+
+#[derive(Serialize)]
+struct MoveFlipsView {
+    square: String,
+    directions: DirectionalFlips,
+}
+
+// 打った手を戻すための情報(打った色・マス・返した石)
+#[derive(Serialize, Deserialize)]
+struct UndoToken {
+    color: game::Color,
+    square: String,
+    flipped: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    mv: String,
+    stats: play::SearchStats,
+}
+
+#[derive(Serialize)]
+struct HintView {
+    mv: String,
+    depth: u8,
+    score: calibration::CalibratedScore,
+}
+
+fn hint_views(hints: Vec<play::Hint>) -> Vec<HintView> {
+    hints.into_iter()
+        .map(|h| HintView { mv: game::square_name(h.mv), depth: h.depth, score: h.score })
+        .collect()
+}
+
 #[wasm_bindgen]
 pub fn initialize(){
     database::initialize_tables();
     database::init_book();
 }
 
+// 手を打った後の盤面と返した石(方向ごとにも)、戻すための情報
 // turn = trueの時、白の手番
-#[wasm_bindgen]
-pub fn update_board(board_str: &str, mv: usize, turn: bool) -> String {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let mut board = make_board(black_board, white_board, !turn);
+// 合法手でなければ(打てる手があるのにpassでも)エラー
+#[wasm_bindgen(unchecked_return_type = "MoveResult")]
+pub fn update_board(board_str: &str, mv: &str, turn: bool) -> Result<JsValue, JsError> {
+    let mut board = parse_board_for(board_str, turn)?;
+    let mv = parse_move(mv)?;
+    let valid_moves = board.get_valid_moves();
 
-    let next_move = format_move(mv);
-    board.do_move_interface(next_move, true);
-    if !turn {
-        board.change_turn();
+    let flips = if mv == game::PASS {
+        if valid_moves != 0 {
+            return Err(JsError::new("Cannot pass when there are legal moves"));
+        }
+        play::MoveFlips { square: mv, directions: [0; 8] }
+    } else {
+        if valid_moves & (1u64 << mv) == 0 {
+            return Err(JsError::new(&format!("Illegal move: {}", game::square_name(mv))));
+        }
+        let flips = board.get_move_flips(mv);
+        board.make_move(mv);
+        flips
+    };
+
+    let color = if turn { game::Color::White } else { game::Color::Black };
+    let position = game::Position::from_board(&board, color);
+
+    #[derive(Serialize)]
+    struct MoveResult {
+        board: String,
+        square: String,
+        flipped: Vec<String>,
+        directions: DirectionalFlips,
+        black: u32,
+        white: u32,
+        undo: UndoToken,
     }
-    
-    boards_to_string(board.my_board, board.opponent_board)
+    Ok(to_js(&MoveResult {
+        board: position.to_string(),
+        square: game::square_name(mv),
+        flipped: squares(flips.flipped()),
+        directions: DirectionalFlips::new(&flips),
+        black: position.black.count_ones(),
+        white: position.white.count_ones(),
+        undo: UndoToken { color, square: game::square_name(mv), flipped: squares(flips.flipped()) },
+    }))
 }
 
+// update_boardの返したundoで手を戻した盤面
+// 盤面がその手を打った後でなければエラー
 #[wasm_bindgen]
-pub fn get_ai_move(board_str: &str, turn: bool, assigned_time_ms: i32, disturbance: f32) -> usize {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
+pub fn undo_board(
+    board_str: &str,
+    #[wasm_bindgen(unchecked_param_type = "UndoToken")] undo: JsValue,
+) -> Result<String, JsError> {
+    let undo: UndoToken = serde_wasm_bindgen::from_value(undo).map_err(|e| JsError::new(&format!("Invalid undo token: {}", e)))?;
+    let mut board = parse_board_for(board_str, undo.color == game::Color::White)?;
+    let square = parse_move(&undo.square)?;
+    let mut flipped = 0u64;
+    for s in &undo.flipped {
+        match game::parse_square(s) {
+            Some(i) if i < 64 => flipped |= 1u64 << i,
+            _ => return Err(JsError::new(&format!("Invalid square: {}", s))),
+        }
+    }
+
+    if square != game::PASS {
+        let placed = (1u64 << square) | flipped;
+        if board.my_board & placed != placed {
+            return Err(JsError::new("Board does not match the undo token"));
+        }
+        board.unmake_move(play::Undo { square, flipped });
+    } else if flipped != 0 {
+        return Err(JsError::new("Board does not match the undo token"));
+    }
+    Ok(game::Position::from_board(&board, undo.color).to_string())
+}
 
-    //return board.get_valid_moves().trailing_zeros() as usize; // stub
+#[wasm_bindgen(unchecked_return_type = "Move")]
+pub fn get_ai_move(board_str: &str, turn: bool, assigned_time_ms: i32, disturbance: f32) -> Result<String, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    Ok(game::square_name(board.decide_move(assigned_time_ms as u64, disturbance) as u8))
+}
 
-    board.decide_move(assigned_time_ms as u64, disturbance)
+// 制限つきでAIの手を取得
+// limitsは {max_depth, max_nodes, max_time_ms, solve_empties} (どれも省略可、省略したものは制限しない)
+// {mv, stats}(statsはget_search_statsと同じ)
+#[wasm_bindgen(unchecked_return_type = "SearchResult")]
+pub fn get_ai_move_with_limits(
+    board_str: &str,
+    turn: bool,
+    #[wasm_bindgen(unchecked_param_type = "SearchLimits")] limits: JsValue,
+    disturbance: f32,
+) -> Result<JsValue, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    let (mv, stats) = board.search_with_limits(&parse_limits(limits)?, disturbance);
+    Ok(to_js(&SearchResult { mv: game::square_name(mv as u8), stats }))
 }
 
 #[wasm_bindgen]
-pub fn is_pass(board_str: &str, turn: bool) -> bool {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
-    board.get_valid_moves() == 0
+pub fn is_pass(board_str: &str, turn: bool) -> Result<bool, JsError> {
+    Ok(parse_board_for(board_str, turn)?.get_valid_moves() == 0)
 }
 
+// 勝敗 {state, black, white, reason}
+// stateは"BlackWin", "WhiteWin", "Draw", "Ongoing"、reasonは終局の理由(終わっていなければundefined)
+#[wasm_bindgen(unchecked_return_type = "GameResult")]
+pub fn get_result(board_str: &str) -> Result<JsValue, JsError> {
+    let (black, white) = parse_board(board_str)?;
+    let game = game::Game::from_position(game::Position { black, white, to_move: game::Color::Black });
+    Ok(to_js(&GameResult::of(&game)))
+}
+
+// 合法手のマスの配列
+#[wasm_bindgen(unchecked_return_type = "Square[]")]
+pub fn get_valid_moves(board_str: &str, turn: bool) -> Result<Vec<String>, JsError> {
+    Ok(squares(parse_board_for(board_str, turn)?.get_valid_moves()))
+}
+
+// 確定石(以後返らない石)のマスの配列
+// 黒白両方の確定石を返す
+#[wasm_bindgen(unchecked_return_type = "Square[]")]
+pub fn get_stable_discs(board_str: &str) -> Result<Vec<String>, JsError> {
+    let (black_board, white_board) = parse_board(board_str)?;
+    let black = Board { my_board: black_board, opponent_board: white_board };
+    let white = Board { my_board: white_board, opponent_board: black_board };
+    Ok(squares(database::get_stable(&black) | database::get_stable(&white)))
+}
+
+// 空きマスの領域と偶奇を取得
+// [{squares: ["a1", "b1", ...], odd: true}, ...]
+#[wasm_bindgen(unchecked_return_type = "ParityRegion[]")]
+pub fn get_parity_regions(board_str: &str) -> Result<JsValue, JsError> {
+    let (black_board, white_board) = parse_board(board_str)?;
+    let board = Board { my_board: black_board, opponent_board: white_board };
+
+    #[derive(Serialize)]
+    struct RegionView {
+        squares: Vec<String>,
+        odd: bool,
+    }
+    let regions: Vec<_> = parity::analyze(&board).into_iter()
+        .map(|r| RegionView { squares: r.squares.into_iter().map(game::square_name).collect(), odd: r.odd })
+        .collect();
+    Ok(to_js(&regions))
+}
+
+// 指定した手が合法かチェック
 #[wasm_bindgen]
-pub fn get_result(board_str: &str) -> String {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board_0 = make_board(black_board, white_board, false);
-    let board_1 = make_board(black_board, white_board, true);
-    
-    if board_0.get_valid_moves() == 0 && board_1.get_valid_moves() == 0 {
-        let count_black = black_board.count_ones();
-        let count_white = white_board.count_ones();
-
-        if count_black > count_white {
-            "BlackWin".to_string()
-        } else if count_white > count_black {
-            "WhiteWin".to_string()
-        } else {
-            "Draw".to_string()
-        }
-    } else {
-        "Ongoing".to_string()
+pub fn is_valid_move(board_str: &str, mv: &str, turn: bool) -> Result<bool, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    let mv = parse_move(mv)?;
+    Ok(mv < 64 && board.get_valid_moves() & (1u64 << mv) != 0)
+}
+
+// 石差と勝率に較正した評価値を取得
+#[wasm_bindgen(unchecked_return_type = "CalibratedScore")]
+pub fn get_calibrated_eval(board_str: &str, turn: bool) -> Result<JsValue, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    Ok(to_js(&board.get_calibrated_eval()))
+}
+
+// 各合法手の評価（石差・勝率）を良い順に取得
+#[wasm_bindgen(unchecked_return_type = "Hint[]")]
+pub fn get_hints(board_str: &str, turn: bool, assigned_time_ms: i32) -> Result<JsValue, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    Ok(to_js(&hint_views(board.get_hints(assigned_time_ms as u64))))
+}
+
+// 終盤を選択的に読み切る
+// 確からしさの低い段階から時間の許す限り段階を上げ、終わった最も高い段階の結果を返す
+// {score, mv, confidence}、最初の段階も終わらなければundefined
+#[wasm_bindgen(unchecked_return_type = "EndgameResult | undefined")]
+pub fn solve_endgame(board_str: &str, turn: bool, assigned_time_ms: i32) -> Result<JsValue, JsError> {
+    let board = parse_board_for(board_str, turn)?;
+    let time_manager = play::TimeManager::new(assigned_time_ms as u64);
+
+    #[derive(Serialize)]
+    struct EndgameView {
+        score: i8,
+        mv: String,
+        confidence: u8,
     }
+    let engine = engine::default_engine();
+    let result = solver::solve_progressive(&board, engine.probcut(), engine.solver_table(), &time_manager, engine.solver_threads(), |_| ())
+        .map(|r| EndgameView { score: r.score, mv: game::square_name(r.mv), confidence: r.confidence });
+    Ok(to_js(&result))
 }
 
-// 合法手のビットマスクを文字列で取得
+// 評価関数を切り替える（"network" または "linear"）
+// 知らない名前ならエラーを投げる
 #[wasm_bindgen]
-pub fn get_valid_moves(board_str: &str, turn: bool) -> String {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
-    let valid_moves = board.get_valid_moves();
-    
-    // ビットマスクを文字列で表現（64文字、'1'は有効手、'0'は無効手）
-    let mut result = String::with_capacity(64);
-    for i in 0..64 {
-        if valid_moves & (1u64 << i) != 0 {
-            result.push('1');
+pub fn set_evaluator(name: &str) -> Result<(), JsError> {
+    engine::set_default_option("evaluator", name).map_err(|e| JsError::new(&e))
+}
+
+// Multi-ProbCut(選択的探索)の有無を切り替える
+// 解析では止めて全幅で読む
+#[wasm_bindgen]
+pub fn set_selectivity(enabled: bool) {
+    engine::update_default_engine(|engine| engine.set_selectivity(enabled));
+}
+
+// Multi-ProbCutで予測値の何σ外なら打ち切るか(既定1.5)
+#[wasm_bindgen]
+pub fn set_probcut_confidence(t: f32) {
+    engine::update_default_engine(|engine| engine.set_probcut_confidence(t));
+}
+
+// 名前で設定を変える(一覧はget_option_list)
+// 値は文字列で渡す、名前・型・範囲が正しくなければエラーを投げる
+#[wasm_bindgen]
+pub fn set_option(name: &str, value: &str) -> Result<(), JsError> {
+    engine::set_default_option(name, value).map_err(|e| JsError::new(&e))
+}
+
+// 設定の今の値、知らない名前ならundefined
+#[wasm_bindgen(unchecked_return_type = "OptionValue | undefined")]
+pub fn get_option(name: &str) -> JsValue {
+    to_js(&engine::default_engine().get_option(name))
+}
+
+// 設定の一覧 [{name, type, min, max, values, default, description}]
+// UIはこれから設定画面を組み立てる
+#[wasm_bindgen(unchecked_return_type = "OptionSpec[]")]
+pub fn get_option_list() -> JsValue {
+    to_js(&*options::OPTIONS)
+}
+
+// 置換表の大きさ(MB)を変える、内容は消える
+#[wasm_bindgen]
+pub fn set_hash_size(size_mb: usize) {
+    database::set_cache_size_mb(size_mb);
+}
+
+// 置換表を空にする
+#[wasm_bindgen]
+pub fn clear_hash() {
+    database::clear_cache();
+}
+
+// 直前の探索の置換表の統計
+// {size_mb, probes, hits, hit_rate, fill}
+#[wasm_bindgen(unchecked_return_type = "HashStats")]
+pub fn get_hash_stats() -> JsValue {
+    to_js(&database::get_cache().stats())
+}
+
+// 直前のget_ai_move(get_ai_move_with_limits)の探索の統計
+// {nodes, helper_nodes, evals, tt_probes, tt_hits, tt_cutoffs, solver_nodes, depth, selective_depth, time_ms, nps, book, solver, selective_solver, solver_confidence, score}
+#[wasm_bindgen(unchecked_return_type = "SearchStats")]
+pub fn get_search_stats() -> JsValue {
+    to_js(&engine::default_engine().last_search_stats())
+}
+
+// 独立した探索エンジン
+// 置換表・評価関数の設定・乱数・ログを他のエンジンと共有しないので、対局の両方のプレイヤーに別々に持たせられる
+// 上の関数(get_ai_moveなど)は既定のエンジンを使う
+// initialize()の後に作る(前に作ると定石を使わない)
+#[wasm_bindgen(js_name = Engine)]
+pub struct WasmEngine {
+    engine: engine::Engine,
+}
+
+#[wasm_bindgen(js_class = Engine)]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { engine: engine::Engine::new() }
+    }
+
+    // 評価関数を切り替える（"network" または "linear"）
+    pub fn set_evaluator(&mut self, name: &str) -> Result<(), JsError> {
+        self.engine.set_option("evaluator", name).map_err(|e| JsError::new(&e))
+    }
+
+    // 名前で設定を変える(一覧はget_option_list)
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), JsError> {
+        self.engine.set_option(name, value).map_err(|e| JsError::new(&e))
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "OptionValue | undefined")]
+    pub fn get_option(&self, name: &str) -> JsValue {
+        to_js(&self.engine.get_option(name))
+    }
+
+    // 置換表の大きさ(MB)を変える、内容は消える
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        self.engine.set_cache_size_mb(size_mb);
+    }
+
+    pub fn clear_hash(&self) {
+        self.engine.clear_cache();
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "HashStats")]
+    pub fn get_hash_stats(&self) -> JsValue {
+        to_js(&self.engine.cache().stats())
+    }
+
+    // 直前の探索のノード数と評価関数の呼び出し回数 {nodes, evals, tt_cutoffs}
+    #[wasm_bindgen(unchecked_return_type = "EngineStats")]
+    pub fn get_stats(&self) -> JsValue {
+        to_js(&self.engine.stats())
+    }
+
+    // 直前のget_ai_move(get_ai_move_with_limits)の探索の統計(get_search_statsと同じ形)
+    #[wasm_bindgen(unchecked_return_type = "SearchStats")]
+    pub fn get_search_stats(&self) -> JsValue {
+        to_js(&self.engine.last_search_stats())
+    }
+
+    // 評価値に加える乱れの乱数の種
+    pub fn set_seed(&self, seed: u32) {
+        self.engine.set_seed(seed as u64);
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "Move")]
+    pub fn get_ai_move(&self, board_str: &str, turn: bool, assigned_time_ms: i32, disturbance: f32) -> Result<String, JsError> {
+        let board = parse_board_for(board_str, turn)?;
+        Ok(game::square_name(self.engine.decide_move(&board, assigned_time_ms as u64, disturbance) as u8))
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "SearchResult")]
+    pub fn get_ai_move_with_limits(
+        &self,
+        board_str: &str,
+        turn: bool,
+        #[wasm_bindgen(unchecked_param_type = "SearchLimits")] limits: JsValue,
+        disturbance: f32,
+    ) -> Result<JsValue, JsError> {
+        let board = parse_board_for(board_str, turn)?;
+        let (mv, stats) = self.engine.search(&board, &parse_limits(limits)?, disturbance);
+        Ok(to_js(&SearchResult { mv: game::square_name(mv as u8), stats }))
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "Hint[]")]
+    pub fn get_hints(&self, board_str: &str, turn: bool, assigned_time_ms: i32) -> Result<JsValue, JsError> {
+        let board = parse_board_for(board_str, turn)?;
+        Ok(to_js(&hint_views(self.engine.hints(&board, assigned_time_ms as u64))))
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "CalibratedScore")]
+    pub fn get_calibrated_eval(&self, board_str: &str, turn: bool) -> Result<JsValue, JsError> {
+        let board = parse_board_for(board_str, turn)?;
+        Ok(to_js(&self.engine.calibrated_eval(&board)))
+    }
+
+    pub fn get_log(&self) -> String {
+        self.engine.logger().contents()
+    }
+
+    pub fn clear_log(&self) {
+        self.engine.logger().clear();
+    }
+}
+
+impl Default for WasmEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 対局の状態(盤面・手番・手の列・パス・待ったとやり直し・勝敗)
+// 規則はすべてこちらで扱い、UIは表示するだけにする
+// 打てる手がなければ自動でパスする
+#[wasm_bindgen(js_name = Game)]
+pub struct WasmGame {
+    game: game::Game,
+}
+
+#[wasm_bindgen(js_class = Game)]
+impl WasmGame {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { game: game::Game::new() }
+    }
+
+    // 盤面の文字列とturn(trueなら白の手番)から始める
+    pub fn from_board(board_str: &str, turn: bool) -> Result<WasmGame, JsError> {
+        let (black, white) = parse_board(board_str)?;
+        let to_move = if turn { game::Color::White } else { game::Color::Black };
+        Ok(Self { game: game::Game::from_position(game::Position { black, white, to_move }) })
+    }
+
+    // 手番つきの盤面の文字列(Edax/OBFの"64文字 手番"など)から始める
+    pub fn from_position(position: &str) -> Result<WasmGame, JsError> {
+        Ok(Self { game: game::Game::from_position(notation::parse(position)?) })
+    }
+
+    // 今の局面をEdax/OBFの形式(X: 黒, O: 白, -: 空き、空白の後に手番)で書く
+    pub fn position(&self) -> String {
+        notation::format(self.game.position(), notation::Notation::Obf)
+    }
+
+    // 盤面の文字列(B: 黒, W: 白, .: 空き)
+    pub fn board(&self) -> String {
+        self.game.position().to_string()
+    }
+
+    // trueなら白の手番
+    pub fn current_player(&self) -> bool {
+        self.game.side_to_move() == game::Color::White
+    }
+
+    // 打った手の数(パスを含む)
+    pub fn ply(&self) -> usize {
+        self.game.ply()
+    }
+
+    // マスの石、空きならundefined
+    #[wasm_bindgen(unchecked_return_type = "Color | undefined")]
+    pub fn disc(&self, square: &str) -> Result<JsValue, JsError> {
+        let square = parse_move(square)?;
+        let position = self.game.position();
+        let color = if square == game::PASS {
+            None
+        } else if position.black >> square & 1 != 0 {
+            Some(game::Color::Black)
+        } else if position.white >> square & 1 != 0 {
+            Some(game::Color::White)
         } else {
-            result.push('0');
+            None
+        };
+        Ok(to_js(&color))
+    }
+
+    // 合法手のマスの配列
+    #[wasm_bindgen(unchecked_return_type = "Square[]")]
+    pub fn legal_moves(&self) -> Vec<String> {
+        squares(self.game.legal_moves())
+    }
+
+    pub fn is_legal(&self, square: &str) -> Result<bool, JsError> {
+        Ok(self.game.is_legal(parse_move(square)?))
+    }
+
+    // 手を打つ、相手がパスしたらtrue
+    // 合法手でなければエラーを投げる
+    pub fn play(&mut self, square: &str) -> Result<bool, JsError> {
+        let mv = parse_move(square)?;
+        self.game.play(mv).map_err(|_| JsError::new(&format!("Illegal move: {}", square)))
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.game.undo()
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.game.redo()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.game.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.game.can_redo()
+    }
+
+    // 最後に石を置いたマス、なければundefined
+    #[wasm_bindgen(unchecked_return_type = "Square | undefined")]
+    pub fn last_move(&self) -> Option<String> {
+        self.game.last_move().map(game::square_name)
+    }
+
+    // 最後に石を置いた手の方向ごとの返した石 {square, directions}(アニメーション用)、なければundefined
+    #[wasm_bindgen(unchecked_return_type = "MoveFlips | undefined")]
+    pub fn last_flips(&self) -> JsValue {
+        let flips = self.game.last_flips().map(|f| MoveFlipsView {
+            square: game::square_name(f.square),
+            directions: DirectionalFlips::new(&f),
+        });
+        to_js(&flips)
+    }
+
+    // 今の局面までの手 [{color, mv, flipped}](mvはマスか"pass"、flippedは返した石のマスの配列)
+    #[wasm_bindgen(unchecked_return_type = "MoveRecord[]")]
+    pub fn moves(&self) -> JsValue {
+        // JavaScriptの数は53ビットまでなので、返した石はビットボードでなくマスで渡す
+        #[derive(Serialize)]
+        struct MoveInfo {
+            color: game::Color,
+            mv: String,
+            flipped: Vec<String>,
         }
+        let moves: Vec<_> = self.game.moves().iter()
+            .map(|r| MoveInfo {
+                color: r.color,
+                mv: game::square_name(r.mv),
+                flipped: squares(r.flipped),
+            })
+            .collect();
+        to_js(&moves)
+    }
+
+    pub fn black_count(&self) -> u32 {
+        self.game.count(game::Color::Black)
+    }
+
+    pub fn white_count(&self) -> u32 {
+        self.game.count(game::Color::White)
+    }
+
+    // 勝敗 {state, black, white, reason}
+    // stateは"BlackWin", "WhiteWin", "Draw", "Ongoing"
+    // reasonは終局の理由 "BoardFull", "Wipeout", "NoMoves"、終わっていなければundefined
+    #[wasm_bindgen(unchecked_return_type = "GameResult")]
+    pub fn result(&self) -> JsValue {
+        to_js(&GameResult::of(&self.game))
+    }
+
+    // 棋譜(f5d6...)、with_passesならパスも"pass"と書く
+    pub fn transcript(&self, with_passes: Option<bool>) -> String {
+        let moves: Vec<u8> = self.game.moves().iter().map(|r| r.mv).collect();
+        transcript::write(&moves, with_passes.unwrap_or(false))
+    }
+
+    // 棋譜を初期局面から再生した対局(続きを打つ、待ったで見返す)
+    // 読めない手や不正な手があれば、最初のものをエラーで投げる
+    pub fn from_transcript(s: &str) -> Result<WasmGame, JsError> {
+        Ok(Self { game: transcript::load(s)? })
+    }
+
+    // GGFの棋譜の最初の対局を開始局面から再生した対局
+    // 読めないものや不正な手があればエラーを投げる
+    pub fn from_ggf(s: &str) -> Result<WasmGame, JsError> {
+        Ok(Self { game: ggf::parse(s)?.to_game()? })
+    }
+
+    // 今の局面までをGGFで書く(ダウンロード用)、infoは対局者などの情報と手ごとの評価値・消費時間
+    pub fn to_ggf(&self, #[wasm_bindgen(unchecked_param_type = "GgfExportInfo | undefined")] info: JsValue) -> Result<String, JsError> {
+        let info: GgfInfo = if info.is_undefined() || info.is_null() {
+            GgfInfo::default()
+        } else {
+            serde_wasm_bindgen::from_value(info).map_err(|e| JsError::new(&format!("Invalid game info: {}", e)))?
+        };
+        let mut record = ggf::GgfGame {
+            place: info.place,
+            date: info.date,
+            black_player: info.black_player,
+            white_player: info.white_player,
+            black_rating: info.black_rating,
+            white_rating: info.white_rating,
+            time_control: info.time_control,
+            ..ggf::GgfGame::from_game(&self.game)
+        };
+        record.set_annotations(&info.evals, &info.times);
+        Ok(record.to_string())
     }
-    result
 }
 
-// 指定した手が合法かチェック
-#[wasm_bindgen]
-pub fn is_valid_move(board_str: &str, mv: usize, turn: bool) -> bool {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
-    let valid_moves = board.get_valid_moves();
-    (valid_moves & (1u64 << mv)) != 0
+impl Default for WasmGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 棋譜を初期局面から再生して確かめる
+// {moves, result, score}(scoreは黒から見た石差、終局していれば空きマスは勝った側に数える)
+// 読めない手や不正な手があれば、最初のものをエラーで投げる
+#[wasm_bindgen(unchecked_return_type = "TranscriptResult")]
+pub fn replay_transcript(s: &str) -> Result<JsValue, JsError> {
+    let game = transcript::load(s)?;
+
+    #[derive(Serialize)]
+    struct TranscriptResult {
+        moves: Vec<String>,
+        result: GameResult,
+        score: i32,
+    }
+    Ok(to_js(&TranscriptResult {
+        moves: game.moves().iter().map(|r| game::square_name(r.mv)).collect(),
+        result: GameResult::of(&game),
+        score: transcript::final_score(&game),
+    }))
+}
+
+// GGFの棋譜のすべての対局を読んで確かめる(アップロード用)
+// {games, errors}、読めない対局(8x8以外の盤や不正な手など)は飛ばし、errorsに何局目(0から)かと理由を入れる
+// 対局が一つもなければエラーを投げる
+#[wasm_bindgen(unchecked_return_type = "GgfReadResult")]
+pub fn read_ggf(s: &str) -> Result<JsValue, JsError> {
+    #[derive(Serialize)]
+    struct GgfReadError {
+        game: usize,
+        message: String,
+    }
+    #[derive(Serialize)]
+    struct GgfReadResult {
+        games: Vec<GgfRecord>,
+        errors: Vec<GgfReadError>,
+    }
+    let results = ggf::parse_all(s);
+    if results.is_empty() {
+        return Err(ggf::GgfError::NoGame.into());
+    }
+    let mut read = GgfReadResult { games: Vec::new(), errors: Vec::new() };
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(record) => read.games.push(GgfRecord::new(record)),
+            Err(e) => read.errors.push(GgfReadError { game: i, message: e.to_string() }),
+        }
+    }
+    Ok(to_js(&read))
 }
 
 // 石の個数を取得（デバッグ用）
-#[wasm_bindgen]
-pub fn get_stone_counts(board_str: &str) -> JsValue {
-    let (black_board, white_board) = string_to_boards(board_str);
-    let counts = serde_json::json!({
-        "black": black_board.count_ones(),
-        "white": white_board.count_ones()
-    });
-    JsValue::from_serde(&counts).unwrap()
+#[wasm_bindgen(unchecked_return_type = "StoneCounts")]
+pub fn get_stone_counts(board_str: &str) -> Result<JsValue, JsError> {
+    let (black_board, white_board) = parse_board(board_str)?;
+    Ok(to_js(&StoneCounts { black: black_board.count_ones(), white: white_board.count_ones() }))
 }
 
 #[cfg(test)]
@@ -198,13 +1048,13 @@ mod tests {
     fn test_ai_move() {
         // Standard opening position
         let board_str = "...........................WB......BW...........................";
-        
+
         // Test black's move (turn = false)
-        let ai_move = get_ai_move(board_str, false, 1000, 0.0);
-        assert!(ai_move < 64, "AI move should be a valid board position");
-        
+        let ai_move = get_ai_move(board_str, false, 1000, 0.0).unwrap();
+        assert_ne!(ai_move, "pass", "AI move should be a valid board position");
+
         // Verify it's a legal move
-        assert!(is_valid_move(board_str, ai_move, false), "AI move should be legal");
+        assert!(get_valid_moves(board_str, false).unwrap().contains(&ai_move), "AI move should be legal");
     }
 }
 
@@ -213,21 +1063,17 @@ pub fn test_eval_wasm_simple() -> f32 {
     test_wasm::test_eval_wasm()
 }
 
-#[wasm_bindgen] 
-pub fn test_ai_move_simple() -> usize {
-    test_wasm::test_decide_move_simple()
+#[wasm_bindgen(unchecked_return_type = "Move")]
+pub fn test_ai_move_simple() -> String {
+    game::square_name(test_wasm::test_decide_move_simple() as u8)
 }
 
-// ログ管理機能
+// ログ管理機能(既定のエンジンのログ)
 #[wasm_bindgen]
 pub fn get_log() -> String {
     #[cfg(target_arch = "wasm32")]
     {
-        if let Ok(buffer) = crate::play::LOG_BUFFER.lock() {
-            buffer.join("\n")
-        } else {
-            "Failed to access log buffer".to_string()
-        }
+        engine::default_engine().logger().contents()
     }
     
     #[cfg(not(target_arch = "wasm32"))]
@@ -238,28 +1084,22 @@ pub fn get_log() -> String {
 
 #[wasm_bindgen]
 pub fn clear_log() {
-    #[cfg(target_arch = "wasm32")]
-    {
-        if let Ok(mut buffer) = crate::play::LOG_BUFFER.lock() {
-            buffer.clear();
-        }
-    }
+    engine::default_engine().logger().clear();
 }
 
 // デバッグ用の関数
 #[wasm_bindgen]
-pub fn debug_board_state(board_str: &str, turn: bool, context: &str) {
+pub fn debug_board_state(board_str: &str, turn: bool, context: &str) -> Result<(), JsError> {
     initialize();
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
+    let board = parse_board_for(board_str, turn)?;
     board.debug_board_state(context);
+    Ok(())
 }
 
 #[wasm_bindgen]
-pub fn test_corner_evaluation(board_str: &str, turn: bool) -> f32 {
+pub fn test_corner_evaluation(board_str: &str, turn: bool) -> Result<f32, JsError> {
     initialize();
-    let (black_board, white_board) = string_to_boards(board_str);
-    let board = make_board(black_board, white_board, turn);
+    let board = parse_board_for(board_str, turn)?;
     
     // 角に石がある場合とない場合の評価値を比較
     let current_eval = board.get_eval();
@@ -277,5 +1117,5 @@ pub fn test_corner_evaluation(board_str: &str, turn: bool) -> f32 {
             corner_names[i], my_has_corner, op_has_corner).into());
     }
     
-    current_eval
+    Ok(current_eval)
 }
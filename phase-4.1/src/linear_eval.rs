@@ -0,0 +1,292 @@
+/*
+線形評価関数 (Logistello/Edax型)
+パターンごとの重み表の和に、着手可能数・潜在的着手可能数(database::calc_freedom)・偶数理論(parity)の項を加える
+ニューラルネットワークよりずっと軽いので、低速な端末では評価の質と引き換えに深く読める
+
+重みはステージ(eval_stage::StageLayout、補間なし)ごとに持ち、
+同じグループのパターンは対称形なので重みを共有する(pattern::ALIGNED_PATTERNS)
+評価値は手番側から見た石差で、重みは1/WEIGHT_SCALE石単位のi16
+
+重みファイル(eval_linear.bin)の形式 (リトルエンディアン)
+    b"RVLN", version: u32, stage_count: u32
+    ステージごとに
+        特徴量の重み: i16 * FEATURE_COUNT
+        パターングループごとに (非ゼロ要素数: u32, (インデックス: u32, 重み: i16) * 非ゼロ要素数)
+*/
+
+use crate::database;
+use crate::eval_stage::StageLayout;
+use crate::parity;
+use crate::pattern::{canonical_indices, ternary_index, ALIGNED_PATTERNS, PATTERNS, PATTERN_GROUP_COUNT, PATTERN_GROUP_SIZE, PATTERN_MASK};
+use crate::play::Board;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::LazyLock;
+
+pub const LINEAR_MAGIC: &[u8; 4] = b"RVLN";
+pub const LINEAR_VERSION: u32 = 2;
+pub const LINEAR_WEIGHTS_PATH: &str = "src/eval_linear.bin";
+
+pub const WEIGHT_SCALE: f32 = 64.0;
+
+// パターン以外の特徴量
+// 定数項, 着手可能数(自分, 相手), 潜在的着手可能数(自分, 相手), 偶数理論(空きマスが奇数なら1, 偶数なら-1),
+// 空きマスが奇数個の領域の数
+pub const FEATURE_COUNT: usize = 7;
+
+static LINEAR_WEIGHTS: &[u8] = include_bytes!("eval_linear.bin");
+
+pub static LINEAR_EVAL: LazyLock<LinearEval> = LazyLock::new(|| {
+    LinearEval::from_bytes(LINEAR_WEIGHTS).expect("Failed to parse eval_linear.bin")
+});
+
+// グループごとの重み表の大きさ(3^パターン長)
+fn table_size(group: usize) -> usize {
+    3usize.pow(PATTERNS[group * PATTERN_GROUP_SIZE].len() as u32)
+}
+
+pub fn features(board: &Board) -> [f32; FEATURE_COUNT] {
+    let my_mobility = board.get_valid_moves().count_ones() as f32;
+    let mut t = *board;
+    t.change_turn();
+    let op_mobility = t.get_valid_moves().count_ones() as f32;
+
+    // 石がない側はcalc_freedomがNaNになる
+    let (my_freedom, op_freedom) = database::calc_freedom(board);
+    let or_zero = |x: f32| if x.is_nan() { 0.0 } else { x };
+
+    let parity = if (64 - board.get_turn()) % 2 == 1 { 1.0 } else { -1.0 };
+    let odd_regions = parity::get_regions(!(board.my_board | board.opponent_board))
+        .iter()
+        .filter(|r| r.count_ones() % 2 == 1)
+        .count() as f32;
+    [1.0, my_mobility, op_mobility, or_zero(my_freedom), or_zero(op_freedom), parity, odd_regions]
+}
+
+// 各パターンの(グループ, 重み表のインデックス)
+fn pattern_entries(board: &Board) -> impl Iterator<Item = (usize, usize)> + '_ {
+    ALIGNED_PATTERNS.iter().enumerate().map(move |(pattern_id, aligned)| {
+        (pattern_id / PATTERN_GROUP_SIZE, ternary_index(board, aligned, PATTERN_MASK[pattern_id]))
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearStage {
+    pub features: [i16; FEATURE_COUNT],
+    // tables[group][index]
+    pub tables: Vec<Vec<i16>>,
+}
+
+impl LinearStage {
+    fn zero() -> Self {
+        Self {
+            features: [0; FEATURE_COUNT],
+            tables: (0..PATTERN_GROUP_COUNT).map(|g| vec![0; table_size(g)]).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearEval {
+    pub layout: StageLayout,
+    pub stages: Vec<LinearStage>,
+}
+
+impl LinearEval {
+    // 手番側から見た石差
+    pub fn eval(&self, board: &Board) -> f32 {
+        let stage = &self.stages[self.layout.stage_of(64 - board.get_turn())];
+
+        let mut sum = 0i32;
+        for (group, index) in pattern_entries(board) {
+            sum += stage.tables[group][index] as i32;
+        }
+        let scalar: f32 = features(board).iter()
+            .zip(stage.features.iter())
+            .map(|(&f, &w)| f * w as f32)
+            .sum();
+
+        (sum as f32 + scalar) / WEIGHT_SCALE
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut pos = 0;
+        let mut take = |len: usize| -> Result<&[u8], String> {
+            let chunk = bytes.get(pos..pos + len).ok_or("Unexpected end of weight file")?;
+            pos += len;
+            Ok(chunk)
+        };
+        let read_u32 = |chunk: &[u8]| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let read_i16 = |chunk: &[u8]| i16::from_le_bytes([chunk[0], chunk[1]]);
+
+        if take(4)? != &LINEAR_MAGIC[..] {
+            return Err("Invalid weight file magic".to_string());
+        }
+        let version = read_u32(take(4)?);
+        if version != LINEAR_VERSION {
+            return Err(format!("Unsupported weight file version: {}", version));
+        }
+        let stage_count = read_u32(take(4)?) as usize;
+        if stage_count == 0 {
+            return Err("Weight file has no stages".to_string());
+        }
+
+        let mut stages = Vec::with_capacity(stage_count);
+        for _ in 0..stage_count {
+            let mut stage = LinearStage::zero();
+            for w in stage.features.iter_mut() {
+                *w = read_i16(take(2)?);
+            }
+            for table in stage.tables.iter_mut() {
+                let count = read_u32(take(4)?) as usize;
+                for _ in 0..count {
+                    let index = read_u32(take(4)?) as usize;
+                    let weight = read_i16(take(2)?);
+                    *table.get_mut(index).ok_or(format!("Pattern index out of range: {}", index))? = weight;
+                }
+            }
+            stages.push(stage);
+        }
+
+        Ok(Self {
+            layout: StageLayout::new(stage_count, false),
+            stages,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = LINEAR_MAGIC.to_vec();
+        out.extend_from_slice(&LINEAR_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.stages.len() as u32).to_le_bytes());
+        for stage in &self.stages {
+            for w in stage.features {
+                out.extend_from_slice(&w.to_le_bytes());
+            }
+            for table in &stage.tables {
+                let nonzero: Vec<(usize, i16)> = table.iter().copied().enumerate().filter(|&(_, w)| w != 0).collect();
+                out.extend_from_slice(&(nonzero.len() as u32).to_le_bytes());
+                for (index, w) in nonzero {
+                    out.extend_from_slice(&(index as u32).to_le_bytes());
+                    out.extend_from_slice(&w.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+}
+
+pub struct TrainOptions {
+    pub stage_count: usize,
+    pub epochs: usize,
+    pub learning_rate: f32,
+    // 隣のステージから借りる空きマス数
+    pub overlap: usize,
+    pub seed: u64,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        Self { stage_count: 6, epochs: 5, learning_rate: 0.1, overlap: 2, seed: 1 }
+    }
+}
+
+// (局面, 手番側から見た最終石差)から重みを学習する
+// 正規化LMS(1局面ごとに、特徴量のノルムで割った誤差で更新)
+// 終盤側のステージから学習し、前のステージの重みを初期値にする
+// 対称な形は同じ重みになるよう、まとめたインデックス(pattern::canonical_indices)で学習する
+pub fn train(samples: &[(Board, i8)], options: &TrainOptions) -> LinearEval {
+    let layout = StageLayout::new(options.stage_count, false);
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let canonical: Vec<Vec<u32>> = (0..PATTERN_GROUP_COUNT).map(canonical_indices).collect();
+
+    struct Encoded {
+        entries: Vec<(usize, usize)>,
+        features: [f32; FEATURE_COUNT],
+        target: f32,
+    }
+    let encoded: Vec<(usize, Encoded)> = samples.iter().map(|(board, disc_diff)| {
+        (64 - board.get_turn(), Encoded {
+            entries: pattern_entries(board).map(|(g, i)| (g, canonical[g][i] as usize)).collect(),
+            features: features(board),
+            target: *disc_diff as f32,
+        })
+    }).collect();
+
+    let mut tables: Vec<Vec<f32>> = (0..PATTERN_GROUP_COUNT).map(|g| vec![0.0; table_size(g)]).collect();
+    let mut weights = [0.0f32; FEATURE_COUNT];
+    let mut stages = Vec::with_capacity(options.stage_count);
+
+    for s in 0..options.stage_count {
+        let members: Vec<usize> = (0..=60).filter(|&e| layout.stage_of(e) == s).collect();
+        let (lo, hi) = (members[0].saturating_sub(options.overlap), members[members.len() - 1] + options.overlap);
+        let mut batch: Vec<&Encoded> = encoded.iter()
+            .filter(|(empties, _)| (lo..=hi).contains(empties))
+            .map(|(_, e)| e)
+            .collect();
+
+        for epoch in 0..options.epochs {
+            batch.shuffle(&mut rng);
+            let mut total = 0.0;
+            for e in &batch {
+                let predicted = e.entries.iter().map(|&(g, i)| tables[g][i]).sum::<f32>()
+                    + e.features.iter().zip(weights.iter()).map(|(f, w)| f * w).sum::<f32>();
+                let err = e.target - predicted;
+                total += err * err;
+
+                let norm = e.entries.len() as f32 + e.features.iter().map(|f| f * f).sum::<f32>();
+                let step = options.learning_rate * err / norm;
+                for &(g, i) in &e.entries {
+                    tables[g][i] += step;
+                }
+                for (w, f) in weights.iter_mut().zip(e.features.iter()) {
+                    *w += step * f;
+                }
+            }
+            eprintln!("stage {} (empties {}-{}, {} positions) epoch {}: mse={:.3}",
+                s, lo, hi, batch.len(), epoch + 1, total / batch.len().max(1) as f32);
+        }
+
+        let quantize = |w: f32| (w * WEIGHT_SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        stages.push(LinearStage {
+            features: weights.map(quantize),
+            tables: tables.iter().zip(canonical.iter())
+                .map(|(t, c)| c.iter().map(|&i| quantize(t[i as usize])).collect())
+                .collect(),
+        });
+    }
+
+    LinearEval { layout, stages }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_eval() {
+        let eval = LinearEval::from_bytes(LINEAR_WEIGHTS).unwrap();
+        assert_eq!(eval.stages.len(), eval.layout.stage_count);
+        assert_eq!(LinearEval::from_bytes(&eval.to_bytes()).unwrap(), eval);
+        assert!(LinearEval::from_bytes(b"XXXX").is_err());
+        assert!(LinearEval::from_bytes(&LINEAR_WEIGHTS[..14]).is_err());
+
+        // 初期局面は対称なので、どの初手の後も同じ評価になる
+        let mut board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let values: Vec<f32> = (0..64u8).filter(|&m| (board.get_valid_moves() >> m) & 1 != 0).map(|m| {
+            let mut b = board;
+            b.do_move(m);
+            b.change_turn();
+            eval.eval(&b)
+        }).collect();
+        assert_eq!(values.len(), 4);
+        assert!(values.iter().all(|&v| v == values[0]));
+
+        // 学習データの石差に近づく
+        board.do_move(19);
+        let samples = vec![(board, 10i8); 50];
+        let trained = train(&samples, &TrainOptions { stage_count: 1, epochs: 5, ..Default::default() });
+        assert!((trained.eval(&board) - 10.0).abs() < 1.0, "{}", trained.eval(&board));
+    }
+}
@@ -0,0 +1,499 @@
+// 学習・解析用のコマンドラインツール
+use fl_reversi_rs::calibration::{self, Calibration};
+use fl_reversi_rs::database;
+use fl_reversi_rs::engine;
+use fl_reversi_rs::game::{square_name, Position};
+use fl_reversi_rs::ggf::{self, GgfGame};
+use fl_reversi_rs::linear_eval::{self, TrainOptions};
+use fl_reversi_rs::notation;
+use fl_reversi_rs::options::OPTIONS;
+use fl_reversi_rs::play::{self, EvalKind, SearchLimits, TimeManager};
+use fl_reversi_rs::probcut::{self, ProbCut};
+use fl_reversi_rs::protocol;
+use fl_reversi_rs::selfplay;
+use fl_reversi_rs::transcript;
+use fl_reversi_rs::wthor::{self, WthorGame};
+
+use getopts::Options;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::process;
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} COMMAND [options]\n\nCommands:\n    fit-calibration    fit eval -> disc difference / win probability coefficients\n    generate-dataset   write self-play positions for src/train_stages.py\n                       (or positions of WTHOR games with --wthor FILE.wtb)\n    train-linear DATASET\n                       train the linear pattern evaluator (src/eval_linear.bin)\n    fit-probcut DATASET\n                       fit Multi-ProbCut parameters (src/probcut.json)\n    search [options] -- POSITION\n                       search a position (64 chars, X: side to move, O: opponent, -: empty,\n                       or Edax/OBF with the side to move)\n                       with --depth / --nodes / --time / --solve-empties limits\n    ggf-import FILE    check the games in a GGF file and print their players, scores and transcripts
+    ggf-export [options] -- TRANSCRIPT
+                       write a transcript (f5d6c3...) as a GGF game record
+                       with --black / --white names and --start POSITION (Edax/OBF)
+    wthor-stats FILE.wtb...\n                       count results, corrupt games, openings and players in WTHOR files\n                       with --plies N (opening length), --players FILE.jou, --tournaments FILE.trn\n    build-book --out FILE FILE.wtb...\n                       build an opening book (like src/book.bin) from WTHOR games\n                       with --plies N and --min-games N\n    options            list engine options (set with --option NAME=VALUE)\n    protocol           read text protocol commands from stdin (setoption, options, position, go, ...)", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn parse_opt<T: std::str::FromStr>(matches: &getopts::Matches, name: &str, default: T) -> T {
+    match matches.opt_str(name) {
+        Some(s) => s.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid value for --{}: {}", name, s);
+            process::exit(1);
+        }),
+        None => default,
+    }
+}
+
+fn fit_calibration(matches: &getopts::Matches) {
+    let games = parse_opt(matches, "games", 200);
+    let random_plies = parse_opt(matches, "random-plies", 10);
+    let solve_empties = parse_opt(matches, "solve-empties", 14);
+    let seed = parse_opt(matches, "seed", 1);
+    // 係数は--evalの評価関数の分を当てはめる
+    let out = matches.opt_str("out").unwrap_or(calibration::path_for_eval(play::get_eval_kind()).to_string());
+
+    eprintln!("Collecting samples from {} games (solve from {} empties)...", games, solve_empties);
+    let samples = calibration::collect_samples(games, random_plies, solve_empties, seed);
+    eprintln!("{} samples collected", samples.len());
+
+    let calibration = Calibration::fit(&samples);
+    for (empties, c) in calibration.stages.iter().enumerate().step_by(10) {
+        eprintln!("empties {:2}: disc = {:.3} * eval + {:.3}, win = sigmoid({:.3} * eval + {:.3})",
+            empties, c.disc_slope, c.disc_intercept, c.win_slope, c.win_intercept);
+    }
+
+    fs::write(&out, calibration.to_json()).expect("Failed to write calibration file");
+    eprintln!("Written to {}", out);
+}
+
+// WTHORのファイルの対局(壊れた対局は番号とエラーを表示して除く)
+fn read_wthor_games(paths: &[String]) -> Vec<WthorGame> {
+    let mut games = Vec::new();
+    for path in paths {
+        let reader = wthor::open(path).unwrap_or_else(|e| {
+            eprintln!("Failed to open {}: {}", path, e);
+            process::exit(1);
+        });
+        for (i, game) in reader.enumerate() {
+            match game.and_then(|game| game.replay().map(|_| game)) {
+                Ok(game) => games.push(game),
+                Err(e) => eprintln!("{}: game {}: {}", path, i + 1, e),
+            }
+        }
+    }
+    games
+}
+
+fn generate_dataset(matches: &getopts::Matches) {
+    let paths = matches.opt_strs("wthor");
+    if !paths.is_empty() {
+        let out = matches.opt_str("out").unwrap_or("dataset.txt".to_string());
+        let mut writer = BufWriter::new(File::create(&out).expect("Failed to create dataset file"));
+        let mut count = 0;
+        for game in read_wthor_games(&paths) {
+            let positions: Vec<_> = game.positions().unwrap().into_iter().map(|(b, _, diff)| (b, diff)).collect();
+            selfplay::write_dataset(&mut writer, &positions).expect("Failed to write dataset");
+            count += positions.len();
+        }
+        eprintln!("Written {} positions to {}", count, out);
+        return;
+    }
+
+    let games = parse_opt(matches, "games", 200);
+    let random_plies = parse_opt(matches, "random-plies", 10);
+    let solve_empties = parse_opt(matches, "solve-empties", 14);
+    let seed = parse_opt(matches, "seed", 1);
+    let out = matches.opt_str("out").unwrap_or("dataset.txt".to_string());
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let time_manager = TimeManager::new(u64::MAX / 2);
+    let mut writer = BufWriter::new(File::create(&out).expect("Failed to create dataset file"));
+    let mut count = 0;
+    for game in 0..games {
+        let positions = selfplay::play_game(&mut rng, random_plies, solve_empties, &time_manager);
+        selfplay::write_dataset(&mut writer, &positions).expect("Failed to write dataset");
+        count += positions.len();
+        if (game + 1) % 100 == 0 {
+            eprintln!("{} games, {} positions", game + 1, count);
+        }
+    }
+    eprintln!("Written {} positions to {}", count, out);
+}
+
+fn train_linear(matches: &getopts::Matches) {
+    let Some(path) = matches.free.get(1) else {
+        eprintln!("train-linear requires a dataset file");
+        process::exit(1);
+    };
+    let defaults = TrainOptions::default();
+    let options = TrainOptions {
+        stage_count: parse_opt(matches, "stages", defaults.stage_count),
+        epochs: parse_opt(matches, "epochs", defaults.epochs),
+        learning_rate: parse_opt(matches, "learning-rate", defaults.learning_rate),
+        overlap: defaults.overlap,
+        seed: parse_opt(matches, "seed", defaults.seed),
+    };
+    let out = matches.opt_str("out").unwrap_or(linear_eval::LINEAR_WEIGHTS_PATH.to_string());
+
+    let file = File::open(path).expect("Failed to open dataset file");
+    let samples = selfplay::read_dataset(BufReader::new(file)).expect("Failed to read dataset");
+    eprintln!("{} positions loaded", samples.len());
+
+    let eval = linear_eval::train(&samples, &options);
+    fs::write(&out, eval.to_bytes()).expect("Failed to write weight file");
+    eprintln!("Written to {}", out);
+}
+
+fn fit_probcut(matches: &getopts::Matches) {
+    let Some(path) = matches.free.get(1) else {
+        eprintln!("fit-probcut requires a dataset file");
+        process::exit(1);
+    };
+    let count = parse_opt(matches, "positions", 1000);
+    // 係数は--evalの評価関数で読んだ値から当てはめる
+    let kind = play::get_eval_kind();
+    let out = matches.opt_str("out").unwrap_or(probcut::path_for_eval(kind).to_string());
+
+    let file = File::open(path).expect("Failed to open dataset file");
+    let dataset = selfplay::read_dataset(BufReader::new(file)).expect("Failed to read dataset");
+    // データセット全体から等間隔に選ぶ
+    let step = (dataset.len() / count.max(1)).max(1);
+    let positions: Vec<_> = dataset.iter().step_by(step).take(count).map(|&(b, _)| b).collect();
+    eprintln!("Searching {} positions to depth {}...", positions.len(), probcut::MAX_DEPTH);
+
+    let mut samples = probcut::collect_samples(&positions, kind);
+    samples.extend(probcut::collect_endgame_samples(&positions));
+    eprintln!("{} samples collected", samples.len());
+
+    let fitted = ProbCut::fit(&samples);
+    for depth in probcut::MIN_DEPTH..=probcut::MAX_DEPTH {
+        for empties in (10..=50).step_by(20) {
+            let p = fitted.params(depth, empties);
+            eprintln!("depth {:2} (shallow {}), empties {:2}: deep = {:.3} * shallow + {:.3}, sigma = {:.3}",
+                depth, probcut::shallow_depth(depth), empties, p.slope, p.intercept, p.sigma);
+        }
+    }
+    for empties in (probcut::ENDGAME_MIN_EMPTIES as usize..=probcut::ENDGAME_FIT_EMPTIES as usize).step_by(4) {
+        let p = fitted.endgame_params(empties);
+        eprintln!("exact (shallow {}), empties {:2}: score = {:.3} * shallow + {:.3}, sigma = {:.3}",
+            probcut::ENDGAME_SHALLOW_DEPTH, empties, p.slope, p.intercept, p.sigma);
+    }
+
+    fs::write(&out, fitted.to_json()).expect("Failed to write probcut file");
+    eprintln!("Written to {}", out);
+}
+
+fn search(matches: &getopts::Matches) {
+    let board = match notation::parse_board(&matches.free[1..].join(" ")) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("search requires a position: {}", e);
+            process::exit(1);
+        }
+    };
+    let limits = SearchLimits {
+        max_depth: matches.opt_str("depth").map(|_| parse_opt(matches, "depth", 0)),
+        max_nodes: matches.opt_str("nodes").map(|_| parse_opt(matches, "nodes", 0)),
+        max_time_ms: matches.opt_str("time").map(|_| parse_opt(matches, "time", 0)),
+        solve_empties: parse_opt(matches, "solve-empties", engine::default_engine().options().solve_empties),
+    };
+
+    let (mv, stats) = board.search_with_limits(&limits, 0.0);
+    if mv < 64 {
+        println!("move: {} ({}), nodes: {}", square_name(mv as u8), mv, stats.nodes);
+    } else {
+        println!("move: pass, nodes: {}", stats.nodes);
+    }
+    println!("depth: {}/{}, evals: {}, tt: {} probes, {} hits, {} cutoffs, helper nodes: {}, solver nodes: {}, time: {}ms, nps: {}",
+        stats.depth, stats.selective_depth, stats.evals, stats.tt_probes, stats.tt_hits, stats.tt_cutoffs,
+        stats.helper_nodes, stats.solver_nodes, stats.time_ms, stats.nps);
+}
+
+fn ggf_import(matches: &getopts::Matches) {
+    let Some(path) = matches.free.get(1) else {
+        eprintln!("ggf-import requires a GGF file");
+        process::exit(1);
+    };
+    let text = fs::read_to_string(path).expect("Failed to read GGF file");
+    let results = ggf::parse_all(&text);
+    if results.is_empty() {
+        eprintln!("Invalid GGF: {}", ggf::GgfError::NoGame);
+        process::exit(1);
+    }
+    // 読めない対局(8x8以外の盤や不正な手など)は飛ばして数える
+    let mut skipped = 0;
+    for (i, result) in results.iter().enumerate() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("Game {}: skipped ({})", i + 1, e);
+                skipped += 1;
+                continue;
+            }
+        };
+        // 読むときに再生して確かめている
+        let game = record.to_game().unwrap();
+        println!("{} vs {}: {:+} ({}) {}",
+            record.black_player.as_deref().unwrap_or("?"), record.white_player.as_deref().unwrap_or("?"),
+            transcript::final_score(&game), record.result.as_deref().unwrap_or("-"), record.transcript());
+    }
+    eprintln!("{} games, {} skipped", results.len() - skipped, skipped);
+}
+
+// "1.5,,-2"のような手ごとの値(空なら書かない)
+fn parse_annotations(matches: &getopts::Matches, name: &str) -> Vec<Option<f32>> {
+    let Some(list) = matches.opt_str(name) else {
+        return Vec::new();
+    };
+    list.split(',').map(str::trim).map(|s| (!s.is_empty()).then(|| s.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value for --{}: {}", name, s);
+        process::exit(1);
+    }))).collect()
+}
+
+fn ggf_export(matches: &getopts::Matches) {
+    let start = match matches.opt_str("start") {
+        Some(s) => s.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid value for --start: {}", e);
+            process::exit(1);
+        }),
+        None => Position::initial(),
+    };
+    let game = match transcript::parse(&matches.free[1..].join("")).and_then(|moves| transcript::replay(start, &moves)) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("Invalid transcript: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut record = GgfGame {
+        date: Some(chrono::Local::now().format("%Y.%m.%d_%H:%M:%S").to_string()),
+        black_player: matches.opt_str("black"),
+        white_player: matches.opt_str("white"),
+        ..GgfGame::from_game(&game)
+    };
+    record.set_annotations(&parse_annotations(matches, "evals"), &parse_annotations(matches, "times"));
+    match matches.opt_str("out") {
+        Some(out) => {
+            fs::write(&out, format!("{}\n", record)).expect("Failed to write GGF file");
+            eprintln!("Written to {}", out);
+        }
+        None => println!("{}", record),
+    }
+}
+
+fn read_names(path: Option<String>, read: fn(File) -> Result<Vec<String>, wthor::WthorError>) -> Vec<String> {
+    let Some(path) = path else {
+        return Vec::new();
+    };
+    File::open(&path).map_err(wthor::WthorError::from).and_then(read).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+fn wthor_stats(matches: &getopts::Matches) {
+    if matches.free.len() < 2 {
+        eprintln!("wthor-stats requires WTHOR files");
+        process::exit(1);
+    }
+    let plies = parse_opt(matches, "plies", 6);
+    let players = read_names(matches.opt_str("players"), wthor::read_players);
+    let tournaments = read_names(matches.opt_str("tournaments"), wthor::read_tournaments);
+    let name = |names: &[String], i: u16| names.get(i as usize).cloned().unwrap_or_else(|| format!("#{}", i));
+
+    let mut total = 0;
+    let mut games = Vec::new();
+    for path in &matches.free[1..] {
+        let reader = wthor::open(path).unwrap_or_else(|e| {
+            eprintln!("Failed to open {}: {}", path, e);
+            process::exit(1);
+        });
+        let header = *reader.header();
+        println!("{}: {} games of {}, created {}-{:02}-{:02}", path, header.games, header.year,
+            header.created.0, header.created.1, header.created.2);
+        total += header.games as usize;
+        for (i, game) in reader.enumerate() {
+            match game.and_then(|game| game.replay().map(|g| (game, g))) {
+                Ok(game) => games.push(game),
+                Err(e) => println!("  game {}: {}", i + 1, e),
+            }
+        }
+    }
+
+    // 黒から見た結果の数(勝ち, 引き分け, 負け)と石差の合計
+    let mut results = [0; 3];
+    let mut score_sum = 0i64;
+    // 局数と石差の合計(対局者はその対局者から見た石差、それ以外は黒から見た石差)
+    let mut openings: HashMap<String, (u32, i64)> = HashMap::new();
+    let mut player_games: HashMap<u16, (u32, i64)> = HashMap::new();
+    let mut tournament_games: HashMap<u16, (u32, i64)> = HashMap::new();
+    fn add<K: std::hash::Hash + Eq>(map: &mut HashMap<K, (u32, i64)>, key: K, score: i32) {
+        let entry = map.entry(key).or_default();
+        entry.0 += 1;
+        entry.1 += score as i64;
+    }
+    for (record, game) in &games {
+        // 途中で終わった対局も記録の石数から数える
+        let score = record.score();
+        results[(1 - score.signum()) as usize] += 1;
+        score_sum += score as i64;
+        let moves: Vec<u8> = game.moves().iter().map(|r| r.mv).take(plies).collect();
+        add(&mut openings, transcript::write(&moves, true), score);
+        add(&mut player_games, record.black, score);
+        add(&mut player_games, record.white, -score);
+        add(&mut tournament_games, record.tournament, score);
+    }
+    let count = games.len().max(1) as f64;
+    println!("{} games, {} corrupt", total, total - games.len());
+    println!("black wins: {}, draws: {}, white wins: {}, average disc difference: {:+.2}",
+        results[0], results[1], results[2], score_sum as f64 / count);
+
+    // 多い順に10件
+    fn top<K: Clone + Ord>(map: &HashMap<K, (u32, i64)>) -> Vec<(K, u32, i64)> {
+        let mut entries: Vec<_> = map.iter().map(|(k, &(n, s))| (k.clone(), n, s)).collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        entries.truncate(10);
+        entries
+    }
+    println!("openings ({} plies):", plies);
+    for (opening, n, sum) in top(&openings) {
+        println!("  {:<width$} {:6} games, {:+.2}", opening, n, sum as f64 / n as f64, width = plies * 2);
+    }
+    println!("players:");
+    for (player, n, sum) in top(&player_games) {
+        println!("  {:<20} {:6} games, {:+.2}", name(&players, player), n, sum as f64 / n as f64);
+    }
+    println!("tournaments:");
+    for (tournament, n, sum) in top(&tournament_games) {
+        println!("  {:<26} {:6} games, {:+.2}", name(&tournaments, tournament), n, sum as f64 / n as f64);
+    }
+}
+
+fn build_book(matches: &getopts::Matches) {
+    if matches.free.len() < 2 {
+        eprintln!("build-book requires WTHOR files");
+        process::exit(1);
+    }
+    let plies = parse_opt(matches, "plies", 20);
+    let min_games = parse_opt(matches, "min-games", 10);
+    // 同梱の定石(src/book.bin)を黙って上書きしないよう、書き出す先は必ず指定させる
+    let Some(out) = matches.opt_str("out") else {
+        eprintln!("build-book requires --out FILE (e.g. --out {} to replace the bundled book)", database::BOOK_PATH);
+        process::exit(1);
+    };
+
+    let games: Vec<_> = read_wthor_games(&matches.free[1..]).iter().map(|game| game.positions().unwrap()).collect();
+    eprintln!("{} games loaded", games.len());
+    let book = wthor::build_book(&games, plies, min_games);
+    fs::write(&out, book.to_bytes()).expect("Failed to write book file");
+    eprintln!("Written {} positions to {}", book.len(), out);
+}
+
+fn list_options() {
+    for spec in OPTIONS.iter() {
+        println!("{}", spec);
+    }
+}
+
+fn run_protocol() {
+    let engine = engine::default_engine().duplicate();
+    protocol::run(engine, io::stdin().lock(), io::stdout().lock()).expect("Failed to read commands");
+}
+
+fn set_option(name: &str, value: &str) {
+    if let Err(e) = engine::set_default_option(name, value) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help");
+    opts.optopt("", "games", "number of self-play games", "N");
+    opts.optopt("", "random-plies", "random opening plies", "N");
+    opts.optopt("", "solve-empties", "empties to start the exact solver", "N");
+    opts.optopt("", "seed", "random seed", "N");
+    opts.optopt("o", "out", "output file", "PATH");
+    opts.optopt("", "stages", "number of stages (train-linear)", "N");
+    opts.optopt("", "epochs", "training epochs (train-linear)", "N");
+    opts.optopt("", "learning-rate", "learning rate (train-linear)", "RATE");
+    opts.optopt("", "eval", "evaluator used in self-play, fit-calibration and fit-probcut: network or linear", "KIND");
+    opts.optopt("", "positions", "number of positions to search (fit-probcut)", "N");
+    opts.optflag("", "no-probcut", "disable Multi-ProbCut (full-width search)");
+    opts.optopt("", "probcut-confidence", "Multi-ProbCut cut threshold in standard deviations", "T");
+    opts.optopt("", "depth", "maximum search depth (search)", "N");
+    opts.optopt("", "nodes", "maximum midgame nodes (search)", "N");
+    opts.optopt("", "time", "maximum search time in ms (search)", "MS");
+    opts.optopt("", "hash", "transposition table size in MB", "MB");
+    opts.optopt("", "threads", "threads for the midgame search and the endgame solver (0: all cores)", "N");
+    opts.optopt("", "black", "black player name (ggf-export)", "NAME");
+    opts.optopt("", "white", "white player name (ggf-export)", "NAME");
+    opts.optopt("", "start", "start position (ggf-export)", "POSITION");
+    opts.optopt("", "evals", "comma-separated evaluations per move including passes, empty for none (ggf-export)", "LIST");
+    opts.optopt("", "times", "comma-separated seconds per move including passes, empty for none (ggf-export)", "LIST");
+    opts.optmulti("", "wthor", "WTHOR file to take positions from (generate-dataset)", "FILE");
+    opts.optopt("", "plies", "opening length in plies (wthor-stats, build-book)", "N");
+    opts.optopt("", "min-games", "minimum games for a book move (build-book)", "N");
+    opts.optopt("", "players", "WTHOR player file (wthor-stats)", "FILE");
+    opts.optopt("", "tournaments", "WTHOR tournament file (wthor-stats)", "FILE");
+    opts.optmulti("", "option", "set an engine option (see the options command)", "NAME=VALUE");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage(&program, &opts);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    database::initialize_tables();
+    play::set_eval_kind(parse_opt(&matches, "eval", EvalKind::Network));
+    if matches.opt_present("hash") {
+        database::set_cache_size_mb(parse_opt(&matches, "hash", database::DEFAULT_CACHE_MB));
+    }
+    if matches.opt_present("no-probcut") {
+        set_option("selectivity", "false");
+    }
+    if let Some(t) = matches.opt_str("probcut-confidence") {
+        set_option("probcut_confidence", &t);
+    }
+    for option in matches.opt_strs("option") {
+        let Some((name, value)) = option.split_once('=') else {
+            eprintln!("--option requires NAME=VALUE: {}", option);
+            process::exit(1);
+        };
+        set_option(name, value);
+    }
+    if let Some(t) = matches.opt_str("threads") {
+        set_option("threads", &t);
+        set_option("solver_threads", &t);
+    }
+
+    match matches.free[0].as_str() {
+        "fit-calibration" => fit_calibration(&matches),
+        "generate-dataset" => generate_dataset(&matches),
+        "train-linear" => train_linear(&matches),
+        "fit-probcut" => fit_probcut(&matches),
+        "search" => search(&matches),
+        "ggf-import" => ggf_import(&matches),
+        "ggf-export" => ggf_export(&matches),
+        "wthor-stats" => wthor_stats(&matches),
+        "build-book" => build_book(&matches),
+        "options" => list_options(),
+        "protocol" => run_protocol(),
+        cmd => {
+            eprintln!("Unknown command: {}", cmd);
+            print_usage(&program, &opts);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+/*
+盤面の文字列の読み書き
+どの形式も64マスをa1, b1, ..., h1, a2, ..., h8の順に1文字ずつ並べる
+  Edax/OBF   X(黒) O(白) -(空き)の後に空白と手番(X/O)、";"から後(最善手や評価値)は読み飛ばす
+  B/W/.      B(黒) W(白) .(空き)、手番(B/W)は省略できる
+  X/O/-      X(手番側) O(相手) -(空き)、手番を書かなければXの手番
+読むときは形式も大文字と小文字も区別せず、手番を省略すると黒(X)の手番になる
+*/
+
+use crate::game::{square_name, Color, Position};
+use crate::play::Board;
+
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum PositionError {
+    #[error("position must have 64 squares, got {0}")]
+    WrongLength(usize),
+    #[error("invalid character '{ch}' at {square}")]
+    InvalidChar { ch: char, square: String },
+    #[error("invalid side to move: {0}")]
+    InvalidSide(String),
+    #[error("black and white discs overlap at {0}")]
+    Overlap(String),
+    // 中央の4マスから始まるので、4個未満にはならない
+    #[error("unreachable disc count: {0}")]
+    UnreachableDiscCount(u32),
+}
+
+// 書き出す形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    // X(黒) O(白) -(空き)と手番
+    Obf,
+    // B(黒) W(白) .(空き)と手番
+    BlackWhite,
+    // X(手番側) O(相手) -(空き)、手番は書かない
+    Relative,
+}
+
+// 盤面として正しいか確かめる
+pub fn validate(black: u64, white: u64) -> Result<(), PositionError> {
+    let overlap = black & white;
+    if overlap != 0 {
+        return Err(PositionError::Overlap(square_name(overlap.trailing_zeros() as u8)));
+    }
+    let discs = (black | white).count_ones();
+    if discs < 4 {
+        return Err(PositionError::UnreachableDiscCount(discs));
+    }
+    Ok(())
+}
+
+pub fn parse(s: &str) -> Result<Position, PositionError> {
+    let s = s.split(';').next().unwrap_or("");
+    let mut tokens = s.split_whitespace();
+    let squares = tokens.next().unwrap_or("");
+
+    // 空白を挟まずに手番を続けてもよい
+    let (squares, side) = match squares.chars().count() {
+        64 => (squares, tokens.next()),
+        65 => {
+            let (i, _) = squares.char_indices().last().unwrap();
+            (&squares[..i], Some(&squares[i..]))
+        }
+        n => return Err(PositionError::WrongLength(n)),
+    };
+    if let Some(extra) = tokens.next() {
+        return Err(PositionError::InvalidSide(extra.to_string()));
+    }
+
+    let mut black = 0u64;
+    let mut white = 0u64;
+    for (i, c) in squares.chars().enumerate() {
+        match c {
+            'X' | 'x' | 'B' | 'b' | '*' => black |= 1 << i,
+            'O' | 'o' | 'W' | 'w' => white |= 1 << i,
+            '-' | '.' => (),
+            _ => return Err(PositionError::InvalidChar { ch: c, square: square_name(i as u8) }),
+        }
+    }
+
+    let to_move = match side {
+        None | Some("X" | "x" | "B" | "b" | "*") => Color::Black,
+        Some("O" | "o" | "W" | "w") => Color::White,
+        Some(side) => return Err(PositionError::InvalidSide(side.to_string())),
+    };
+    Position::new(black, white, to_move)
+}
+
+pub fn format(position: &Position, notation: Notation) -> String {
+    let (first, second, empty, side) = match notation {
+        Notation::Obf => (position.black, position.white, '-', Some(if position.to_move == Color::Black { 'X' } else { 'O' })),
+        Notation::BlackWhite => (position.black, position.white, '.', Some(if position.to_move == Color::Black { 'B' } else { 'W' })),
+        Notation::Relative => {
+            let board = position.board();
+            (board.my_board, board.opponent_board, '-', None)
+        }
+    };
+    let (first_char, second_char) = if notation == Notation::BlackWhite { ('B', 'W') } else { ('X', 'O') };
+
+    let mut s: String = (0..64)
+        .map(|i| if first >> i & 1 != 0 { first_char } else if second >> i & 1 != 0 { second_char } else { empty })
+        .collect();
+    if let Some(side) = side {
+        s.push(' ');
+        s.push(side);
+    }
+    s
+}
+
+// 手番側から見た盤面を読む
+pub fn parse_board(s: &str) -> Result<Board, PositionError> {
+    parse(s).map(|position| position.board())
+}
+
+// 手番側から見た盤面をX/O/-で書く
+pub fn format_board(board: &Board) -> String {
+    format(&Position::from_board(board, Color::Black), Notation::Relative)
+}
+
+impl FromStr for Position {
+    type Err = PositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            // 重ならない石をランダムに置く(4個以上)
+            let (black, white) = loop {
+                let black: u64 = rng.gen::<u64>() & rng.gen::<u64>();
+                let white: u64 = rng.gen::<u64>() & !black;
+                if (black | white).count_ones() >= 4 {
+                    break (black, white);
+                }
+            };
+            let to_move = if rng.gen() { Color::Black } else { Color::White };
+            let position = Position { black, white, to_move };
+
+            for notation in [Notation::Obf, Notation::BlackWhite] {
+                let s = format(&position, notation);
+                assert_eq!(parse(&s), Ok(position), "{}", s);
+                // 手番を続けて書いても、小文字で書いても同じ
+                assert_eq!(parse(&s.replace(' ', "")), Ok(position), "{}", s);
+                assert_eq!(parse(&s.to_lowercase()), Ok(position), "{}", s);
+            }
+            // 手番を書かない形式は手番側から見た盤面が同じになる
+            let s = format(&position, Notation::Relative);
+            assert!(parse_board(&s).unwrap() == position.board(), "{}", s);
+            assert_eq!(format_board(&position.board()), s);
+            // B/W/.の盤面だけ(Display)なら黒の手番
+            assert_eq!(position.to_string().parse(), Ok(Position { to_move: Color::Black, ..position }));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let start = "---------------------------OX------XO---------------------------";
+        assert_eq!(parse(&format!("{} O ; e6:+0", start)).map(|p| p.to_move), Ok(Color::White));
+        // 小文字のb/wもx/oと同じく読む
+        let lower = start.replace('-', ".").replace('O', "w").replace('X', "b");
+        assert_eq!(parse(&format!("{} w", lower)), parse(&format!("{} O", start)));
+        assert_eq!(parse(&start[1..]), Err(PositionError::WrongLength(63)));
+        assert_eq!(parse(&format!("{}--", start)), Err(PositionError::WrongLength(66)));
+        assert_eq!(parse(&start.replacen('-', "?", 1)), Err(PositionError::InvalidChar { ch: '?', square: "a1".to_string() }));
+        assert_eq!(parse(&format!("{} Z", start)), Err(PositionError::InvalidSide("Z".to_string())));
+        assert_eq!(parse(&format!("{} X X", start)), Err(PositionError::InvalidSide("X".to_string())));
+        assert_eq!(parse(&"-".repeat(64)), Err(PositionError::UnreachableDiscCount(0)));
+        assert_eq!(Position::new(1, 0xf, Color::Black), Err(PositionError::Overlap("a1".to_string())));
+        assert_eq!(PositionError::WrongLength(63).to_string(), "position must have 64 squares, got 63");
+    }
+}
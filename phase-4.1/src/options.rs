@@ -0,0 +1,290 @@
+/*
+エンジンの設定
+名前・型・既定値・範囲・説明を一覧(OPTIONS)にまとめ、WebAssembly版・ツール・テキストプロトコルから同じ名前で設定する
+UIは一覧から設定画面を組み立てられる
+*/
+
+use crate::database::{DEFAULT_CACHE_MB, MAX_CACHE_MB};
+use crate::play::{EvalKind, DEFAULT_SOLVE_EMPTIES};
+use crate::probcut::DEFAULT_CONFIDENCE;
+
+use serde::{Serialize, Deserialize};
+use std::fmt::{self, Display, Formatter};
+
+// 設定の型と範囲
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OptionType {
+    Check,
+    Spin { min: i64, max: i64 },
+    Float { min: f64, max: f64 },
+    Combo { values: &'static [&'static str] },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Display for OptionValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OptionValue::Bool(b) => write!(f, "{}", b),
+            OptionValue::Int(n) => write!(f, "{}", n),
+            OptionValue::Float(x) => write!(f, "{}", x),
+            OptionValue::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OptionSpec {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: OptionType,
+    pub default: OptionValue,
+    pub description: &'static str,
+}
+
+impl OptionSpec {
+    // 文字列を型と範囲を確かめて値にする
+    pub fn parse(&self, s: &str) -> Result<OptionValue, String> {
+        let invalid = || format!("Invalid value for {}: {}", self.name, s);
+        match self.kind {
+            OptionType::Check => match s {
+                "true" | "on" | "1" => Ok(OptionValue::Bool(true)),
+                "false" | "off" | "0" => Ok(OptionValue::Bool(false)),
+                _ => Err(invalid()),
+            },
+            OptionType::Spin { min, max } => {
+                let n: i64 = s.parse().map_err(|_| invalid())?;
+                if n < min || n > max {
+                    return Err(format!("{} must be in {}..={}: {}", self.name, min, max, n));
+                }
+                Ok(OptionValue::Int(n))
+            }
+            OptionType::Float { min, max } => {
+                let x: f64 = s.parse().map_err(|_| invalid())?;
+                if !(min..=max).contains(&x) {
+                    return Err(format!("{} must be in {}..={}: {}", self.name, min, max, x));
+                }
+                Ok(OptionValue::Float(x))
+            }
+            OptionType::Combo { values } => {
+                if values.contains(&s) {
+                    Ok(OptionValue::Str(s.to_string()))
+                } else {
+                    Err(format!("{} must be one of {}: {}", self.name, values.join("/"), s))
+                }
+            }
+        }
+    }
+}
+
+// テキストプロトコルとツールの一覧表示の1行
+impl Display for OptionSpec {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "option name {} ", self.name)?;
+        match self.kind {
+            OptionType::Check => write!(f, "type check")?,
+            OptionType::Spin { min, max } => write!(f, "type spin min {} max {}", min, max)?,
+            OptionType::Float { min, max } => write!(f, "type float min {} max {}", min, max)?,
+            OptionType::Combo { values } => write!(f, "type combo values {}", values.join(" "))?,
+        }
+        write!(f, " default {} -- {}", self.default, self.description)
+    }
+}
+
+const EVALUATORS: &[&str] = &["network", "linear"];
+
+pub static OPTIONS: std::sync::LazyLock<Vec<OptionSpec>> = std::sync::LazyLock::new(|| {
+    let defaults = EngineOptions::default();
+    vec![
+        OptionSpec {
+            name: "hash",
+            kind: OptionType::Spin { min: 1, max: MAX_CACHE_MB as i64 },
+            default: OptionValue::Int(defaults.hash_mb as i64),
+            description: "transposition table size in MB (clears the table)",
+        },
+        OptionSpec {
+            name: "evaluator",
+            kind: OptionType::Combo { values: EVALUATORS },
+            default: OptionValue::Str("network".to_string()),
+            description: "evaluation function: network (accurate) or linear (fast)",
+        },
+        OptionSpec {
+            name: "max_depth",
+            kind: OptionType::Spin { min: 1, max: 60 },
+            default: OptionValue::Int(defaults.max_depth as i64),
+            description: "maximum depth of the iterative deepening",
+        },
+        OptionSpec {
+            name: "solve_empties",
+            kind: OptionType::Spin { min: 0, max: 30 },
+            default: OptionValue::Int(defaults.solve_empties as i64),
+            description: "solve the endgame when this many squares or fewer are empty",
+        },
+        OptionSpec {
+            name: "solver_time_divisor",
+            kind: OptionType::Spin { min: 1, max: 1000 },
+            default: OptionValue::Int(defaults.solver_time_divisor as i64),
+            description: "when the solver runs out of time or nodes, search with the assigned time and nodes divided by this",
+        },
+        OptionSpec {
+            name: "book_disturbance",
+            kind: OptionType::Float { min: 0.0, max: 10000.0 },
+            default: OptionValue::Float(defaults.book_disturbance as f64),
+            description: "use the opening book and the solver only when the disturbance is at most this",
+        },
+        OptionSpec {
+            name: "selectivity",
+            kind: OptionType::Check,
+            default: OptionValue::Bool(defaults.selectivity),
+            description: "prune with Multi-ProbCut (off: full-width search for analysis)",
+        },
+        OptionSpec {
+            name: "probcut_confidence",
+            kind: OptionType::Float { min: 0.0, max: 10.0 },
+            default: OptionValue::Float(defaults.probcut_confidence as f64),
+            description: "Multi-ProbCut cut threshold in standard deviations (larger is safer)",
+        },
+        OptionSpec {
+            name: "threads",
+            kind: OptionType::Spin { min: 0, max: 256 },
+            default: OptionValue::Int(defaults.threads as i64),
+            description: "threads for the Lazy SMP midgame search (0: all cores, ignored on WebAssembly)",
+        },
+        OptionSpec {
+            name: "solver_threads",
+            kind: OptionType::Spin { min: 0, max: 256 },
+            default: OptionValue::Int(defaults.solver_threads as i64),
+            description: "threads for the endgame solver split at the root (0: all cores, ignored on WebAssembly)",
+        },
+    ]
+});
+
+pub fn find(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|spec| spec.name == name)
+}
+
+// スレッド数の設定(0なら利用できるコア数)から実際に使うスレッド数へ
+// WebAssembly版はスレッドを使わない
+pub fn thread_count(setting: usize) -> usize {
+    if cfg!(target_arch = "wasm32") {
+        return 1;
+    }
+    match setting {
+        0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    }
+}
+
+// 設定の値(エンジンごとに持つ)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EngineOptions {
+    pub hash_mb: usize,
+    pub evaluator: EvalKind,
+    pub max_depth: u8,
+    pub solve_empties: u8,
+    pub solver_time_divisor: u64,
+    // これ以下の乱れなら定石と読み切りを使う(弱いレベルでは使わない)
+    pub book_disturbance: f32,
+    pub selectivity: bool,
+    pub probcut_confidence: f32,
+    // 中盤探索のスレッド数(0なら利用できるコア数)
+    // Lazy SMPが複数コアで速くなることを測れていないので、既定は1
+    pub threads: usize,
+    // 完全読みのスレッド数(0なら利用できるコア数)、複数コアで速くなることを測れていないので既定は1
+    pub solver_threads: usize,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            hash_mb: DEFAULT_CACHE_MB,
+            evaluator: EvalKind::Network,
+            max_depth: 60,
+            solve_empties: DEFAULT_SOLVE_EMPTIES,
+            solver_time_divisor: 12,
+            book_disturbance: 10.0,
+            selectivity: true,
+            probcut_confidence: DEFAULT_CONFIDENCE,
+            threads: 1,
+            solver_threads: 1,
+        }
+    }
+}
+
+impl EngineOptions {
+    // 名前と文字列で設定する
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let spec = find(name).ok_or_else(|| format!("Unknown option: {}", name))?;
+        match (spec.name, spec.parse(value)?) {
+            ("hash", OptionValue::Int(n)) => self.hash_mb = n as usize,
+            ("evaluator", OptionValue::Str(s)) => self.evaluator = s.parse()?,
+            ("max_depth", OptionValue::Int(n)) => self.max_depth = n as u8,
+            ("solve_empties", OptionValue::Int(n)) => self.solve_empties = n as u8,
+            ("solver_time_divisor", OptionValue::Int(n)) => self.solver_time_divisor = n as u64,
+            ("book_disturbance", OptionValue::Float(x)) => self.book_disturbance = x as f32,
+            ("selectivity", OptionValue::Bool(b)) => self.selectivity = b,
+            ("probcut_confidence", OptionValue::Float(x)) => self.probcut_confidence = x as f32,
+            ("threads", OptionValue::Int(n)) => self.threads = n as usize,
+            ("solver_threads", OptionValue::Int(n)) => self.solver_threads = n as usize,
+            _ => unreachable!("option {} is not handled", name),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<OptionValue> {
+        Some(match name {
+            "hash" => OptionValue::Int(self.hash_mb as i64),
+            "evaluator" => OptionValue::Str(EVALUATORS[(self.evaluator == EvalKind::Linear) as usize].to_string()),
+            "max_depth" => OptionValue::Int(self.max_depth as i64),
+            "solve_empties" => OptionValue::Int(self.solve_empties as i64),
+            "solver_time_divisor" => OptionValue::Int(self.solver_time_divisor as i64),
+            "book_disturbance" => OptionValue::Float(self.book_disturbance as f64),
+            "selectivity" => OptionValue::Bool(self.selectivity),
+            "probcut_confidence" => OptionValue::Float(self.probcut_confidence as f64),
+            "threads" => OptionValue::Int(self.threads as i64),
+            "solver_threads" => OptionValue::Int(self.solver_threads as i64),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let mut options = EngineOptions::default();
+        // 一覧の既定値と構造体の既定値は一致し、一覧の全ての設定を読み書きできる
+        for spec in OPTIONS.iter() {
+            assert_eq!(options.get(spec.name).as_ref(), Some(&spec.default), "{}", spec.name);
+            options.set(spec.name, &spec.default.to_string()).unwrap();
+        }
+        assert_eq!(options, EngineOptions::default());
+
+        options.set("max_depth", "12").unwrap();
+        options.set("evaluator", "linear").unwrap();
+        options.set("selectivity", "off").unwrap();
+        options.set("probcut_confidence", "2.5").unwrap();
+        options.set("solver_threads", "0").unwrap();
+        assert_eq!(options.solver_threads, 0);
+        assert!(thread_count(options.solver_threads) >= 1);
+        assert_eq!((options.max_depth, options.evaluator, options.selectivity, options.probcut_confidence), (12, EvalKind::Linear, false, 2.5));
+
+        assert!(options.set("max_depth", "61").is_err());
+        assert!(options.set("max_depth", "deep").is_err());
+        assert!(options.set("evaluator", "random").is_err());
+        assert!(options.set("no_such_option", "1").is_err());
+        assert_eq!(options.max_depth, 12);
+
+        assert_eq!(find("max_depth").unwrap().to_string(), "option name max_depth type spin min 1 max 60 default 60 -- maximum depth of the iterative deepening");
+    }
+}
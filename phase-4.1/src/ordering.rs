@@ -0,0 +1,181 @@
+/*
+中盤探索の手の並び替え
+置換表の最善手、子局面の置換表の値、キラー手(手数ごとにβカットを起こした手)、
+ヒストリー(手番とマスごとのβカットの実績)、着手可能数と隅を見た静的な優先度の順に重視する
+置換表と違い探索スレッドごとに持つ
+*/
+
+use crate::play::Board;
+use serde::{Serialize, Deserialize};
+
+// この深さ以上では、置換表に値のない子局面を浅く読んでから並べる
+pub const SHALLOW_ORDERING_DEPTH: u8 = 5;
+
+// 優先度の段階、上の段階ほど先に読む
+const TT_MOVE_SCORE: i32 = 1 << 30;
+const CHILD_VALUE_SCORE: i32 = 1 << 24;
+const KILLER_SCORE: [i32; 2] = [1 << 22, 1 << 21];
+// ヒストリーがこれを超えたら半分にして、古い実績の影響を減らす
+const HISTORY_MAX: u32 = 1 << 16;
+
+const CORNER_SCORE: i32 = 1 << 13;
+const X_SQUARE_SCORE: i32 = -(1 << 13);
+const MOBILITY_SCORE: i32 = -(1 << 10);
+
+const CORNERS: u64 = 0x8100000000000081;
+// (Xマス, 隣の隅)
+const X_SQUARES: [(u8, u8); 4] = [(9, 0), (14, 7), (49, 56), (54, 63)];
+
+// 並び替えの質の統計
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderingStats {
+    // βカットしたノード数
+    pub cutoffs: u64,
+    // そのうち最初に読んだ手でカットしたノード数
+    pub first_move_cutoffs: u64,
+}
+
+impl OrderingStats {
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        if self.cutoffs == 0 {
+            0.0
+        } else {
+            self.first_move_cutoffs as f64 / self.cutoffs as f64
+        }
+    }
+}
+
+pub struct MoveOrdering {
+    // [石数][0: 最新, 1: その前]
+    killers: [[u8; 2]; 65],
+    // [手番][マス]
+    history: [[u32; 64]; 2],
+    stats: OrderingStats,
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveOrdering {
+    pub fn new() -> Self {
+        Self {
+            killers: [[u8::MAX; 2]; 65],
+            history: [[0; 64]; 2],
+            stats: OrderingStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> OrderingStats {
+        self.stats
+    }
+
+    // 盤面は手番の色を持たないので石数の偶奇で代える(パスがあるとずれるが並び替えには十分)
+    fn side(board: &Board) -> usize {
+        board.get_turn() & 1
+    }
+
+    // boardでmvを打ってchildになる手の優先度(大きいほど先に読む)
+    // child_valueは子局面の置換表の値(子の手番側から見た値)
+    pub fn score(&self, board: &Board, child: &Board, mv: u8, tt_move: u8, child_value: Option<f32>) -> i32 {
+        let mut score = 0;
+        if mv == tt_move {
+            score += TT_MOVE_SCORE;
+        }
+        if let Some(value) = child_value {
+            score += CHILD_VALUE_SCORE - (value.clamp(-2000.0, 2000.0) * 256.0) as i32;
+        }
+        let killers = &self.killers[board.get_turn()];
+        if let Some(i) = killers.iter().position(|&k| k == mv) {
+            score += KILLER_SCORE[i];
+        }
+        score += self.history[Self::side(board)][mv as usize] as i32;
+        score + static_score(board, child, mv)
+    }
+
+    // mvでβカットした(indexは読んだ順番)
+    pub fn record_cutoff(&mut self, board: &Board, mv: u8, depth: u8, index: usize) {
+        self.stats.cutoffs += 1;
+        if index == 0 {
+            self.stats.first_move_cutoffs += 1;
+        }
+        if mv >= 64 {
+            return;
+        }
+
+        let killers = &mut self.killers[board.get_turn()];
+        if killers[0] != mv {
+            killers[1] = killers[0];
+            killers[0] = mv;
+        }
+
+        let history = &mut self.history[Self::side(board)];
+        history[mv as usize] += depth as u32 * depth as u32;
+        if history[mv as usize] > HISTORY_MAX {
+            history.iter_mut().for_each(|h| *h /= 2);
+        }
+    }
+}
+
+// 相手の着手可能数が少なく、隅を取り、空いた隅の隣のXマスを避ける手を優先する
+fn static_score(board: &Board, child: &Board, mv: u8) -> i32 {
+    let mut score = child.get_valid_moves().count_ones() as i32 * MOBILITY_SCORE;
+    if (CORNERS >> mv) & 1 != 0 {
+        score += CORNER_SCORE;
+    }
+    let occupied = board.my_board | board.opponent_board;
+    for &(x, corner) in X_SQUARES.iter() {
+        if mv == x && (occupied >> corner) & 1 == 0 {
+            score += X_SQUARE_SCORE;
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordering() {
+        let board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let child = |mv: u8| {
+            let mut t = board.clone();
+            t.do_move(mv);
+            t.change_turn();
+            t
+        };
+        let mut ordering = MoveOrdering::new();
+
+        // 初期局面の4手は対称なので、置換表の手・子局面の値・キラー手の順に優先される
+        let base = ordering.score(&board, &child(19), 19, 255, None);
+        assert_eq!(base, ordering.score(&board, &child(26), 26, 255, None));
+        assert!(ordering.score(&board, &child(19), 19, 19, None) > ordering.score(&board, &child(26), 26, 255, Some(-50.0)));
+        assert!(ordering.score(&board, &child(26), 26, 255, Some(-50.0)) > ordering.score(&board, &child(37), 37, 255, Some(50.0)));
+
+        ordering.record_cutoff(&board, 37, 4, 1);
+        ordering.record_cutoff(&board, 44, 4, 0);
+        assert!(ordering.score(&board, &child(44), 44, 255, None) > ordering.score(&board, &child(37), 37, 255, None));
+        assert!(ordering.score(&board, &child(37), 37, 255, None) > base);
+        assert_eq!(ordering.stats(), OrderingStats { cutoffs: 2, first_move_cutoffs: 1 });
+        assert_eq!(ordering.stats().first_move_cutoff_rate(), 0.5);
+
+        // ヒストリーは上限を超えると半分になる
+        for _ in 0..2000 {
+            ordering.record_cutoff(&board, 19, 8, 0);
+        }
+        assert!(ordering.history[MoveOrdering::side(&board)][19] <= HISTORY_MAX);
+    }
+}
+
+/*
+bench_lazy_smp の局面(1スレッド、線形評価関数、深さ11)でのノード数と最初の手でのβカット率
+子局面の置換表の値だけで並べた場合 / この並び替え:
+8040201c8c100000 000058407060e060:  17441 (62.7%) /  10612 (82.5%)
+0000013678301010 0001120806422c00: 187070 (57.9%) /  59131 (73.6%)
+101010183d1a0000 0000640000242c54:  73690 (63.2%) /  87905 (76.5%)
+00241808001a0000 004021363e040c04: 957647 (45.3%) / 500045 (68.8%)
+浅い探索での並び替えは反復深化で子局面の値がほぼ置換表にあるため、ノード数はほとんど変わらない(3局面目で94382 -> 87905)
+*/
@@ -0,0 +1,101 @@
+/*
+空きマスの偶奇(パリティ)の解析
+終盤では空きマスがつながった領域ごとに、奇数個の領域に先に打つ(最後の1マスを取る)方が有利になりやすい
+完全読みの手の並び替え、線形評価関数の特徴量、表示用の解析APIで使う
+*/
+
+use crate::play::Board;
+use serde::{Serialize, Deserialize};
+
+const FILE_A: u64 = 0x0101010101010101;
+const FILE_H: u64 = 0x8080808080808080;
+
+// 4つの象限(左上, 右上, 左下, 右下)
+pub const QUADRANT_MASK: [u64; 4] = [
+    0x000000000f0f0f0f,
+    0x00000000f0f0f0f0,
+    0x0f0f0f0f00000000,
+    0xf0f0f0f000000000,
+];
+
+// 8近傍に広げる
+fn expand(b: u64) -> u64 {
+    let horizontal = b | ((b << 1) & !FILE_A) | ((b >> 1) & !FILE_H);
+    horizontal | (horizontal << 8) | (horizontal >> 8)
+}
+
+// 空きマスを8近傍でつながった領域に分ける
+pub fn get_regions(empties: u64) -> Vec<u64> {
+    let mut regions = Vec::new();
+    let mut left = empties;
+    while left != 0 {
+        let mut region = left & left.wrapping_neg();
+        loop {
+            let next = expand(region) & empties;
+            if next == region {
+                break;
+            }
+            region = next;
+        }
+        regions.push(region);
+        left &= !region;
+    }
+    regions
+}
+
+// 空きマスが奇数個の領域に属するマス
+pub fn odd_region_squares(empties: u64) -> u64 {
+    get_regions(empties)
+        .into_iter()
+        .filter(|r| r.count_ones() % 2 == 1)
+        .fold(0, |acc, r| acc | r)
+}
+
+// 空きマスが奇数個の象限に属するマス
+// 領域を求めるより軽いので、残りが少ない局面の並び替えに使う
+pub fn odd_quadrant_squares(empties: u64) -> u64 {
+    QUADRANT_MASK.iter()
+        .filter(|&&q| (empties & q).count_ones() % 2 == 1)
+        .fold(0, |acc, &q| acc | (empties & q))
+}
+
+// 表示用の領域情報
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParityRegion {
+    pub squares: Vec<u8>,
+    pub odd: bool,
+}
+
+pub fn analyze(board: &Board) -> Vec<ParityRegion> {
+    get_regions(!(board.my_board | board.opponent_board))
+        .into_iter()
+        .map(|r| ParityRegion {
+            squares: (0..64).filter(|&i| (r >> i) & 1 != 0).collect(),
+            odd: r.count_ones() % 2 == 1,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        // 左上の隅2マスと、右下の隅3マス(端をまたいでつながらない)
+        let empties = 0x0000000000000003 | 0xc080000000000000;
+        let regions = get_regions(empties);
+        assert_eq!(regions, vec![0x0000000000000003, 0xc080000000000000]);
+        assert_eq!(odd_region_squares(empties), 0xc080000000000000);
+        assert_eq!(odd_quadrant_squares(empties), 0xc080000000000000);
+
+        // 斜めでもつながる、行の端から次の行の先頭へはつながらない
+        assert_eq!(get_regions(0x0000000000000201).len(), 1);
+        assert_eq!(get_regions(0x0000000000000180).len(), 2);
+
+        let board = Board { my_board: !empties, opponent_board: 0 };
+        let regions = analyze(&board);
+        assert_eq!(regions[0], ParityRegion { squares: vec![0, 1], odd: false });
+        assert_eq!(regions[1], ParityRegion { squares: vec![55, 62, 63], odd: true });
+    }
+}
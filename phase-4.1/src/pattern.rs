@@ -0,0 +1,316 @@
+/*
+評価関数が使うパターン(盤面上のマスの並び)と、その3進インデックスの計算
+ニューラルネットワーク(eval.rs, eval_wasm.rs)と線形評価関数(linear_eval.rs)で共有する
+インデックスは自分の石=1, 相手の石=2, 空=0 とし、パターンの先頭のマスを最下位の桁とする
+*/
+
+use crate::play::Board;
+use std::sync::LazyLock;
+
+// 4つずつ、盤面の対称変換で互いに移り合うパターンのグループになっている
+pub static PATTERNS: &[&[u8]] = &[
+    // Group 0: edge + 2X
+    &[0, 1, 2, 3, 4, 5, 6, 7, 9, 14],
+    &[0, 8, 9, 16, 24, 32, 40, 48, 49, 56],
+    &[7, 14, 15, 23, 31, 39, 47, 54, 55, 63],
+    &[49, 54, 56, 57, 58, 59, 60, 61, 62, 63],
+
+    // Group 1: hor2
+    &[8, 9, 10, 11, 12, 13, 14, 15],
+    &[48, 49, 50, 51, 52, 53, 54, 55],
+    &[1, 9, 17, 25, 33, 41, 49, 57],
+    &[6, 14, 22, 30, 38, 46, 54, 62],
+
+    // Group 2: hor3
+    &[16, 17, 18, 19, 20, 21, 22, 23],
+    &[40, 41, 42, 43, 44, 45, 46, 47],
+    &[2, 10, 18, 26, 34, 42, 50, 58],
+    &[5, 13, 21, 29, 37, 45, 53, 61],
+
+    // Group 3: hor4
+    &[24, 25, 26, 27, 28, 29, 30, 31],
+    &[32, 33, 34, 35, 36, 37, 38, 39],
+    &[3, 11, 19, 27, 35, 43, 51, 59],
+    &[4, 12, 20, 28, 36, 44, 52, 60],
+
+    // Group 4: diag4 corner
+    &[0, 1, 2, 3, 8, 9, 10, 16, 17, 24],
+    &[4, 5, 6, 7, 13, 14, 15, 22, 23, 31],
+    &[32, 40, 41, 48, 49, 50, 56, 57, 58, 59],
+    &[39, 46, 47, 53, 54, 55, 60, 61, 62, 63],
+
+    // Group 5: diag5 + 3X
+    &[4, 9, 11, 14, 18, 25, 32, 49],
+    &[14, 31, 38, 45, 49, 52, 54, 59],
+    &[3, 9, 12, 14, 21, 30, 39, 54],
+    &[9, 24, 33, 42, 49, 51, 54, 60],
+
+    // Group 6: diag6-C-corner
+    &[5, 6, 7, 12, 19, 26, 33, 40, 48, 56],
+    &[7, 15, 23, 30, 37, 44, 51, 56, 57, 58],
+    &[0, 1, 2, 11, 20, 29, 38, 47, 55, 63],
+    &[0, 8, 16, 25, 34, 43, 52, 61, 62, 63],
+
+    // Group 7: diag7-corner
+    &[6, 7, 13, 20, 27, 34, 41, 48, 56],
+    &[7, 15, 22, 29, 36, 43, 50, 56, 57],
+    &[0, 1, 10, 19, 28, 37, 46, 55, 63],
+    &[0, 8, 17, 26, 35, 44, 53, 62, 63],
+
+    // Group 8: diag8 + 2C
+    &[0, 1, 8, 9, 18, 27, 36, 45, 54, 63],
+    &[0, 9, 18, 27, 36, 45, 54, 55, 62, 63],
+    &[6, 7, 14, 15, 21, 28, 35, 42, 49, 56],
+    &[7, 14, 21, 28, 35, 42, 48, 49, 56, 57],
+
+    // Group 9: 33-corner
+    &[0, 1, 2, 8, 9, 10, 16, 17, 18],
+    &[5, 6, 7, 13, 14, 15, 21, 22, 23],
+    &[40, 41, 42, 48, 49, 50, 56, 57, 58],
+    &[45, 46, 47, 53, 54, 55, 61, 62, 63],
+
+    // Group 10: wing-corner
+    &[0, 1, 2, 3, 4, 8, 9, 16, 24, 32],
+    &[3, 4, 5, 6, 7, 14, 15, 23, 31, 39],
+    &[24, 32, 40, 48, 49, 56, 57, 58, 59, 60],
+    &[31, 39, 47, 54, 55, 59, 60, 61, 62, 63],
+
+    // Group 11: 24-midedge + corner
+    &[0, 2, 3, 4, 5, 7, 10, 11, 12, 13],
+    &[0, 16, 17, 24, 25, 32, 33, 40, 41, 56],
+    &[7, 22, 23, 30, 31, 38, 39, 46, 47, 63],
+    &[50, 51, 52, 53, 56, 58, 59, 60, 61, 63],
+
+    // Group 12: flint
+    &[0, 1, 8, 9, 10, 11, 17, 18, 25, 27],
+    &[6, 7, 12, 13, 14, 15, 21, 22, 28, 30],
+    &[33, 35, 41, 42, 48, 49, 50, 51, 56, 57],
+    &[36, 38, 45, 46, 52, 53, 54, 55, 62, 63]
+];
+
+pub const PATTERN_MASK: [u64; 52] = [
+    0x42ff,
+    0x103010101010301,
+    0x80c080808080c080,
+    0xff42000000000000,
+    0xff00,
+    0xff000000000000,
+    0x202020202020202,
+    0x4040404040404040,
+    0xff0000,
+    0xff0000000000,
+    0x404040404040404,
+    0x2020202020202020,
+    0xff000000,
+    0xff00000000,
+    0x808080808080808,
+    0x1010101010101010,
+    0x103070f,
+    0x80c0e0f0,
+    0xf07030100000000,
+    0xf0e0c08000000000,
+    0x2000102044a10,
+    0x852204080004000,
+    0x40008040205208,
+    0x104a040201000200,
+    0x1010102040810e0,
+    0x708102040808080,
+    0x8080804020100807,
+    0xe010080402010101,
+    0x1010204081020c0,
+    0x304081020408080,
+    0x8080402010080403,
+    0xc020100804020101,
+    0x8040201008040303,
+    0xc0c0201008040201,
+    0x10204081020c0c0,
+    0x303040810204080,
+    0x70707,
+    0xe0e0e0,
+    0x707070000000000,
+    0xe0e0e00000000000,
+    0x10101031f,
+    0x808080c0f8,
+    0x1f03010101000000,
+    0xf8c0808080000000,
+    0x3cbd,
+    0x100030303030001,
+    0x8000c0c0c0c00080,
+    0xbd3c000000000000,
+    0xa060f03,
+    0x5060f0c0,
+    0x30f060a00000000,
+    0xc0f0605000000000,
+];
+
+pub const PATTERN_GROUP_SIZE: usize = 4;
+pub const PATTERN_GROUP_COUNT: usize = 13;
+
+// ビット操作用のルックアップテーブル
+// パターン長ごとに (自分のビット << 長さ) | 相手のビット -> 3進インデックス
+static BIT_TO_TERNARY_TABLES: LazyLock<Vec<Vec<u32>>> = LazyLock::new(|| {
+    let max_len = PATTERNS.iter().map(|p| p.len()).max().unwrap_or(0);
+    let mut tables = vec![Vec::new(); max_len + 1];
+
+    for pattern_len in PATTERNS.iter().map(|p| p.len()) {
+        if !tables[pattern_len].is_empty() {
+            continue;
+        }
+        let mut conversion_table = vec![0u32; 1usize << (pattern_len * 2)];
+
+        // 3進インデックスの各桁から my_bits と opp_bits を復元して埋める
+        for ternary_index in 0..3u32.pow(pattern_len as u32) {
+            let (mut my_bits, mut opp_bits) = (0usize, 0usize);
+            let mut rest = ternary_index;
+            for i in 0..pattern_len {
+                match rest % 3 {
+                    1 => my_bits |= 1 << i,  // 自分の石
+                    2 => opp_bits |= 1 << i, // 相手の石
+                    _ => {}                  // 空
+                }
+                rest /= 3;
+            }
+            conversion_table[(my_bits << pattern_len) | opp_bits] = ternary_index;
+        }
+        tables[pattern_len] = conversion_table;
+    }
+
+    tables
+});
+
+// パターンの3進インデックスを計算する
+// maskはパターンのマスの集合(PATTERN_MASK)
+#[inline]
+pub fn ternary_index(board: &Board, pattern: &[u8], mask: u64) -> usize {
+    let my_masked = board.my_board & mask;
+    let opp_masked = board.opponent_board & mask;
+
+    // パターン内の位置にビットを圧縮
+    let mut my_compressed = 0usize;
+    let mut opp_compressed = 0usize;
+    for (i, &pos) in pattern.iter().enumerate() {
+        my_compressed |= ((my_masked >> pos) & 1) as usize * (1 << i);
+        opp_compressed |= ((opp_masked >> pos) & 1) as usize * (1 << i);
+    }
+
+    BIT_TO_TERNARY_TABLES[pattern.len()][(my_compressed << pattern.len()) | opp_compressed] as usize
+}
+
+// 全パターンのインデックス(ニューラルネットワークの入力)
+pub fn pattern_indices(board: &Board) -> Vec<i64> {
+    PATTERNS.iter()
+        .zip(PATTERN_MASK.iter())
+        .map(|(pattern, &mask)| ternary_index(board, pattern, mask) as i64)
+        .collect()
+}
+
+// 対称変換(回転4通り x 反転)でマスを移す
+fn transform_square(symmetry: usize, pos: u8) -> u8 {
+    let (mut x, mut y) = (pos % 8, pos / 8);
+    for _ in 0..symmetry % 4 {
+        (x, y) = (7 - y, x);
+    }
+    if symmetry >= 4 {
+        x = 7 - x;
+    }
+    y * 8 + x
+}
+
+// マスの並びをグループの先頭パターンに揃えたパターン
+// 同じグループのパターンは同じインデックスが対称な形を表すので、重みを共有できる
+pub static ALIGNED_PATTERNS: LazyLock<Vec<Vec<u8>>> = LazyLock::new(|| {
+    PATTERNS.iter().enumerate().map(|(pattern_id, pattern)| {
+        let base = PATTERNS[pattern_id / PATTERN_GROUP_SIZE * PATTERN_GROUP_SIZE];
+        let symmetry = (0..8)
+            .find(|&s| {
+                let mut moved: Vec<u8> = pattern.iter().map(|&pos| transform_square(s, pos)).collect();
+                let mut expected = base.to_vec();
+                moved.sort_unstable();
+                expected.sort_unstable();
+                moved == expected
+            })
+            .expect("Pattern is not symmetric to its group");
+        base.iter()
+            .map(|&b| *pattern.iter().find(|&&pos| transform_square(symmetry, pos) == b).unwrap())
+            .collect()
+    }).collect()
+});
+
+// グループの先頭パターンを自分自身に移す対称変換(直線パターンの左右反転など)で
+// 同じ形になるインデックスのうち最小のもの
+// 対称な形の重みを揃えるのに使う
+pub fn canonical_indices(group: usize) -> Vec<u32> {
+    let base = PATTERNS[group * PATTERN_GROUP_SIZE];
+    // 対称変換ごとの桁の移り先
+    let perms: Vec<Vec<u32>> = (1..8)
+        .filter_map(|s| {
+            base.iter()
+                .map(|&pos| base.iter().position(|&b| b == transform_square(s, pos)).map(|j| 3u32.pow(j as u32)))
+                .collect()
+        })
+        .collect();
+
+    (0..3u32.pow(base.len() as u32)).map(|index| {
+        perms.iter().map(|perm| {
+            let mut rest = index;
+            perm.iter().map(|&power| {
+                let digit = rest % 3;
+                rest /= 3;
+                digit * power
+            }).sum()
+        }).fold(index, u32::min)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_indices() {
+        for (pattern, &mask) in PATTERNS.iter().zip(PATTERN_MASK.iter()) {
+            assert_eq!(pattern.iter().fold(0u64, |m, &pos| m | 1 << pos), mask);
+        }
+
+        let board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let indices = pattern_indices(&board);
+        for (pattern, &index) in PATTERNS.iter().zip(indices.iter()) {
+            let mut expected = 0;
+            for &pos in pattern.iter().rev() {
+                expected = expected * 3 + if (board.my_board >> pos) & 1 != 0 {
+                    1
+                } else if (board.opponent_board >> pos) & 1 != 0 {
+                    2
+                } else {
+                    0
+                };
+            }
+            assert_eq!(index, expected);
+        }
+
+        // 揃えたパターンでは、対称な盤面でもグループ内の(対称形をまとめた)インデックスの組が変わらない
+        let mut board = board;
+        board.do_move(board.get_valid_moves().trailing_zeros() as u8);
+        board.change_turn();
+        let transform_board = |bits: u64, symmetry: usize| {
+            (0..64u8).filter(|&p| (bits >> p) & 1 != 0).fold(0u64, |b, p| b | 1 << transform_square(symmetry, p))
+        };
+        let canonical: Vec<Vec<u32>> = (0..PATTERN_GROUP_COUNT).map(canonical_indices).collect();
+        let group_indices = |board: &Board| {
+            let mut groups = vec![Vec::new(); PATTERN_GROUP_COUNT];
+            for (pattern_id, aligned) in ALIGNED_PATTERNS.iter().enumerate() {
+                let group = pattern_id / PATTERN_GROUP_SIZE;
+                groups[group].push(canonical[group][ternary_index(board, aligned, PATTERN_MASK[pattern_id])]);
+            }
+            groups.iter_mut().for_each(|g| g.sort_unstable());
+            groups
+        };
+        for symmetry in 0..8 {
+            let transformed = Board {
+                my_board: transform_board(board.my_board, symmetry),
+                opponent_board: transform_board(board.opponent_board, symmetry),
+            };
+            assert_eq!(group_indices(&board), group_indices(&transformed));
+        }
+    }
+}
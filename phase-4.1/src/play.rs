@@ -1,87 +1,160 @@
 // ここいじる
-use crate::database;
-
-// 評価関数のインポートを条件分岐
-#[cfg(not(target_arch = "wasm32"))]
-use crate::eval;
+use crate::database::{self, Bound, Cache};
+use crate::engine::{self, Engine, Logger};
 
 use super::proto::Move;
-use crate::solver::solve;
+use crate::solver;
+use crate::probcut;
+use crate::ordering::{self, MoveOrdering};
+use crate::calibration::CalibratedScore;
 
 use itertools::Itertools;
 use std::fmt::{self, Display, Formatter};
-use std::io::Write;  // selfを削除
-use std::fs::OpenOptions;
 use std::time::{Instant, Duration};
 use chrono::Local;
 
-// WebAssembly環境でのログ管理用
-#[cfg(target_arch = "wasm32")]
-use std::sync::Mutex;
-#[cfg(target_arch = "wasm32")]
-pub static LOG_BUFFER: Mutex<Vec<String>> = Mutex::new(Vec::new());
-
 
 const PASS : u8 = 64;
 const NIL_MOVE : u8 = 255;
 const DEPTH_INF : u8 = 64;
 
-const WIN_SCORE : f32 = 1000.0;
-const LOSE_SCORE : f32 = -1000.0;
+pub const WIN_SCORE : f32 = 1000.0;
+pub const LOSE_SCORE : f32 = -1000.0;
 
 pub struct InitGame {
     pub opponent_name: String,
     pub assigned_time_ms: i32,
 }
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-static XORSHIFT_STATE: AtomicU64 = AtomicU64::new(1);
+// 評価関数の種類
+// Linearは軽い代わりに精度が落ちるので、低速な端末で深く読みたいときに使う
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvalKind {
+    Network,
+    Linear,
+}
 
-fn initialize_xorshift_seed() {
-    // WebAssembly環境では現在時刻とランダム値を組み合わせてシード生成
-    let timestamp = js_sys::Date::now() as u64;
-    let random_js = (js_sys::Math::random() * (u32::MAX as f64)) as u64;
-    
-    // より良いシード値を生成（ゼロを避ける）
-    let seed = if timestamp ^ random_js == 0 { 
-        0x123456789ABCDEF 
-    } else { 
-        timestamp ^ random_js 
-    };
-    
-    XORSHIFT_STATE.store(seed, Ordering::Relaxed);
+impl std::str::FromStr for EvalKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "network" | "nn" => Ok(EvalKind::Network),
+            "linear" => Ok(EvalKind::Linear),
+            _ => Err(format!("Unknown evaluator: {}", s)),
+        }
+    }
+}
+
+// 既定のエンジンの評価関数
+pub fn set_eval_kind(kind: EvalKind) {
+    engine::update_default_engine(|engine| engine.set_eval_kind(kind));
 }
 
-fn xorshift() -> u64 {
-    // 初回呼び出し時にシードを初期化
-    if XORSHIFT_STATE.load(Ordering::Relaxed) == 1 {
-        initialize_xorshift_seed();
+pub fn get_eval_kind() -> EvalKind {
+    engine::default_engine().eval_kind()
+}
+
+// 中盤探索のスレッド数(設定が0なら利用できるコア数)
+// 2以上ならLazy SMPで補助スレッドを走らせる
+#[cfg(not(target_arch = "wasm32"))]
+fn search_threads(engine: &Engine) -> usize {
+    crate::options::thread_count(engine.options().threads)
+}
+
+// 空きマスがこれ以下なら読み切る(既定値)
+pub const DEFAULT_SOLVE_EMPTIES: u8 = 18;
+
+// 選択的読み切りの結果を使う最低の確からしさ(%)
+// これより低い段階しか終わらなければ中盤探索で手を決める
+pub const MIN_SOLVER_CONFIDENCE: u8 = 95;
+
+// 探索の制限
+// 指定したものを自由に組み合わせ、どれかに達したら止める(何も指定しなければ読み切るまで読む)
+// 深さとノード数だけで制限すれば、1スレッドでは機械の速さによらず同じ手・同じノード数になる
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchLimits {
+    // 中盤探索の反復深化の最大の深さ
+    pub max_depth: Option<u8>,
+    // 中盤探索と読み切りのノード数の合計
+    pub max_nodes: Option<u64>,
+    pub max_time_ms: Option<u64>,
+    // 空きマスがこれ以下なら読み切る
+    pub solve_empties: u8,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            max_nodes: None,
+            max_time_ms: None,
+            solve_empties: DEFAULT_SOLVE_EMPTIES,
+        }
     }
-    
-    let mut x = XORSHIFT_STATE.load(Ordering::Relaxed);
-    x ^= x << 13;
-    x ^= x >> 7;
-    x ^= x << 17;
-    XORSHIFT_STATE.store(x, Ordering::Relaxed);
-    x
 }
 
-fn get_rand(l: f32, r: f32) -> f32 {
-    let rand_val = xorshift() as f32 / (u64::MAX as f32);
-    l + (r - l) * rand_val
+impl SearchLimits {
+    // 時間だけで制限する(従来のdecide_move)
+    pub fn time(assigned_time_ms: u64) -> Self {
+        Self { max_time_ms: Some(assigned_time_ms), ..Self::default() }
+    }
+}
+
+// 1回の探索の統計(探索ごとに返し、対局を通してグラフにできる)
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SearchStats {
+    // 本探索のスレッドの中盤探索のノード数(評価回数・置換表の統計も同じスレッドの分)
+    pub nodes: u64,
+    // Lazy SMPの補助スレッドのノード数(nodesとnpsには含めない)
+    pub helper_nodes: u64,
+    pub evals: u64,
+    // 探索したノードでの置換表の参照とヒット(並び替えのための子局面の参照は含めない)
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    // 置換表の値で打ち切ったノード数
+    pub tt_cutoffs: u64,
+    pub solver_nodes: u64,
+    // 読み終えた反復深化の深さ(読み切った場合は空きマス数)
+    pub depth: u8,
+    // 最も深く読んだ手数(ProbCutや並び替えの浅い探索、パスを除く)
+    pub selective_depth: u8,
+    pub time_ms: u64,
+    // 1秒あたりのノード数(本探索のスレッドの中盤探索と読み切りの合計)
+    pub nps: u64,
+    // 定石の手を返した
+    pub book: bool,
+    // 完全読み(確からしさ100%)の手を返した
+    pub solver: bool,
+    // 選択的読み切り(確からしさ100%未満)の手を返した
+    pub selective_solver: bool,
+    // 読み切りで終わった最も高い段階の確からしさ(%)、結果を使わなかった場合も入れる
+    pub solver_confidence: Option<u8>,
+    // 返した手の評価(手番側から見た予想石差)、定石の手や読み終えた手がなければNone
+    pub score: Option<f32>,
 }
 
 #[cfg(target_arch = "wasm32")]
 pub struct TimeManager {
     start_time: f64, // WebAssemblyではperformance.now()を使用
     assigned_time_ms: u64,
+    stopped: AtomicBool,
+    max_nodes: u64,
+    nodes: AtomicU64,
+    solver_nodes: AtomicU64,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 pub struct TimeManager {
     start_time: Instant,
     assigned_time: Duration,
+    stopped: AtomicBool,
+    max_nodes: u64,
+    nodes: AtomicU64,
+    solver_nodes: AtomicU64,
 }
 
 impl TimeManager {
@@ -91,6 +164,10 @@ impl TimeManager {
         TimeManager {
             start_time,
             assigned_time_ms,
+            stopped: AtomicBool::new(false),
+            max_nodes: u64::MAX,
+            nodes: AtomicU64::new(0),
+            solver_nodes: AtomicU64::new(0),
         }
     }
 
@@ -98,19 +175,56 @@ impl TimeManager {
     pub fn new(limit_ms: u64) -> Self {
         Self {
             start_time: Instant::now(),
-            assigned_time: Duration::from_millis(limit_ms)
+            assigned_time: Duration::from_millis(limit_ms),
+            stopped: AtomicBool::new(false),
+            max_nodes: u64::MAX,
+            nodes: AtomicU64::new(0),
+            solver_nodes: AtomicU64::new(0),
+        }
+    }
+
+    // 時間とノード数の制限
+    pub fn from_limits(limits: &SearchLimits) -> Self {
+        Self {
+            max_nodes: limits.max_nodes.unwrap_or(u64::MAX),
+            ..Self::new(limits.max_time_ms.unwrap_or(u64::MAX / 2))
         }
     }
 
     #[cfg(target_arch = "wasm32")]
     pub fn should_stop(&self) -> bool {
         let elapsed = js_sys::Date::now() - self.start_time;
-        elapsed >= self.assigned_time_ms as f64
+        elapsed >= self.assigned_time_ms as f64 || self.stopped.load(Ordering::Relaxed)
+            || self.nodes.load(Ordering::Relaxed) + self.solver_nodes.load(Ordering::Relaxed) >= self.max_nodes
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     pub fn should_stop(&self) -> bool {
-        self.start_time.elapsed() >= self.assigned_time
+        self.start_time.elapsed() >= self.assigned_time || self.stopped.load(Ordering::Relaxed)
+            || self.nodes.load(Ordering::Relaxed) + self.solver_nodes.load(Ordering::Relaxed) >= self.max_nodes
+    }
+
+    // 時間が残っていても止める(Lazy SMPの補助スレッドを終わらせるのに使う)
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    // 中盤探索のノードを数える
+    pub fn count_node(&self) {
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    // 読み切りのノードは別に数える(ノード数の制限には合計で含める)
+    pub fn count_solver_node(&self) {
+        self.solver_nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_solver_nodes(&self) -> u64 {
+        self.solver_nodes.load(Ordering::Relaxed)
     }
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -142,25 +256,39 @@ pub struct Board{
 
 type Pos = (usize, usize);
 
-impl Board {
-    // ログ出力用のヘルパー関数（条件付きコンパイル対応）
-    #[cfg(target_arch = "wasm32")]
-    fn write_to_log(&self, message: &str) {
-        if let Ok(mut buffer) = LOG_BUFFER.lock() {
-            buffer.push(message.to_string());
-        }
-        // WebAssemblyではコンソールにも出力
-        web_sys::console::log_1(&message.into());
+// 候補手ごとの評価
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Hint {
+    pub mv: u8,
+    pub depth: u8,
+    pub score: CalibratedScore,
+}
+
+// 打った手を戻すための情報(置いたマスと返した石)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Undo {
+    pub square: u8,
+    pub flipped: u64,
+}
+
+// 置いたマスから見た方向ごとの返る石(database::DIRECTIONSの順)
+// 石を返すアニメーションに使う
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveFlips {
+    pub square: u8,
+    pub directions: [u64; 8],
+}
+
+impl MoveFlips {
+    pub fn flipped(&self) -> u64 {
+        self.directions.iter().fold(0, |acc, &d| acc | d)
     }
+}
 
-    #[cfg(not(target_arch = "wasm32"))]
+impl Board {
+    // 既定のエンジンのログに出力する
     fn write_to_log(&self, message: &str) {
-        if let Ok(mut file) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("log.txt") {
-            let _ = writeln!(file, "{}", message);
-        }
+        engine::default_engine().log(message);
     }
 
     // デバッグ用：盤面状態を詳細にログ出力する関数
@@ -195,26 +323,26 @@ impl Board {
         }
 
         // 評価値も表示
-        let eval_score = self.eval(0.0);
+        let eval_score = self.get_eval();
         self.write_to_log(&format!("Current evaluation: {:.2}", eval_score));
     }
 
     // 盤面をコンパクトにログ出力
-    fn log_board_compact(&self, context: &str) {
+    fn log_board_compact(&self, logger: &Logger, context: &str) {
         // 現在の時間を表示
         let now = Local::now();
-        self.write_to_log(&format!("{} - {}", now.format("\n%Y-%m-%d %H:%M:%S"), context));
+        logger.write(&format!("{} - {}", now.format("\n%Y-%m-%d %H:%M:%S"), context));
         
         // 石の数を表示
         let my_count = self.my_board.count_ones();
         let op_count = self.opponent_board.count_ones();
-        self.write_to_log(&format!("O: {} pieces, X: {} pieces", my_count, op_count));
+        logger.write(&format!("O: {} pieces, X: {} pieces", my_count, op_count));
         
         // 配置可能位置を取得
         let placeable = self.get_valid_moves();
         
         // 座標軸（列）を表示
-        self.write_to_log("  ABCDEFGH");
+        logger.write("  ABCDEFGH");
         
         // 盤面の出力
         for j in 0..8 {
@@ -232,7 +360,7 @@ impl Board {
                 };
                 line.push(mark);
             }
-            self.write_to_log(&line);
+            logger.write(&line);
         }
         
         // 配置可能位置があれば詳細を表示
@@ -246,9 +374,9 @@ impl Board {
                     placeable_positions.push(format!("{}{}", col_char, row + 1));
                 }
             }
-            self.write_to_log(&format!("Placeable: {}", placeable_positions.join(" ")));
+            logger.write(&format!("Placeable: {}", placeable_positions.join(" ")));
         } else {
-            self.write_to_log("No placeable positions");
+            logger.write("No placeable positions");
         }
     }
 
@@ -268,7 +396,7 @@ impl Board {
         };
         
         // 初期盤面をログに出力
-        board.log_board_compact("Initial Board");
+        board.log_board_compact(engine::default_engine().logger(), "Initial Board");
         board
     }
 
@@ -335,7 +463,7 @@ impl Board {
                 let col_char = (b'A' + (x_ah - 1) as u8) as char;
                 self.write_to_log(&format!("Move: {}{}", col_char, y_18));
                 if !is_my_turn {
-                    self.log_board_compact("Opponent's Move");
+                    self.log_board_compact(engine::default_engine().logger(), "Opponent's Move");
                 }
             }
             Move::Pass => {
@@ -351,12 +479,17 @@ impl Board {
         return database::get_placeable(self);
     }
 
-    fn get_turn(&self) -> usize {
+    pub fn get_turn(&self) -> usize {
         (self.my_board | self.opponent_board).count_ones() as usize
     }
 
     // 一手動かす(手番変更なし)
     pub fn do_move(&mut self, m: u8) {
+        self.make_move(m);
+    }
+
+    // 一手動かし(手番変更なし)、unmake_moveで戻すための情報を返す
+    pub fn make_move(&mut self, m: u8) -> Undo {
         let flipper = database::get_flipper(self, m as usize);
         if flipper == 0 {
             self.print_board();
@@ -365,371 +498,671 @@ impl Board {
         self.my_board |= 1 << m;
         self.my_board ^= flipper;
         self.opponent_board ^= flipper;
+        Undo { square: m, flipped: flipper }
     }
 
-    fn eval(&self, disturbance: f32) -> f32 {
-        // eval呼び出しをカウント
-        database::increment_eval_count();
-        
-        let my_piece_count = self.my_board.count_ones() as i32;
-        let op_piece_count = self.opponent_board.count_ones() as i32;
-        let turn = (my_piece_count + op_piece_count) as usize;
-        
-        if turn == 64 {
-            let diff = my_piece_count - op_piece_count;
-            return if my_piece_count > op_piece_count {
-                WIN_SCORE + diff as f32
-            } 
-            else if my_piece_count < op_piece_count {
-                LOSE_SCORE + diff as f32
-            }
-            else {
-                0.0
-            };
-        }
-
-        let my_placeable = self.get_valid_moves();
-        let my_placeable_count = my_placeable.count_ones() as i32;
-        let mut t = self.clone();
-        t.change_turn();
-        let op_placeable = t.get_valid_moves();
-        let op_placeable_count = op_placeable.count_ones() as i32;
-        
-        if my_placeable == 0 && op_placeable == 0 {
-            let diff = my_piece_count - op_piece_count;
-            return if my_piece_count > op_piece_count {
-                WIN_SCORE + diff as f32
-            } 
-            else if my_piece_count < op_piece_count {
-                LOSE_SCORE + diff as f32
-            }
-            else {
-                0.0
-            };
-        }
-
-        // (my_piece_count - op_piece_count) as f32 * (1.0 - database::get_sigmoid_table()[turn]) + 
-        // (my_placeable_count - op_placeable_count) as f32 * database::get_sigmoid_table()[turn]
-        
-        // WebAssembly環境では専用の評価関数を使用
-        #[cfg(target_arch = "wasm32")]
-        {
+    // make_moveで動かした手を戻す(盤面を写さずに済む)
+    pub fn unmake_move(&mut self, undo: Undo) {
+        self.my_board ^= (1 << undo.square) | undo.flipped;
+        self.opponent_board ^= undo.flipped;
+    }
 
-            let base_eval = -crate::eval_wasm::EVAL_FUNCTION.eval(&self);
-            let noise = get_rand(-disturbance, disturbance);
-            base_eval + noise
-        }
-        
-        // 通常環境では既存の評価関数を使用
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let base_eval = -crate::eval::EVAL_FUNCTION.eval(&self);
-            let noise = get_rand(-disturbance, disturbance);
-            base_eval + noise
-        }
+    // mに打ったときの方向ごとの返る石
+    pub fn get_move_flips(&self, m: u8) -> MoveFlips {
+        MoveFlips { square: m, directions: database::get_directional_flips(self, m as usize) }
     }
 
-    // 公開用の評価関数
+    // 公開用の評価関数(既定のエンジンの評価関数を使う)
     pub fn get_eval(&self) -> f32 {
-        self.eval(0.0)
+        engine::default_engine().eval(self, 0.0)
+    }
+
+    // 石差と勝率に較正した評価値
+    pub fn get_calibrated_eval(&self) -> CalibratedScore {
+        engine::default_engine().calibrated_eval(self)
+    }
+
+    // 以下は既定のエンジンで読む
+    pub fn get_hints(&self, assigned_time_ms: u64) -> Vec<Hint> {
+        engine::default_engine().hints(self, assigned_time_ms)
+    }
+
+    pub fn search_values(&self, max_depth: u8) -> Vec<f32> {
+        engine::default_engine().search_values(self, max_depth)
     }
 
     pub fn decide_move(&self, assigned_time_ms: u64, disturbance: f32) -> usize {
-        self.log_board_compact("AI Thinking");
-        
-        // カウンターをリセット
-        database::reset_counters();
+        engine::default_engine().decide_move(self, assigned_time_ms, disturbance)
+    }
+
+    // 制限つきで手を決める、(手, 探索の統計)を返す
+    pub fn search_with_limits(&self, limits: &SearchLimits, disturbance: f32) -> (usize, SearchStats) {
+        engine::default_engine().search(self, limits, disturbance)
+    }
 
-        let turn = self.get_turn();
-        let mut time_manager = TimeManager::new(assigned_time_ms); // バグ元
+    // バックグラウンド思考専用のdecide_move（中断可能）
+    pub fn decide_move_background(&self, stop_flag: &std::sync::Arc<std::sync::atomic::AtomicBool>) -> usize {
+        engine::default_engine().decide_move_background(self, stop_flag)
+    }
+}
 
-        let moves = self.get_valid_moves();
-        if moves == 0 {
-            return PASS as usize;
+// 各合法手を反復深化で評価し、較正済みの値で良い順に返す
+// 時間切れで中断した深さの結果は使わない
+pub(crate) fn hints(engine: &Engine, board: &Board, assigned_time_ms: u64) -> Vec<Hint> {
+    let time_manager = TimeManager::new(assigned_time_ms);
+    engine.cache().new_search();
+    let mut context = SearchContext::new();
+    let moves = board.get_valid_moves();
+    let child_empties = 63 - board.get_turn();
+    let mut hints = Vec::new();
+
+    for depth in 1..=engine.options().max_depth {
+        let mut current = Vec::new();
+        let mut all_finished = true;
+        for m in (0..64).filter(|&m| moves & (1 << m) != 0) {
+            let mut t = board.clone();
+            t.do_move(m);
+            t.change_turn();
+            let (score, finished, _, terminated) = nega_scout(&t, f32::NEG_INFINITY, f32::INFINITY, depth - 1, &time_manager, 0.0, engine, &mut context);
+            if terminated {
+                return hints;
+            }
+            all_finished &= finished;
+            current.push(Hint {
+                mv: m,
+                depth,
+                score: engine.calibration().calibrate(child_empties, -score),
+            });
         }
+        current.sort_by(|a, b| b.score.raw.partial_cmp(&a.score.raw).unwrap());
+        hints = current;
+
+        if all_finished || time_manager.should_stop() {
+            break;
+        }
+    }
+    hints
+}
+
+// 反復深化で深さ1..=max_depthの探索値を順に返す(時間制限なし)
+// 勝敗が読み切れたらそこで止める
+pub(crate) fn search_values(engine: &Engine, board: &Board, max_depth: u8) -> Vec<f32> {
+    let time_manager = TimeManager::new(u64::MAX / 2);
+    engine.cache().new_search();
+    let mut context = SearchContext::new();
+    let mut values = Vec::new();
+    for depth in 1..=max_depth {
+        let (score, finished, _, _) = nega_scout(board, f32::NEG_INFINITY, f32::INFINITY, depth, &time_manager, 0.0, engine, &mut context);
+        values.push(score);
+        if finished {
+            break;
+        }
+    }
+    values
+}
+
+// 制限つきで手を決める、(手, 探索の統計)を返す
+pub(crate) fn search(engine: &Engine, board: &Board, limits: &SearchLimits, disturbance: f32) -> (usize, SearchStats) {
+    board.log_board_compact(engine.logger(), "AI Thinking");
+    
+    let cache = engine.cache();
+    cache.new_search();
+
+    let turn = board.get_turn();
+    let mut time_manager = TimeManager::from_limits(limits);
+    let options = engine.options();
+    let max_depth = limits.max_depth.unwrap_or(options.max_depth).clamp(1, 60);
+
+    let mut stats = SearchStats::default();
+    let moves = board.get_valid_moves();
+    if moves == 0 {
+        return (PASS as usize, stats);
+    }
 
-        if let Some(mv) = database::lookup_book(self) && disturbance <= 10.0 {
-            self.write_to_log("Using book move");
-            return mv as usize;
+    if disturbance <= options.book_disturbance {
+        if let Some(mv) = engine.book().lookup(board) {
+            engine.log("Using book move");
+            stats.book = true;
+            return (mv as usize, finish_stats(&time_manager, stats));
         }
         else {
-            self.write_to_log("No book move found");
+            engine.log("No book move found");
         }
+    }
 
-        if turn >= 46 && disturbance <= 10.0 {
-            let (res, mv) = solve(self, &time_manager);
-            if (res != -2){
-                if (res == -1) {
-                    self.write_to_log("Solved: Losing position");
-                }
-                else if (res == 0) {
-                    self.write_to_log(&format!("Solved: Draw position with move {}", mv));
-                }
-                else {
-                    self.write_to_log(&format!("Solved: Winning position with move {}", mv));
-                }
+    // 終盤は確からしさの低い段階から読み切り、時間の許す限り段階を上げる
+    // 深さを空きマス数より浅く制限した場合は読み切らない(読み切りは深さで止められない)
+    let within_depth = limits.max_depth.is_none_or(|d| d as usize >= 64 - turn);
+    if 64 - turn <= limits.solve_empties as usize && within_depth && disturbance <= options.book_disturbance {
+        let result = solver::solve_progressive(board, engine.probcut(), engine.solver_table(), &time_manager, engine.solver_threads(), |r| {
+            engine.log(&format!("Solved ({}%): score={:+}, move={}", r.confidence, r.score, r.mv));
+        });
+        if let Some(result) = result {
+            debug_assert!(result.mv < 64 && moves & (1 << result.mv) != 0, "illegal move {} from the solver", result.mv);
+            stats.solver_confidence = Some(result.confidence);
+            // 負けの局面では、相手が間違えやすい手を中盤探索で探す
+            // 確からしさの低い段階の結果は使わない
+            if result.score >= 0 && result.confidence >= MIN_SOLVER_CONFIDENCE {
+                stats.solver = result.confidence == 100;
+                stats.selective_solver = result.confidence < 100;
+                stats.depth = (64 - turn) as u8;
+                stats.score = Some(result.score as f32);
+                return (result.mv as usize, finish_stats(&time_manager, stats));
             }
-            if res >= 0 {
-                return mv as usize;
-            }
-            time_manager = TimeManager::new(assigned_time_ms / 12);
+            engine.log(&format!("Solver result not used ({}%, score={:+})", result.confidence, result.score));
         }
+        // 読み切りに使った時間とノード数は統計に足しておく
+        stats.time_ms = time_manager.get_elapsed_ms();
+        stats.solver_nodes = time_manager.get_solver_nodes();
+        // 読み切りが使い切った時間とノード数の一部で中盤探索をする
+        time_manager = TimeManager::from_limits(&SearchLimits {
+            max_time_ms: limits.max_time_ms.map(|ms| ms / options.solver_time_divisor),
+            max_nodes: limits.max_nodes.map(|n| n / options.solver_time_divisor),
+            ..*limits
+        });
+    }
 
-        let alpha = f32::NEG_INFINITY;
-        let beta = f32::INFINITY;
+    // 本探索の反復深化(補助スレッドは置換表を通して手伝う)
+    let search = || {
         let mut best_score = f32::NEG_INFINITY;
-        let mut best_move = PASS as usize;
         let mut total_nodes = 0;
         let mut total_evals = 0;
-        
-        for depth in 1..61 {
+        let mut context = SearchContext::new();
+        // 最初の反復が時間切れでも合法手を返せるよう、時間を見ずに並び替えた先頭の手を控えておく
+        let mut best_move = root_fallback_move(board, cache, &context.ordering) as usize;
+        let mut completed_depth = 0;
+    
+        for depth in 1..=max_depth {
             // 前の深度での値を記録
-            let prev_nodes = database::get_node_count();
-            let prev_evals = database::get_eval_count();
-            
-            let (score, finished, next_move, terminated) = nega_scout(&self, alpha, beta, depth, &time_manager, disturbance);
+            let prev_nodes = context.stats.nodes;
+            let prev_evals = context.stats.evals;
+        
+            let (score, finished, next_move, terminated, researches) = aspiration_search(board, best_score, depth, &time_manager, disturbance, engine, &mut context);
 
             // この深度での新規訪問数
-            let depth_nodes = database::get_node_count() - prev_nodes;
-            let depth_evals = database::get_eval_count() - prev_evals;
-            
+            let depth_nodes = context.stats.nodes - prev_nodes;
+            let depth_evals = context.stats.evals - prev_evals;
+        
+            // 根で枝刈りされた場合などは手が返らないので、値だけ使う
+            let legal = next_move < 64 && moves & (1 << next_move) != 0;
             if !terminated {
                 best_score = score;
-                best_move = next_move as usize;
-                total_nodes = database::get_node_count();
-                total_evals = database::get_eval_count();
-                
+                if legal {
+                    best_move = next_move as usize;
+                }
+                completed_depth = depth;
+                total_nodes = context.stats.nodes;
+                total_evals = context.stats.evals;
+            
                 // 統計情報をログ出力
-                self.write_to_log(&format!(
-                    "Depth {}: nodes={}, evals={}, score={:.2}, move={}", 
-                    depth, depth_nodes, depth_evals, score, next_move
+                let calibrated = engine.calibration().calibrate(64 - turn, score);
+                engine.log(&format!(
+                    "Depth {}: nodes={}, evals={}, score={:.2} ({:+.1} discs, win {:.1}%), move={}, researches={}", 
+                    depth, depth_nodes, depth_evals, score, calibrated.disc_diff, calibrated.win_prob * 100.0, next_move, researches
                 ));
             }
+            // 中断した反復でも、読み終えた手が前の反復の最善より良ければ使う
+            else if legal && score > best_score {
+                best_score = score;
+                best_move = next_move as usize;
+                engine.log(&format!("Depth {}: interrupted, partial result score={:.2}, move={}", depth, score, next_move));
+            }
 
-            if finished || time_manager.should_stop() || depth == 60 {
-                self.write_to_log(&format!(
+            if finished || time_manager.should_stop() || depth == max_depth {
+                engine.log(&format!(
                     "Search completed: Total nodes={}, Total evals={}, Ratio={:.2}%", 
                     total_nodes, total_evals, 
                     if total_nodes > 0 { (total_evals as f64 / total_nodes as f64) * 100.0 } else { 0.0 }
                 ));
-                
-                self.write_to_log(&format!("Final: Depth={}, Best move={}, Score={:.2}, Finished={}", 
-                    depth, best_move, best_score, finished));
+                // ヒット率は本探索のスレッドの分
+                let stats = cache.stats();
+                let hit_rate = context.stats.tt_hits as f64 / context.stats.tt_probes.max(1) as f64;
+                engine.log(&format!(
+                    "Hash: {}MB, hit rate={:.1}%, fill={:.1}%",
+                    stats.size_mb, hit_rate * 100.0, stats.fill * 100.0
+                ));
+                let ordering_stats = context.ordering.stats();
+                engine.log(&format!(
+                    "Ordering: first-move cutoff rate={:.1}% ({} / {})",
+                    ordering_stats.first_move_cutoff_rate() * 100.0, ordering_stats.first_move_cutoffs, ordering_stats.cutoffs
+                ));
+            
+                let calibrated = engine.calibration().calibrate(64 - turn, best_score);
+                engine.log(&format!("Final: Depth={}, Best move={}, Score={:.2} ({:+.1} discs, win {:.1}%), Finished={}", 
+                    depth, best_move, best_score, calibrated.disc_diff, calibrated.win_prob * 100.0, finished));
                 let now = Local::now();
-                self.write_to_log(&format!("End : {}\n", now.format("%Y-%m-%d %H:%M:%S")));
-
-                return best_move as usize;
+                engine.log(&format!("End : {}\n", now.format("%Y-%m-%d %H:%M:%S")));
+                break;
             }
         }
+        (best_move, completed_depth, best_score, context)
+    };
+    let ((best_move, depth, score, context), helpers) = search_with_helpers(board, &time_manager, disturbance, engine, search);
+    // 置換表の統計は全スレッドの分を記録する
+    cache.record_probes(context.stats.tt_probes + helpers.stats.tt_probes, context.stats.tt_hits + helpers.stats.tt_hits);
+    debug_assert!(best_move < 64 && moves & (1 << best_move) != 0, "illegal move {} for {:016x} {:016x}", best_move, board.my_board, board.opponent_board);
+    stats.depth = depth;
+    stats.score = (score > f32::NEG_INFINITY).then(|| engine.calibration().calibrate(64 - turn, score).disc_diff);
+    stats.selective_depth = (64 - turn as u8).saturating_sub(context.min_empties);
+    let stats = SearchStats {
+        nodes: context.stats.nodes,
+        evals: context.stats.evals,
+        tt_probes: context.stats.tt_probes,
+        tt_hits: context.stats.tt_hits,
+        tt_cutoffs: context.stats.tt_cutoffs,
+        helper_nodes: helpers.stats.nodes,
+        ..stats
+    };
+    (best_move, finish_stats(&time_manager, stats))
+}
+
+// 探索の統計に時間・読み切りのノード数・NPSを入れる
+// 読み切りから中盤探索に切り替えた場合は、読み切りの分をstatsに入れて渡す
+fn finish_stats(time_manager: &TimeManager, stats: SearchStats) -> SearchStats {
+    let time_ms = stats.time_ms + time_manager.get_elapsed_ms();
+    let solver_nodes = stats.solver_nodes + time_manager.get_solver_nodes();
+    let nodes = stats.nodes;
+    SearchStats {
+        solver_nodes,
+        time_ms,
+        nps: (nodes + solver_nodes) * 1000 / time_ms.max(1),
+        ..stats
+    }
+}
 
+// バックグラウンド思考専用のdecide_move（中断可能）
+pub(crate) fn search_background(engine: &Engine, board: &Board, stop_flag: &AtomicBool) -> usize {
+    // バックグラウンド思考専用のカウンターをリセット
+    engine.reset_background_nodes();
+    
+    let moves = board.get_valid_moves();
+    if moves == 0 {
         return PASS as usize;
     }
+
+    // book moveをチェック
+    if let Some(mv) = engine.book().lookup(board) {
+        return mv as usize;
+    }
+
+    let alpha = f32::NEG_INFINITY;
+    let beta = f32::INFINITY;
+    // 深さ1を読み終える前に止められても合法手を返せるよう、並び替えた先頭の手を控えておく
+    let mut best_move = root_fallback_move(board, engine.cache(), &MoveOrdering::new()) as usize;
     
-    // バックグラウンド思考専用のdecide_move（中断可能）
-    pub fn decide_move_background(&self, stop_flag: &std::sync::Arc<std::sync::atomic::AtomicBool>) -> usize {
-        //println!("Background thinking started...");
-        
-        // バックグラウンド思考専用のカウンターをリセット
-        database::reset_background_counter();
+    // 浅い深度から開始して、中断されるまで反復深化
+    for depth in 1..=10 {  // バックグラウンドなので深度は制限
+        if stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            break;
+        }
         
-        let moves = self.get_valid_moves();
-        if moves == 0 {
-            //println!("Background thinking: No valid moves - PASS");
-            return PASS as usize;
+        let (_score, _finished, next_move, terminated) = nega_scout_background(
+            board, alpha, beta, depth, engine, stop_flag
+        );
+
+        let legal = next_move < 64 && moves & (1 << next_move) != 0;
+        if !terminated && legal && !stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            best_move = next_move as usize;
         }
 
-        // book moveをチェック
-        if let Some(mv) = database::lookup_book(self) {
-            //println!("Background thinking: Using book move {}", mv);
-            return mv as usize;
+        if terminated || stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            break;
         }
+    }
 
-        let alpha = f32::NEG_INFINITY;
-        let beta = f32::INFINITY;
-        let mut best_move = PASS as usize;
-        
-        // 浅い深度から開始して、中断されるまで反復深化
-        for depth in 1..=10 {  // バックグラウンドなので深度は制限
-            if stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
-                //println!("Background thinking interrupted at depth {}", depth);
-                break;
-            }
-            
-            let (score, _finished, next_move, terminated) = nega_scout_background(
-                &self, alpha, beta, depth, stop_flag
-            );
+    debug_assert!(best_move < 64 && moves & (1 << best_move) != 0, "illegal move {} for {:016x} {:016x}", best_move, board.my_board, board.opponent_board);
+    best_move
+}
 
-            if !terminated && !stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
-                best_move = next_move as usize;
-                let total_nodes = database::get_background_node_count();
-                // println!("Background thinking: Depth {} completed, {} nodes, best move: {}", 
-                //     depth, total_nodes, next_move);
-            }
+// Lazy SMP
+// 補助スレッドも同じ局面を反復深化で読み、結果を置換表に残して本探索の枝刈りと並び替えを助ける
+// 補助スレッドの半分は1手深く読み始め、スレッドごとに読む順序がずれるようにする
+// 補助スレッドの結果は使わず、本探索(search)が終わったら止める
+// 補助スレッドの統計はまとめて返す
+#[cfg(not(target_arch = "wasm32"))]
+fn search_with_helpers<R>(board: &Board, time_manager: &TimeManager, disturbance: f32, engine: &Engine, search: impl FnOnce() -> R) -> (R, SearchContext) {
+    let helpers = search_threads(engine).saturating_sub(1);
+    let mut total = SearchContext::new();
+    if helpers == 0 {
+        return (search(), total);
+    }
+    std::thread::scope(|scope| {
+        let handles = (0..helpers).map(|id| {
+            scope.spawn(move || {
+                let mut context = SearchContext::new();
+                for depth in (1 + id as u8 % 2)..61 {
+                    let (_, finished, _, terminated) = nega_scout(board, f32::NEG_INFINITY, f32::INFINITY, depth, time_manager, disturbance, engine, &mut context);
+                    if finished || terminated {
+                        break;
+                    }
+                }
+                context
+            })
+        }).collect_vec();
+        let result = search();
+        time_manager.stop();
+        for handle in handles {
+            total.merge(&handle.join().unwrap());
+        }
+        (result, total)
+    })
+}
 
-            if terminated || stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
-                break;
-            }
+#[cfg(target_arch = "wasm32")]
+fn search_with_helpers<R>(_board: &Board, _time_manager: &TimeManager, _disturbance: f32, _engine: &Engine, search: impl FnOnce() -> R) -> (R, SearchContext) {
+    (search(), SearchContext::new())
+}
+
+// 時間を見ずに根の手を並べ、先頭の手を返す(置換表の手・子局面の値・キラー手・静的な優先度)
+fn root_fallback_move(board: &Board, cache: &Cache, ordering: &MoveOrdering) -> u8 {
+    let tt_move = cache.get(board).map_or(NIL_MOVE, |node| node.next_move);
+    let moves = board.get_valid_moves();
+    (0..64)
+        .filter(|&m| moves & (1 << m) != 0)
+        .max_by_key(|&m| {
+            let mut t = board.clone();
+            t.do_move(m);
+            t.change_turn();
+            ordering.score(board, &t, m, tt_move, cache.get(&t).map(|node| node.value))
+        })
+        .unwrap_or(PASS)
+}
+
+// アスピレーション窓
+// 前の反復の値を中心にした狭い窓で読み、窓の外に出たらその側を広げて読み直す
+// 前の反復の値がない、または勝敗が決まっている場合は窓を開けて読む
+const ASPIRATION_DELTA: f32 = 2.0;
+const ASPIRATION_MAX_DELTA: f32 = 32.0;
+
+// (値, 探索終了, 最善手, 中断, 読み直した回数)
+fn aspiration_search(board: &Board, previous: f32, depth: u8, time_manager: &TimeManager, disturbance: f32, engine: &Engine, context: &mut SearchContext) -> (f32, bool, u8, bool, u32) {
+    let (mut alpha, mut beta) = if previous.abs() < WIN_SCORE {
+        (previous - ASPIRATION_DELTA, previous + ASPIRATION_DELTA)
+    } else {
+        (f32::NEG_INFINITY, f32::INFINITY)
+    };
+    let mut delta = ASPIRATION_DELTA;
+    let mut researches = 0;
+    loop {
+        let (score, finished, next_move, terminated) = nega_scout(board, alpha, beta, depth, time_manager, disturbance, engine, context);
+        if terminated {
+            return (score, finished, next_move, true, researches);
         }
 
-        let final_nodes = database::get_background_node_count();
-        // println!("Background thinking completed: {} total nodes, best move: {}", 
-        //     final_nodes, best_move);
-        
-        return best_move;
+        // 値は窓の外でも失敗した側の限界になっている(fail-soft)ので、そこから広げる
+        delta *= 2.0;
+        if score <= alpha && alpha > f32::NEG_INFINITY {
+            alpha = if delta > ASPIRATION_MAX_DELTA { f32::NEG_INFINITY } else { score - delta };
+        } else if score >= beta && beta < f32::INFINITY {
+            beta = if delta > ASPIRATION_MAX_DELTA { f32::INFINITY } else { score + delta };
+        } else {
+            return (score, finished, next_move, false, researches);
+        }
+        researches += 1;
+    }
+}
+
+// 探索するスレッドごとの状態(並び替えの履歴と探索の統計)
+// 統計は共有の変数を書き換えずにスレッドごとに数え、探索の終わりにまとめる
+pub(crate) struct SearchContext {
+    ordering: MoveOrdering,
+    // nodes, evals, tt_probes, tt_hits, tt_cutoffsだけを数える
+    stats: SearchStats,
+    // 訪れた局面の最も少ない空きマス数(選択的深さを求める)
+    min_empties: u8,
+}
+
+impl SearchContext {
+    pub(crate) fn new() -> Self {
+        Self { ordering: MoveOrdering::new(), stats: SearchStats::default(), min_empties: 64 }
+    }
+
+    fn count_node(&mut self, board: &Board) {
+        self.stats.nodes += 1;
+        self.min_empties = self.min_empties.min(64 - board.get_turn() as u8);
+    }
+
+    fn eval(&mut self, engine: &Engine, board: &Board, disturbance: f32) -> f32 {
+        self.stats.evals += 1;
+        engine.eval(board, disturbance)
+    }
+
+    // 他のスレッドの統計を足す
+    fn merge(&mut self, other: &SearchContext) {
+        self.stats.nodes += other.stats.nodes;
+        self.stats.evals += other.stats.evals;
+        self.stats.tt_probes += other.stats.tt_probes;
+        self.stats.tt_hits += other.stats.tt_hits;
+        self.stats.tt_cutoffs += other.stats.tt_cutoffs;
+        self.min_empties = self.min_empties.min(other.min_empties);
     }
 }
 
-fn nega_scout(board: &Board, original_alpha: f32, beta: f32, depth: u8, time_manager: &TimeManager, disturbance: f32) -> (f32, bool, u8, bool) {
+fn nega_scout(board: &Board, original_alpha: f32, beta: f32, depth: u8, time_manager: &TimeManager, disturbance: f32, engine: &Engine, context: &mut SearchContext) -> (f32, bool, u8, bool) {
     // ノード訪問をカウント
-    database::increment_node_count();
+    context.count_node(board);
+    time_manager.count_node();
+    let cache = engine.cache();
     
     if time_manager.should_stop() {
         return (f32::NEG_INFINITY, false, PASS, true);
     }
 
     let mut alpha = original_alpha;
-    match database::get_cache().get(&board){
+    let mut tt_move = NIL_MOVE;
+    // 並び替えのための子局面の参照は数えない
+    context.stats.tt_probes += 1;
+    match cache.get(&board){
         Some(node) => {
-            if node.complete && node.value >= beta {
+            context.stats.tt_hits += 1;
+            tt_move = node.next_move;
+            let cutoff = (node.complete && node.value >= beta) || (node.depth >= depth && match node.bound {
+                Bound::Exact => true,
+                Bound::Lower => node.value >= beta,
+                Bound::Upper => node.value <= original_alpha,
+                Bound::None => false,
+            });
+            if cutoff {
+                context.stats.tt_cutoffs += 1;
                 return (node.value, node.complete, node.next_move, false);
             }
-
-            if node.depth >= depth {
-                if node.exact {
-                    return (node.value, node.complete, node.next_move, false);
-                }
-                if node.lower_bound && node.value >= beta {
-                    return (node.value, node.complete, node.next_move, false);
-                }
-                if node.upper_bound && node.value <= original_alpha {
-                    return (node.value, node.complete, node.next_move, false);
-                }
-
-                if node.lower_bound {
-                    alpha = alpha.max(node.value);
-                }
+            if node.depth >= depth && node.bound == Bound::Lower {
+                alpha = alpha.max(node.value);
             }
         }
         None => ()
     }
+    // 置換表の下限でαを上げた場合は、上げた窓で失敗した値をその下限に直す
+    let search_alpha = alpha;
 
     // 終端条件
     if board.get_turn() == 64 {
-        let eval = board.eval(disturbance);  // ここでeval()が呼ばれる
-        database::get_cache().set(board, DEPTH_INF, NIL_MOVE, eval, true, true, false, false);
+        let eval = context.eval(engine, board, disturbance);
+        cache.set(board, DEPTH_INF, NIL_MOVE, eval, Bound::Exact, true);
         return (eval, true, NIL_MOVE, false);
     }
 
     if depth == 0 {
-        let eval = board.eval(disturbance);  // ここでeval()が呼ばれる
+        let eval = context.eval(engine, board, disturbance);
         let finished = eval >= WIN_SCORE || eval <= LOSE_SCORE;
-        database::get_cache().set(board, 0, NIL_MOVE, eval, false, finished, false, false);
+        cache.set(board, 0, NIL_MOVE, eval, Bound::None, finished);
         return (eval, finished, NIL_MOVE, false);
     }
 
+    // 確定石が過半数なら勝敗が決まっているので、窓の外なら打ち切る
+    if let Some((bound, lower_bound)) = stability_bound(board) {
+        if (lower_bound && bound >= beta) || (!lower_bound && bound <= original_alpha) {
+            cache.set(board, DEPTH_INF, NIL_MOVE, bound, if lower_bound { Bound::Lower } else { Bound::Upper }, false);
+            return (bound, false, NIL_MOVE, false);
+        }
+    }
+
+    // Multi-ProbCut
+    // 浅い探索で深い探索の値が窓の外に出ると予測できれば打ち切る
+    if depth >= probcut::MIN_DEPTH && engine.options().selectivity {
+        let empties = 64 - board.get_turn();
+        if let Some((lower, upper)) = engine.probcut().bounds(depth, empties, original_alpha, beta, engine.options().probcut_confidence) {
+            let shallow = probcut::shallow_depth(depth);
+            let (v, _, _, terminated) = nega_scout(board, upper - 1.0, upper, shallow, time_manager, disturbance, engine, context);
+            if terminated {
+                return (f32::NEG_INFINITY, false, PASS, true);
+            }
+            if v >= upper {
+                return (beta, false, NIL_MOVE, false);
+            }
+            let (v, _, _, terminated) = nega_scout(board, lower, lower + 1.0, shallow, time_manager, disturbance, engine, context);
+            if terminated {
+                return (f32::NEG_INFINITY, false, PASS, true);
+            }
+            if v <= lower {
+                return (original_alpha, false, NIL_MOVE, false);
+            }
+        }
+    }
+
     let moves = board.get_valid_moves();
     if moves == 0 {
         let mut t = board.clone();
         t.change_turn();
         if t.get_valid_moves() == 0 {
-            let eval = board.eval(disturbance);  // ここでeval()が呼ばれる
-            database::get_cache().set(&board, DEPTH_INF, NIL_MOVE, eval, true, true, false, false);
+            let eval = context.eval(engine, board, disturbance);
+            cache.set(board, DEPTH_INF, NIL_MOVE, eval, Bound::Exact, true);
             return (eval, true, NIL_MOVE, false);
         }
 
-        let p = nega_scout(&t, -beta, -alpha, depth - 1, &time_manager, disturbance);
-        let (eval, finished, _, terminated) = (-p.0, p.1, p.2, p.3);
+        let p = nega_scout(&t, -beta, -alpha, depth - 1, &time_manager, disturbance, engine, context);
+        let (mut eval, finished, _, terminated) = (-p.0, p.1, p.2, p.3);
         if terminated {return (eval, finished, PASS, true);}
 
-        let (exact, lower_bound, upper_bound) = if eval <= original_alpha {
-            (false, false, true)   // Upper bound
-        } else if eval >= beta {
-            (false, true, false)   // Lower bound
-        } else {
-            (true, false, false)   // Exact
-        };
-        
-        database::get_cache().set(board, depth, PASS, eval, exact, finished, lower_bound, upper_bound);
+        if eval <= search_alpha && search_alpha > original_alpha {
+            eval = search_alpha;
+        }
+        cache.set(board, depth, PASS, eval, Bound::from_window(eval, original_alpha, beta), finished);
         return (eval, finished, PASS, false);
     }
 
     // 手順生成と並び替え
-    let mut ordered_moves: Vec<(f32, u8, Board)> = (0..64)
-        .filter(|&m| moves & (1 << m) != 0)
+    // 子局面は盤面を写さず、打って読んだ後に戻す
+    let mut child = *board;
+    let move_list = (0..64).filter(|&m| moves & (1 << m) != 0).collect_vec();
+
+    // 深い探索では置換表に値のない子局面を浅く読み、その値で並べる
+    if depth >= ordering::SHALLOW_ORDERING_DEPTH {
+        for &m in move_list.iter() {
+            let undo = child.make_move(m);
+            child.change_turn();
+            if cache.get(&child).is_none() {
+                let (_, _, _, terminated) = nega_scout(&child, f32::NEG_INFINITY, f32::INFINITY, depth / 4, time_manager, disturbance, engine, context);
+                if terminated {
+                    return (f32::NEG_INFINITY, false, PASS, true);
+                }
+            }
+            child.change_turn();
+            child.unmake_move(undo);
+        }
+    }
+
+    let mut ordered_moves: Vec<(i32, u8)> = move_list.into_iter()
         .map(|m| {
-            let mut t = board.clone();
-            t.do_move(m);
-            t.change_turn();
-            let eval = match database::get_cache().get(&t){
-                Some(node) => node.value,
-                None => 0.0 //t.eval()  // ここでeval()が呼ばれる
-            };
-            (eval, m, t)
+            let undo = child.make_move(m);
+            child.change_turn();
+            let child_value = cache.get(&child).map(|node| node.value);
+            let score = context.ordering.score(board, &child, m, tt_move, child_value);
+            child.change_turn();
+            child.unmake_move(undo);
+            (score, m)
         })
         .collect_vec();
-    ordered_moves.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-
-    // 残りの探索は既存のまま...
-    let p = nega_scout(&ordered_moves[0].2, -beta, -alpha, depth - 1, &time_manager, disturbance);
+    ordered_moves.sort_by_key(|a| std::cmp::Reverse(a.0));
+
+    // 中断した場合は、読み終えた手の中の最善(なければNIL_MOVE)を返す
+    let undo = child.make_move(ordered_moves[0].1);
+    child.change_turn();
+    let p = nega_scout(&child, -beta, -alpha, depth - 1, &time_manager, disturbance, engine, context);
+    child.change_turn();
+    child.unmake_move(undo);
     let (v, finished, _, terminated) = (-p.0, p.1, p.2, p.3);
+    if terminated {return (f32::NEG_INFINITY, false, NIL_MOVE, true);}
     let mut best_score = v;
     let mut best_move = ordered_moves[0].1;
     let mut is_finished = finished;
     alpha = alpha.max(v);
 
-    if terminated {return (best_score, is_finished, best_move, true);}
-
     if beta <= alpha {
-        database::get_cache().set(board, depth, best_move, best_score, false, is_finished, true, false);
+        context.ordering.record_cutoff(board, best_move, depth, 0);
+        cache.set(board, depth, best_move, best_score, Bound::Lower, is_finished);
         return (best_score, is_finished, best_move, false);
     }
 
-    for (_eval, m, t) in ordered_moves.iter().skip(1) {
-        let p = nega_scout(t, -alpha - 1.0, -alpha, depth - 1, &time_manager, disturbance);
+    for (i, &(_, m)) in ordered_moves.iter().enumerate().skip(1) {
+        let undo = child.make_move(m);
+        child.change_turn();
+        let p = nega_scout(&child, -alpha - 1.0, -alpha, depth - 1, &time_manager, disturbance, engine, context);
         let (mut v, mut finished, _, terminated) = (-p.0, p.1, p.2, p.3);
         if terminated {return (best_score, is_finished, best_move, true);}
 
         if alpha < v && v < beta {
-            let p = nega_scout(t, -beta, -v, depth - 1, &time_manager, disturbance);
+            let p = nega_scout(&child, -beta, -v, depth - 1, &time_manager, disturbance, engine, context);
             (v, finished) = (-p.0, p.1);
             if p.3 {return (best_score, is_finished, best_move, true);}
         }
+        child.change_turn();
+        child.unmake_move(undo);
         
         if v > best_score {
             best_score = v;
-            best_move = *m;
+            best_move = m;
             is_finished = finished;
             alpha = v;
         }
         
         if beta <= alpha {
-            database::get_cache().set(board, depth, best_move, best_score, false, is_finished, true, false);
+            context.ordering.record_cutoff(board, best_move, depth, i);
+            cache.set(board, depth, best_move, best_score, Bound::Lower, is_finished);
             return (best_score, is_finished, best_move, false);
         }
     }
     
-    let (exact, lower_bound, upper_bound) = if best_score <= original_alpha {
-        (false, false, true)   // Upper bound
-    } else if best_score >= beta {
-        (false, true, false)   // Lower bound  
-    } else {
-        (true, false, false)   // Exact
-    };
-
-    database::get_cache().set(board, depth, best_move, best_score, exact, is_finished, lower_bound, upper_bound);
+    // 置換表の下限で上げた窓で失敗したなら、値はその下限に決まる
+    // (ProbCutで値が窓によって変わると、元の窓では上限のまま返してアスピレーション窓の値が食い違う)
+    if best_score <= search_alpha && search_alpha > original_alpha {
+        best_score = search_alpha;
+    }
+    cache.set(board, depth, best_move, best_score, Bound::from_window(best_score, original_alpha, beta), is_finished);
     (best_score, is_finished, best_move, false)
 }
 
+// 確定石から決まる評価値の限界 (値, 下限ならtrue)
+// 自分の確定石がs個(s > 32)なら勝ちで石差2s - 64以上、相手がs個なら負けで64 - 2s以下
+fn stability_bound(board: &Board) -> Option<(f32, bool)> {
+    if board.my_board.count_ones() > 32 {
+        let stable = database::get_stable(board).count_ones() as f32;
+        if stable > 32.0 {
+            return Some((WIN_SCORE + 2.0 * stable - 64.0, true));
+        }
+    }
+    if board.opponent_board.count_ones() > 32 {
+        let mut t = board.clone();
+        t.change_turn();
+        let stable = database::get_stable(&t).count_ones() as f32;
+        if stable > 32.0 {
+            return Some((LOSE_SCORE + 64.0 - 2.0 * stable, false));
+        }
+    }
+    None
+}
+
 // バックグラウンド思考専用のnega_scout（1000ノードごとに進捗出力）
 fn nega_scout_background(
     board: &Board, 
     original_alpha: f32, 
     beta: f32, 
     depth: u8, 
-    stop_flag: &std::sync::Arc<std::sync::atomic::AtomicBool>
+    engine: &Engine,
+    stop_flag: &AtomicBool
 ) -> (f32, bool, u8, bool) {
     // バックグラウンド思考専用のノード訪問カウント
-    let current_nodes = database::increment_background_node_count();
+    let current_nodes = engine.count_background_node();
     
     // 1000ノードごとに進捗を出力
     if current_nodes % 1000 == 0 {
@@ -745,12 +1178,12 @@ fn nega_scout_background(
     
     // 終端条件
     if board.get_turn() == 64 {
-        let eval = board.eval(0.0);
+        let eval = engine.eval(board, 0.0);
         return (eval, true, NIL_MOVE, false);
     }
 
     if depth == 0 {
-        let eval = board.eval(0.0);
+        let eval = engine.eval(board, 0.0);
         let finished = eval >= WIN_SCORE || eval <= LOSE_SCORE;
         return (eval, finished, NIL_MOVE, false);
     }
@@ -760,11 +1193,11 @@ fn nega_scout_background(
         let mut t = board.clone();
         t.change_turn();
         if t.get_valid_moves() == 0 {
-            let eval = board.eval(0.0);
+            let eval = engine.eval(board, 0.0);
             return (eval, true, NIL_MOVE, false);
         }
         
-        let (score, complete, _, terminated) = nega_scout_background(&t, -beta, -alpha, depth - 1, stop_flag);
+        let (score, complete, _, terminated) = nega_scout_background(&t, -beta, -alpha, depth - 1, engine, stop_flag);
         return (-score, complete, PASS, terminated);
     }
 
@@ -777,7 +1210,7 @@ fn nega_scout_background(
     let mut t = board.clone();
     t.do_move(first_move);
     t.change_turn();
-    let (score, complete, _, terminated) = nega_scout_background(&t, -beta, -alpha, depth - 1, stop_flag);
+    let (score, complete, _, terminated) = nega_scout_background(&t, -beta, -alpha, depth - 1, engine, stop_flag);
     
     if terminated {
         return (f32::NEG_INFINITY, false, PASS, true);
@@ -812,7 +1245,7 @@ fn nega_scout_background(
         t.change_turn();
         
         // null window search
-        let (mut score, complete, _, terminated) = nega_scout_background(&t, -alpha - 1.0, -alpha, depth - 1, stop_flag);
+        let (mut score, complete, _, terminated) = nega_scout_background(&t, -alpha - 1.0, -alpha, depth - 1, engine, stop_flag);
         
         if terminated {
             return (best_score, is_finished, best_move, true);
@@ -822,7 +1255,7 @@ fn nega_scout_background(
         
         if alpha < score && score < beta {
             // re-search with full window
-            let (score2, complete2, _, terminated2) = nega_scout_background(&t, -beta, -score, depth - 1, stop_flag);
+            let (score2, complete2, _, terminated2) = nega_scout_background(&t, -beta, -score, depth - 1, engine, stop_flag);
             
             if terminated2 {
                 return (best_score, is_finished, best_move, true);
@@ -888,6 +1321,258 @@ impl Display for Board {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::options::EngineOptions;
+    use std::sync::Arc;
+
+    // 初期局面から石数がdiscsになるまでランダムに進めた局面
+    fn random_positions(count: usize, discs: usize) -> Vec<Board> {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        (0..count).map(|_| {
+            let mut board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+            while board.get_turn() < discs {
+                let moves = board.get_valid_moves();
+                if moves == 0 {
+                    board.change_turn();
+                    if board.get_valid_moves() == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                let n = rng.gen_range(0..moves.count_ones()) as usize;
+                board.do_move((0..64u8).filter(|&m| (moves >> m) & 1 != 0).nth(n).unwrap());
+                board.change_turn();
+            }
+            board
+        }).collect()
+    }
+
+    // 中盤の局面で、Lazy SMPのスレッド数ごとに決まった深さまでの時間とNPSを測る
+    // cargo test --release bench_lazy_smp -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_lazy_smp() {
+        database::initialize_tables();
+        for board in random_positions(4, 24) {
+            for threads in [1, 2, 4] {
+                let engine = Engine::with_options(EngineOptions { threads, ..EngineOptions::default() });
+                let time_manager = TimeManager::new(u64::MAX / 2);
+                let start = Instant::now();
+                let ((score, mv, context), helpers) = search_with_helpers(&board, &time_manager, 0.0, &engine, || {
+                    let mut result = (0.0, NIL_MOVE);
+                    let mut context = SearchContext::new();
+                    for depth in 1..=5 {
+                        let (score, _, mv, _) = nega_scout(&board, f32::NEG_INFINITY, f32::INFINITY, depth, &time_manager, 0.0, &engine, &mut context);
+                        result = (score, mv);
+                    }
+                    (result.0, result.1, context)
+                });
+                let elapsed = start.elapsed();
+                let nodes = context.stats.nodes;
+                eprintln!("{:016x} {:016x}, {} threads: depth 5 in {:?}, {} + {} (helpers) nodes, {:.0} nps, score {:.2}, move {}",
+                    board.my_board, board.opponent_board, threads, elapsed, nodes, helpers.stats.nodes,
+                    nodes as f64 / elapsed.as_secs_f64(), score, mv);
+            }
+        }
+    }
+
+    // 同じ局面・同じ深さの反復深化で、窓を開けた場合とアスピレーション窓のノード数を比べる
+    // 速く測るため線形評価関数で読む
+    // cargo test --release bench_aspiration -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_aspiration() {
+        const DEPTH: u8 = 10;
+        database::initialize_tables();
+        let mut engine = Engine::new();
+        engine.set_eval_kind(EvalKind::Linear);
+        for board in random_positions(8, 24) {
+            let time_manager = TimeManager::new(u64::MAX / 2);
+
+            engine.clear_cache();
+            let mut context = SearchContext::new();
+            let mut full = (0.0, NIL_MOVE);
+            for depth in 1..=DEPTH {
+                let (score, _, mv, _) = nega_scout(&board, f32::NEG_INFINITY, f32::INFINITY, depth, &time_manager, 0.0, &engine, &mut context);
+                full = (score, mv);
+            }
+            let full_nodes = context.stats.nodes;
+
+            engine.clear_cache();
+            let mut context = SearchContext::new();
+            let mut aspiration = (f32::NEG_INFINITY, NIL_MOVE);
+            let mut researches = Vec::new();
+            for depth in 1..=DEPTH {
+                let (score, _, mv, _, r) = aspiration_search(&board, aspiration.0, depth, &time_manager, 0.0, &engine, &mut context);
+                aspiration = (score, mv);
+                researches.push(r);
+            }
+            let aspiration_nodes = context.stats.nodes;
+
+            eprintln!("{:016x} {:016x}: full {} nodes ({:.2}, {}), aspiration {} nodes ({:.2}, {}), researches {:?}",
+                board.my_board, board.opponent_board, full_nodes, full.0, full.1,
+                aspiration_nodes, aspiration.0, aspiration.1, researches);
+        }
+    }
+
+    // アスピレーション窓で読んだ後に窓を開けて読み直しても同じ値になる
+    // Multi-ProbCutを使うと値が窓によって変わるので、置換表を共有して係数のある深さまで比べる
+    #[test]
+    fn test_aspiration() {
+        database::initialize_tables();
+        let time_manager = TimeManager::new(u64::MAX / 2);
+        let engine = Engine::with_options(EngineOptions {
+            hash_mb: 1,
+            threads: 1,
+            selectivity: true,
+            probcut_confidence: probcut::DEFAULT_CONFIDENCE,
+            ..EngineOptions::default()
+        });
+        for board in random_positions(4, 24) {
+            let mut context = SearchContext::new();
+            let mut previous = f32::NEG_INFINITY;
+            for depth in 1..=probcut::MAX_DEPTH {
+                let (score, _, _, _, _) = aspiration_search(&board, previous, depth, &time_manager, 0.0, &engine, &mut context);
+                let (expected, _, _, _) = nega_scout(&board, f32::NEG_INFINITY, f32::INFINITY, depth, &time_manager, 0.0, &engine, &mut context);
+                assert_eq!(score, expected, "{:016x} {:016x} depth {}", board.my_board, board.opponent_board, depth);
+                previous = score;
+            }
+        }
+    }
+
+    // 深さやノード数で制限すれば、何度読んでも同じ手・同じノード数になる
+    #[test]
+    fn test_search_limits() {
+        database::initialize_tables();
+        let board = random_positions(1, 24)[0];
+        // 結果はスレッド数とProbCutの設定で変わるので、既定値によらず決めておく
+        let options = EngineOptions {
+            hash_mb: 1,
+            threads: 1,
+            selectivity: true,
+            probcut_confidence: probcut::DEFAULT_CONFIDENCE,
+            ..EngineOptions::default()
+        };
+
+        // 時間は毎回変わるので、手とノード数だけ比べる
+        let search = |limits: &SearchLimits| {
+            let (mv, stats) = Engine::with_options(options).search(&board, limits, 0.0);
+            (mv, stats.nodes)
+        };
+
+        let limits = SearchLimits { max_depth: Some(4), ..SearchLimits::default() };
+        let (mv, stats) = Engine::with_options(options).search(&board, &limits, 0.0);
+        let nodes = stats.nodes;
+        assert_eq!(search(&limits), (mv, nodes));
+        assert_eq!((mv, nodes), (51, 839));
+        assert_eq!(stats.depth, 4);
+        assert!(stats.selective_depth >= 4);
+        assert!(stats.evals > 0 && stats.tt_hits <= stats.tt_probes && stats.tt_cutoffs <= stats.tt_hits);
+        assert!(!stats.book && !stats.solver && stats.solver_nodes == 0);
+
+        let limits = SearchLimits { max_nodes: Some(nodes / 2), ..SearchLimits::default() };
+        let (mv, half) = search(&limits);
+        assert_eq!(half, nodes / 2);
+        assert!(board.get_valid_moves() & (1 << mv) != 0);
+        assert_eq!(search(&limits), (mv, half));
+    }
+
+    // 終盤でも深さやノード数の制限で止まる(読み切りのノードもノード数に含める)
+    #[test]
+    fn test_search_limits_endgame() {
+        database::initialize_tables();
+        let board = random_positions(1, 48)[0];
+        let moves = board.get_valid_moves();
+        let mut engine = Engine::with_options(EngineOptions { hash_mb: 1, threads: 1, ..EngineOptions::default() });
+        engine.set_book(Arc::new(database::Book::default()));
+        engine.set_logger(Logger::Silent);
+        engine.set_eval_kind(EvalKind::Linear);
+
+        // 空きマス数より浅い深さなら読み切らない
+        let (mv, stats) = engine.search(&board, &SearchLimits { max_depth: Some(4), ..SearchLimits::default() }, 0.0);
+        assert!(moves & (1 << mv) != 0);
+        assert!(!stats.solver && stats.solver_nodes == 0);
+        assert_eq!(stats.depth, 4);
+
+        // 読み切りが終わらなければ、残りの一部のノード数で中盤探索をする
+        let limits = SearchLimits { max_nodes: Some(120), ..SearchLimits::default() };
+        let (mv, stats) = engine.search(&board, &limits, 0.0);
+        assert!(moves & (1 << mv) != 0);
+        assert!(!stats.solver && !stats.selective_solver && stats.solver_confidence.is_none());
+        assert_eq!(stats.solver_nodes, 120);
+        assert_eq!(stats.nodes, 120 / engine.options().solver_time_divisor);
+
+        // 勝ちの局面は完全読みの手、負けの局面は中盤探索の手を返す
+        let limits = SearchLimits { max_nodes: Some(1_000_000), ..SearchLimits::default() };
+        for (s, solved) in [
+            ("OOO-OO-X-OXOOOXXXXOOOOXXXXOXXOOX-OXXOOOOO-XOOOOO--XX-OOX-OX--OOO", true),
+            ("XO-O---XOO-O-XXXXOXOXOOX-OXOOXOX-OXOOOOOO-XXOOOO---XOOOOXXXXXXXX", false),
+        ] {
+            let board = crate::notation::parse_board(s).unwrap();
+            let (mv, stats) = engine.search(&board, &limits, 0.0);
+            assert!(board.get_valid_moves() & (1 << mv) != 0);
+            assert_eq!(stats.solver_confidence, Some(100), "{}", s);
+            assert_eq!((stats.solver, stats.selective_solver), (solved, false), "{}", s);
+            assert_eq!(stats.nodes > 0, !solved, "{}", s);
+        }
+    }
+
+    // 時間がなくても合法手を返す(中盤と、読み切りが終わらない終盤、止められたバックグラウンド思考)
+    #[test]
+    fn test_decide_move_timeout() {
+        database::initialize_tables();
+        for board in random_positions(4, 24).into_iter().chain(random_positions(4, 48)) {
+            let moves = board.get_valid_moves();
+            if moves == 0 {
+                continue;
+            }
+            for assigned_time_ms in [0, 1] {
+                let mv = board.decide_move(assigned_time_ms, 0.0);
+                assert!(mv < 64 && moves & (1 << mv) != 0, "{:016x} {:016x}: {}", board.my_board, board.opponent_board, mv);
+            }
+            // バックグラウンド思考は読み始める前に止められても合法手を返す
+            let stop_flag = std::sync::Arc::new(AtomicBool::new(true));
+            let mv = board.decide_move_background(&stop_flag);
+            assert!(mv < 64 && moves & (1 << mv) != 0, "{:016x} {:016x}: {}", board.my_board, board.opponent_board, mv);
+        }
+    }
+
+    #[test]
+    fn test_make_move() {
+        database::initialize_tables();
+        // 初期局面でf5に打つとe5(西)を返す
+        let board = Board::new(false);
+        let flips = board.get_move_flips(37);
+        assert_eq!(flips.directions, [0, 0, 0, 0, 0, 0, 1 << 36, 0]);
+
+        // d4に打つと8方向すべてで返す(東は2個)、方向はdatabase::DIRECTIONSの順(北はマスの番号が小さい側)
+        let opponent = [18, 19, 20, 26, 28, 29, 34, 35, 36].iter().fold(0u64, |b, &i| b | 1 << i);
+        let mine = [9, 11, 13, 25, 30, 41, 43, 45].iter().fold(0u64, |b, &i| b | 1 << i);
+        let original = Board { my_board: mine, opponent_board: opponent };
+        let mut board = original;
+        let flips = board.get_move_flips(27);
+        assert_eq!(flips.directions, [1 << 19, 1 << 20, 1 << 28 | 1 << 29, 1 << 36, 1 << 35, 1 << 34, 1 << 26, 1 << 18]);
+        let undo = board.make_move(27);
+        assert_eq!((undo.square, undo.flipped), (27, opponent));
+        assert_eq!((board.my_board, board.opponent_board), (mine | opponent | 1 << 27, 0));
+        board.unmake_move(undo);
+        assert!(board == original);
+
+        for original in random_positions(8, 30) {
+            let mut board = original;
+            let moves = board.get_valid_moves();
+            for m in (0..64u8).filter(|&m| moves >> m & 1 != 0) {
+                let flips = board.get_move_flips(m);
+                let undo = board.make_move(m);
+                assert_eq!(flips.flipped(), undo.flipped);
+                // 方向は重ならない
+                assert_eq!(flips.directions.iter().map(|d| d.count_ones()).sum::<u32>(), undo.flipped.count_ones());
+                board.unmake_move(undo);
+                assert!(board == original, "{}", original);
+            }
+        }
+    }
 
     #[test]
     fn test_board_fmt() {
@@ -896,3 +1581,22 @@ mod tests {
     }
 }
 
+
+/*
+bench_lazy_smp (release), depth 5, threads 1 / 2 / 4:
+(1コアの環境で計測したため速くはならない、NPSはほぼ一定でスレッド数に応じて探索量が増える)
+(NPSは補助スレッドを含めた値、複数コアで速くなることを確かめるまでthreadsの既定は1)
+8040201c8c100000 000058407060e060: 9.69s / 17.2s / 30.3s, 4037 / 4148 / 3590 nps
+0000013678301010 0001120806422c00: 11.7s / 34.6s / 62.6s, 3789 / 3965 / 3990 nps
+101010183d1a0000 0000640000242c54: 9.06s / 19.0s / 51.8s, 4013 / 3942 / 3293 nps
+00241808001a0000 004021363e040c04: 29.1s / 52.4s / 107.0s, 3559 / 3531 / 3432 nps
+最善手と評価値はスレッド数によらず同じ
+*/
+
+/*
+bench_aspiration (release, 線形評価関数, 深さ10まで反復深化、窓を開けた場合 / アスピレーション窓のノード数)
+Multi-ProbCutあり: 8局面の合計 607198 -> 371882
+  値は窓によって打ち切りが変わるため局面によって少しずれる(最善手は8局面とも同じ)
+Multi-ProbCutなし: 8局面の合計 12713550 -> 12450212、値と最善手は8局面とも同じ
+  局面ごとには-30%から+27%まで、読み直しは1反復あたり0-2回
+*/
@@ -0,0 +1,355 @@
+/*
+Multi-ProbCut
+浅い探索の値から深い探索の値を deep ≈ slope * shallow + intercept (誤差の標準偏差sigma) で予測し、
+深い探索の値が窓の外に出ると十分な確からしさで言えるときは深い探索を省く
+係数は(深さ, 空きマス数)ごとに自己対戦の局面から当てはめる
+評価関数ごとに誤差の出方が違うので、係数も評価関数(EvalKind)ごとに当てはめて持つ
+終盤の選択的読み切りでは、浅い探索の値から最終石差を同じ形で予測する
+*/
+
+use crate::play::{Board, EvalKind, TimeManager, WIN_SCORE, LOSE_SCORE};
+use crate::solver;
+use crate::engine;
+
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, LazyLock};
+
+// この深さ以上でProbCutを試す
+pub const MIN_DEPTH: u8 = 3;
+// 係数を当てはめる最大の深さ、より深い探索はこの深さの係数を使う
+pub const MAX_DEPTH: u8 = 6;
+pub const STAGE_COUNT: usize = 61;
+
+// 選択的読み切りで打ち切りを試す空きマス数の下限(これより少ないと完全に読む方が速い)
+pub const ENDGAME_MIN_EMPTIES: u32 = 10;
+// 選択的読み切りの予測に使う浅い探索の深さ
+pub const ENDGAME_SHALLOW_DEPTH: u8 = 2;
+// 最終石差の予測を当てはめる空きマス数の上限(これより多い局面は完全読みが重い)
+pub const ENDGAME_FIT_EMPTIES: u32 = 18;
+// 完全読みのサンプルにつける深さ
+pub const EXACT_DEPTH: u8 = 64;
+
+pub const PROBCUT_PATH: &str = "src/probcut.json";
+pub const LINEAR_PROBCUT_PATH: &str = "src/probcut_linear.json";
+
+// 同梱の係数、エンジンは評価関数に合わせてこれを共有し、set_probcutで差し替えられる
+pub static PROBCUT: LazyLock<Arc<ProbCut>> = LazyLock::new(|| {
+    Arc::new(ProbCut::from_json(include_str!("probcut.json"))
+        .expect("Failed to parse probcut file."))
+});
+
+static LINEAR_PROBCUT: LazyLock<Arc<ProbCut>> = LazyLock::new(|| {
+    Arc::new(ProbCut::from_json(include_str!("probcut_linear.json"))
+        .expect("Failed to parse linear probcut file."))
+});
+
+// 評価関数に合わせた係数
+pub fn for_eval(kind: EvalKind) -> Arc<ProbCut> {
+    match kind {
+        EvalKind::Network => PROBCUT.clone(),
+        EvalKind::Linear => LINEAR_PROBCUT.clone(),
+    }
+}
+
+pub fn path_for_eval(kind: EvalKind) -> &'static str {
+    match kind {
+        EvalKind::Network => PROBCUT_PATH,
+        EvalKind::Linear => LINEAR_PROBCUT_PATH,
+    }
+}
+
+// 既定の確からしさ(正規分布で片側約93%)
+// 打ち切るかどうかと確からしさはエンジンの設定(selectivity, probcut_confidence)で変える
+pub const DEFAULT_CONFIDENCE: f32 = 1.5;
+
+// 深さdepthの探索を予測する浅い探索の深さ
+// 偶数・奇数で評価値の傾向が変わるので、偶奇をそろえる
+pub fn shallow_depth(depth: u8) -> u8 {
+    (depth / 2) & !1 | (depth & 1)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CutParams {
+    pub slope: f32,
+    pub intercept: f32,
+    pub sigma: f32,
+}
+
+impl Default for CutParams {
+    // 当てはめられなかった場合は実質的に打ち切らない
+    fn default() -> Self {
+        Self {
+            slope: 1.0,
+            intercept: 0.0,
+            sigma: WIN_SCORE,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProbCut {
+    // [depth - MIN_DEPTH][空きマス数]
+    pub params: Vec<Vec<CutParams>>,
+    // 浅い探索の値から最終石差を予測する係数 [空きマス数]
+    pub endgame: Vec<CutParams>,
+}
+
+// 当てはめ用のサンプル、値はどちらも手番側から見た探索値
+// 完全読みのサンプルはdepthをEXACT_DEPTHにし、deepを最終石差にする
+#[derive(Clone, Copy, Debug)]
+pub struct ProbCutSample {
+    pub depth: u8,
+    pub empties: u8,
+    pub shallow: f32,
+    pub deep: f32,
+}
+
+impl ProbCut {
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn params(&self, depth: u8, empties: usize) -> &CutParams {
+        let row = &self.params[(depth.clamp(MIN_DEPTH, MAX_DEPTH) - MIN_DEPTH) as usize];
+        &row[empties.min(row.len() - 1)]
+    }
+
+    pub fn endgame_params(&self, empties: usize) -> &CutParams {
+        &self.endgame[empties.min(self.endgame.len() - 1)]
+    }
+
+    // 深い探索の値がbeta以上と予測できる浅い探索の値の下限
+    // 深い探索の値がalpha以下と予測できる浅い探索の値の上限
+    // 予測が使えない(勝敗が決まる値にかかる)場合はNone
+    pub fn bounds(&self, depth: u8, empties: usize, alpha: f32, beta: f32, t: f32) -> Option<(f32, f32)> {
+        if !(LOSE_SCORE < alpha && beta < WIN_SCORE) {
+            return None;
+        }
+        cut_bounds(self.params(depth, empties), alpha, beta, t)
+    }
+
+    // 最終石差がbeta以上・alpha以下と予測できる浅い探索の値
+    pub fn endgame_bounds(&self, empties: usize, alpha: f32, beta: f32, t: f32) -> Option<(f32, f32)> {
+        cut_bounds(self.endgame_params(empties), alpha, beta, t)
+    }
+
+    // 深さ・空きマス数ごとに最小二乗で当てはめる
+    pub fn fit(samples: &[ProbCutSample]) -> Self {
+        let params = (MIN_DEPTH..=MAX_DEPTH)
+            .map(|depth| fit_stages(samples, depth))
+            .collect();
+        let endgame = fit_stages(samples, EXACT_DEPTH);
+        Self { params, endgame }
+    }
+}
+
+fn cut_bounds(p: &CutParams, alpha: f32, beta: f32, t: f32) -> Option<(f32, f32)> {
+    if p.slope < 0.1 {
+        return None;
+    }
+    let upper = (beta + t * p.sigma - p.intercept) / p.slope;
+    let lower = (alpha - t * p.sigma - p.intercept) / p.slope;
+    if !(LOSE_SCORE < lower && upper < WIN_SCORE) {
+        return None;
+    }
+    Some((lower, upper))
+}
+
+// 深さdepthのサンプルから空きマス数ごとの係数を当てはめる
+// サンプルが少ない段階のために前後WINDOWマスのサンプルもまとめて使う
+fn fit_stages(samples: &[ProbCutSample], depth: u8) -> Vec<CutParams> {
+    const WINDOW: usize = 2;
+    const MIN_SAMPLES: usize = 8;
+
+    let mut fitted: Vec<Option<CutParams>> = vec![None; STAGE_COUNT];
+    for (empties, slot) in fitted.iter_mut().enumerate() {
+        let stage_samples: Vec<&ProbCutSample> = samples.iter()
+            .filter(|s| s.depth == depth && (s.empties as usize).abs_diff(empties) <= WINDOW)
+            .collect();
+        if stage_samples.len() >= MIN_SAMPLES {
+            *slot = Some(fit_linear(&stage_samples));
+        }
+    }
+
+    // 当てはめられなかった段階は最も近い段階の係数を使う
+    (0..STAGE_COUNT).map(|empties| {
+        (0..STAGE_COUNT)
+            .filter_map(|e| fitted[e].map(|c| (e.abs_diff(empties), c)))
+            .min_by_key(|&(d, _)| d)
+            .map(|(_, c)| c)
+            .unwrap_or_default()
+    }).collect()
+}
+
+fn fit_linear(samples: &[&ProbCutSample]) -> CutParams {
+    let n = samples.len() as f64;
+    let mean_x = samples.iter().map(|s| s.shallow as f64).sum::<f64>() / n;
+    let mean_y = samples.iter().map(|s| s.deep as f64).sum::<f64>() / n;
+    let mut sxx = 0.0;
+    let mut sxy = 0.0;
+    for s in samples {
+        let dx = s.shallow as f64 - mean_x;
+        sxx += dx * dx;
+        sxy += dx * (s.deep as f64 - mean_y);
+    }
+    let slope = if sxx < 1e-9 { 0.0 } else { sxy / sxx };
+    let intercept = mean_y - slope * mean_x;
+    let variance = samples.iter()
+        .map(|s| (s.deep as f64 - slope * s.shallow as f64 - intercept).powi(2))
+        .sum::<f64>() / n;
+    CutParams {
+        slope: slope as f32,
+        intercept: intercept as f32,
+        sigma: variance.sqrt() as f32,
+    }
+}
+
+// 局面ごとに評価関数kindで深さ1..=MAX_DEPTHの値を求めてサンプルにする
+// 深い方の値はProbCutなしで求める
+// 勝敗が決まった値は予測に使わないので除く
+pub fn collect_samples(positions: &[Board], kind: EvalKind) -> Vec<ProbCutSample> {
+    let mut engine = engine::default_engine().duplicate();
+    engine.set_eval_kind(kind);
+    engine.set_selectivity(false);
+
+    let mut samples = Vec::new();
+    for (i, board) in positions.iter().enumerate() {
+        let values = engine.search_values(board, MAX_DEPTH);
+        let empties = (64 - board.get_turn()) as u8;
+        for depth in MIN_DEPTH..=MAX_DEPTH {
+            if values.len() < depth as usize {
+                break;
+            }
+            let shallow = values[shallow_depth(depth) as usize - 1];
+            let deep = values[depth as usize - 1];
+            if shallow.abs() < WIN_SCORE && deep.abs() < WIN_SCORE {
+                samples.push(ProbCutSample { depth, empties, shallow, deep });
+            }
+        }
+        if (i + 1) % 100 == 0 {
+            eprintln!("{} positions, {} samples", i + 1, samples.len());
+        }
+    }
+
+    samples
+}
+
+// 空きマスがENDGAME_MIN_EMPTIES..=ENDGAME_FIT_EMPTIESの局面を完全に読んでサンプルにする
+pub fn collect_endgame_samples(positions: &[Board]) -> Vec<ProbCutSample> {
+    let time_manager = TimeManager::new(u64::MAX / 2);
+    let table = solver::SolverTable::new(solver::SOLVER_TABLE_BITS);
+    let threads = engine::default_engine().solver_threads();
+    let endgame: Vec<&Board> = positions.iter()
+        .filter(|b| (ENDGAME_MIN_EMPTIES..=ENDGAME_FIT_EMPTIES).contains(&(64 - b.get_turn() as u32)))
+        .collect();
+
+    let mut samples = Vec::new();
+    for (i, board) in endgame.iter().enumerate() {
+        let shallow = solver::shallow_search(board, f32::NEG_INFINITY, f32::INFINITY, ENDGAME_SHALLOW_DEPTH);
+        let Some((score, _)) = solver::solve_exact(board, &table, &time_manager, threads) else {
+            continue;
+        };
+        samples.push(ProbCutSample {
+            depth: EXACT_DEPTH,
+            empties: (64 - board.get_turn()) as u8,
+            shallow,
+            deep: score as f32,
+        });
+        if (i + 1) % 10 == 0 {
+            eprintln!("{} / {} endgame positions solved", i + 1, endgame.len());
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Book;
+    use crate::engine::{Engine, Logger};
+    use crate::options::EngineOptions;
+    use crate::play::SearchLimits;
+
+    #[test]
+    fn test_fit_probcut() {
+        assert_eq!(shallow_depth(4), 2);
+        assert_eq!(shallow_depth(7), 3);
+        assert_eq!(shallow_depth(8), 4);
+
+        // deep = 2 * shallow + 1 ± 3
+        let samples: Vec<ProbCutSample> = (MIN_DEPTH..=MAX_DEPTH)
+            .flat_map(|depth| (0..STAGE_COUNT as u8).flat_map(move |empties| {
+                (-10..=10).map(move |x| ProbCutSample {
+                    depth,
+                    empties,
+                    shallow: x as f32,
+                    deep: (2 * x + 1) as f32 + if x % 2 == 0 { 3.0 } else { -3.0 },
+                })
+            }))
+            .collect();
+        let probcut = ProbCut::fit(&samples);
+        let p = probcut.params(5, 30);
+        assert!((p.slope - 2.0).abs() < 0.1 && (p.intercept - 1.0).abs() < 0.5, "{p:?}");
+        assert!((p.sigma - 3.0).abs() < 0.1, "{p:?}");
+        // 深すぎる探索は最大の深さの係数を使う
+        assert_eq!(probcut.params(20, 30), probcut.params(MAX_DEPTH, 30));
+
+        // beta = 10, t = 1.0: 浅い探索が(10 + 3 - 1) / 2 = 6以上なら打ち切る
+        let (lower, upper) = probcut.bounds(5, 30, -10.0, 10.0, 1.0).unwrap();
+        assert!((upper - 6.0).abs() < 0.3 && (lower + 7.0).abs() < 0.3, "{lower} {upper}");
+        assert!(probcut.bounds(5, 30, -10.0, f32::INFINITY, 1.0).is_none());
+        // 完全読みのサンプルがなければ実質的に打ち切らない
+        assert_eq!(*probcut.endgame_params(12), CutParams::default());
+
+        let restored = ProbCut::from_json(&probcut.to_json()).unwrap();
+        assert_eq!(restored, probcut);
+    }
+
+    // エンジンは評価関数に合わせて同梱の係数を使う
+    #[test]
+    fn test_probcut_file() {
+        for kind in [EvalKind::Network, EvalKind::Linear] {
+            let engine = Engine::with_options(EngineOptions { hash_mb: 1, evaluator: kind, ..EngineOptions::default() });
+            let probcut = engine.probcut();
+            assert_eq!(probcut, &*for_eval(kind));
+            assert_eq!(probcut.params.len(), (MAX_DEPTH - MIN_DEPTH + 1) as usize);
+            assert!(probcut.params.iter().all(|row| row.len() == STAGE_COUNT));
+            assert_eq!(probcut.endgame.len(), STAGE_COUNT);
+            assert_eq!(engine.options().probcut_confidence, DEFAULT_CONFIDENCE);
+        }
+        assert_ne!(for_eval(EvalKind::Network), for_eval(EvalKind::Linear));
+
+        // 評価関数を切り替えると係数も切り替わる
+        let mut engine = Engine::with_cache_size_mb(1);
+        engine.set_option("evaluator", "linear").unwrap();
+        assert_eq!(engine.probcut(), &*for_eval(EvalKind::Linear));
+        engine.set_eval_kind(EvalKind::Network);
+        assert_eq!(engine.probcut(), &*for_eval(EvalKind::Network));
+    }
+
+    // 係数はエンジンごとに差し替えられ、打ち切らない係数なら打ち切りを切った探索と同じになる
+    #[test]
+    fn test_engine_probcut() {
+        crate::database::initialize_tables();
+        let board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+        let limits = SearchLimits { max_depth: Some(6), ..SearchLimits::default() };
+        let engine = |selectivity: bool, probcut: Option<ProbCut>| {
+            let mut engine = Engine::with_options(EngineOptions { hash_mb: 1, threads: 1, selectivity, ..EngineOptions::default() });
+            engine.set_logger(Logger::Silent);
+            engine.set_book(Arc::new(Book::default()));
+            if let Some(probcut) = probcut {
+                engine.set_probcut(Arc::new(probcut));
+            }
+            engine
+        };
+        let nodes = |engine: &Engine| engine.search(&board, &limits, 0.0).1.nodes;
+
+        let without_cuts = nodes(&engine(false, None));
+        assert!(nodes(&engine(true, None)) < without_cuts);
+        assert_eq!(nodes(&engine(true, Some(ProbCut::fit(&[])))), without_cuts);
+    }
+}
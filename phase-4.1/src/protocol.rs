@@ -0,0 +1,147 @@
+/*
+テキストプロトコル
+1行に1つのコマンドを読み、応答を行で返す
+  options                           設定の一覧(option name ... の行)
+  setoption name NAME value VALUE   設定を変える
+  getoption name NAME               設定の今の値
+  position POSITION                 盤面(X/O/-の64文字で手番を省略するとXの手番、Edax/OBFの"64文字 手番"も読む)
+  go [depth N] [nodes N] [time MS]  探索して bestmove を返す(制限がなければ1秒)
+  quit
+*/
+
+use crate::engine::Engine;
+use crate::game::square_name;
+use crate::notation;
+use crate::options::OPTIONS;
+use crate::play::{Board, SearchLimits};
+
+use std::io::{self, BufRead, Write};
+
+// 制限を指定しないgoの時間
+const DEFAULT_GO_TIME_MS: u64 = 1000;
+
+pub struct Session {
+    engine: Engine,
+    board: Option<Board>,
+}
+
+impl Session {
+    pub fn new(engine: Engine) -> Self {
+        Self { engine, board: None }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    // 1行を実行して応答の行を返す
+    pub fn execute(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => Ok(vec![]),
+            ["options"] => Ok(OPTIONS.iter().map(|spec| spec.to_string()).collect()),
+            ["setoption", "name", name, "value", value] => {
+                self.engine.set_option(name, value)?;
+                Ok(vec![])
+            }
+            ["getoption", "name", name] => match self.engine.get_option(name) {
+                Some(value) => Ok(vec![format!("option {} value {}", name, value)]),
+                None => Err(format!("Unknown option: {}", name)),
+            },
+            ["position", position @ ..] => {
+                let board = notation::parse_board(&position.join(" ")).map_err(|e| format!("Invalid position: {}", e))?;
+                self.board = Some(board);
+                Ok(vec![])
+            }
+            ["go", args @ ..] => self.go(args),
+            _ => Err(format!("Unknown command: {}", line.trim())),
+        }
+    }
+
+    fn go(&mut self, args: &[&str]) -> Result<Vec<String>, String> {
+        let board = self.board.ok_or("No position")?;
+        let mut limits = SearchLimits { solve_empties: self.engine.options().solve_empties, ..SearchLimits::default() };
+        for pair in args.chunks(2) {
+            let [name, value] = pair else {
+                return Err(format!("Missing value for {}", pair[0]));
+            };
+            let invalid = || format!("Invalid value for {}: {}", name, value);
+            match *name {
+                "depth" => limits.max_depth = Some(value.parse().map_err(|_| invalid())?),
+                "nodes" => limits.max_nodes = Some(value.parse().map_err(|_| invalid())?),
+                "time" => limits.max_time_ms = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(format!("Unknown limit: {}", name)),
+            }
+        }
+        if limits.max_depth.is_none() && limits.max_nodes.is_none() && limits.max_time_ms.is_none() {
+            limits.max_time_ms = Some(DEFAULT_GO_TIME_MS);
+        }
+
+        let (mv, stats) = self.engine.search(&board, &limits, 0.0);
+        Ok(vec![format!("bestmove {} depth {} seldepth {} nodes {} time {} nps {}",
+            square_name(mv as u8), stats.depth, stats.selective_depth, stats.nodes, stats.time_ms, stats.nps)])
+    }
+}
+
+// quitか入力の終わりまで読み続ける
+// エラーは"error: ..."の行で返し、続ける
+pub fn run(engine: Engine, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut session = Session::new(engine);
+    for line in input.lines() {
+        let line = line?;
+        if line.trim() == "quit" {
+            break;
+        }
+        match session.execute(&line) {
+            Ok(lines) => {
+                for l in lines {
+                    writeln!(output, "{}", l)?;
+                }
+            }
+            Err(e) => writeln!(output, "error: {}", e)?,
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{self, Book};
+    use crate::engine::Logger;
+    use crate::options::EngineOptions;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_session() {
+        database::initialize_tables();
+        let mut engine = Engine::with_options(EngineOptions { hash_mb: 1, threads: 1, ..EngineOptions::default() });
+        engine.set_logger(Logger::Silent);
+        engine.set_book(Arc::new(Book::default()));
+        let mut session = Session::new(engine);
+
+        assert_eq!(session.execute("options").unwrap().len(), OPTIONS.len());
+        session.execute("setoption name evaluator value linear").unwrap();
+        session.execute("setoption name max_depth value 3").unwrap();
+        assert_eq!(session.execute("getoption name max_depth").unwrap(), vec!["option max_depth value 3"]);
+        assert!(session.execute("setoption name max_depth value 0").is_err());
+        assert!(session.execute("setoption name no_such_option value 1").is_err());
+        assert!(session.execute("go depth 2").is_err());
+
+        // 開始局面の合法手はd3, c4, f5, e6
+        let start = "---------------------------OX------XO---------------------------";
+        session.execute(&format!("position {}", start)).unwrap();
+        let response = session.execute("go depth 2").unwrap();
+        let mv = response[0].split_whitespace().nth(1).unwrap();
+        assert!(["d3", "c4", "f5", "e6"].contains(&mv), "{}", response[0]);
+        assert!(session.execute("go depth").is_err());
+        // Edax/OBFの形式で白の手番
+        session.execute(&format!("position {} O", start)).unwrap();
+        let response = session.execute("go depth 2").unwrap();
+        let mv = response[0].split_whitespace().nth(1).unwrap();
+        assert!(["e3", "f4", "c5", "d6"].contains(&mv), "{}", response[0]);
+        assert!(session.execute("position XO").is_err());
+        assert!(session.execute("jump").is_err());
+    }
+}
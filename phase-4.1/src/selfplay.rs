@@ -0,0 +1,101 @@
+/*
+自己対戦による学習用局面の生成
+random_plies手までランダムに打ち、その後は1手読みで進めて
+空きマスがsolve_empties以下になったら完全読みの最善手で終局まで打つ
+*/
+
+use crate::engine;
+use crate::play::{Board, TimeManager};
+use crate::solver::{solve_exact, SolverTable, SOLVER_TABLE_BITS};
+
+use rand::Rng;
+use std::io::{self, BufRead, Write};
+
+// 1局分の(局面, 手番側から見た最終石差)を返す
+// パスしかない局面は含めない
+pub fn play_game<R: Rng>(rng: &mut R, random_plies: usize, solve_empties: usize, time_manager: &TimeManager) -> Vec<(Board, i8)> {
+    let mut board = Board {
+        my_board: 0x0000000810000000,
+        opponent_board: 0x0000001008000000,
+    };
+    // (盤面, 初期手番側から見て手番側か)
+    let mut positions: Vec<(Board, bool)> = Vec::new();
+    let mut side = true;
+    let mut ply = 0;
+    let table = SolverTable::new(SOLVER_TABLE_BITS);
+    let threads = engine::default_engine().solver_threads();
+
+    let final_diff = loop {
+        let moves = board.get_valid_moves();
+        if moves == 0 {
+            let mut t = board.clone();
+            t.change_turn();
+            if t.get_valid_moves() == 0 {
+                let diff = board.my_board.count_ones() as i8 - board.opponent_board.count_ones() as i8;
+                break if side { diff } else { -diff };
+            }
+            board = t;
+            side = !side;
+            continue;
+        }
+
+        positions.push((board, side));
+        let mv = if 64 - board.get_turn() <= solve_empties {
+            // 以降は両者最善で進むので、終局の石差が読み切りの値になる
+            solve_exact(&board, &table, time_manager, threads).unwrap().1
+        } else if ply < random_plies {
+            let k = rng.gen_range(0..moves.count_ones());
+            (0..64u8).filter(|&m| moves & (1 << m) != 0).nth(k as usize).unwrap()
+        } else {
+            // 相手から見た評価が最も低くなる手
+            (0..64u8)
+                .filter(|&m| moves & (1 << m) != 0)
+                .map(|m| {
+                    let mut t = board.clone();
+                    t.do_move(m);
+                    t.change_turn();
+                    (t.get_eval(), m)
+                })
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+                .unwrap().1
+        };
+
+        board.do_move(mv);
+        board.change_turn();
+        side = !side;
+        ply += 1;
+    };
+
+    positions.into_iter()
+        .map(|(b, s)| (b, if s { final_diff } else { -final_diff }))
+        .collect()
+}
+
+// 学習用データを "my_board opponent_board disc_diff" (盤面は16進) の行で書き出す
+pub fn write_dataset<W: Write>(out: &mut W, positions: &[(Board, i8)]) -> io::Result<()> {
+    for (b, disc_diff) in positions {
+        writeln!(out, "{:016x} {:016x} {}", b.my_board, b.opponent_board, disc_diff)?;
+    }
+    Ok(())
+}
+
+// write_datasetの出力を読み込む
+pub fn read_dataset<R: BufRead>(reader: R) -> io::Result<Vec<(Board, i8)>> {
+    let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid dataset line: {}", line));
+    let mut positions = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(invalid(&line));
+        }
+        let my_board = u64::from_str_radix(fields[0], 16).map_err(|_| invalid(&line))?;
+        let opponent_board = u64::from_str_radix(fields[1], 16).map_err(|_| invalid(&line))?;
+        let disc_diff = fields[2].parse().map_err(|_| invalid(&line))?;
+        positions.push((Board { my_board, opponent_board }, disc_diff));
+    }
+    Ok(positions)
+}
@@ -1,77 +1,147 @@
+use crate::database;
+use crate::linear_eval::LINEAR_EVAL;
+use crate::parity;
 use crate::play::{Board, TimeManager};
+use crate::probcut::{self, ProbCut};
 use itertools::Itertools;
+use serde::{Serialize, Deserialize};
 use std::io::{self, Write};
-use std::collections::HashMap;
-use std::sync::LazyLock;
-use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, AtomicI8, AtomicI32};
+
+// 読み切りの置換表
+// 手番側から見た最終石差の範囲、最善手、読んだ段階の確からしさ(%)を持つ
+// 勝敗の読み切りも石差の範囲(勝ちなら1以上など)として同じ表に書く
+// 並列読みで複数スレッドから同時に読み書きするため、ロックは使わない
+// 盤面はデータとのXORで書いておき、読み出し時に照合して書き込み途中のエントリや別の局面を捨てる
+// エンジンごとに持つ
+#[cfg(not(target_arch = "wasm32"))]
+pub const SOLVER_TABLE_BITS: u32 = 20;
+#[cfg(target_arch = "wasm32")]
+pub const SOLVER_TABLE_BITS: u32 = 18;
 
 #[derive(Default)]
-pub struct IdentityHasher {
-    value: u64,
+struct SolverEntry {
+	my_board: AtomicU64,
+	opponent_board: AtomicU64,
+	data: AtomicU64,
 }
 
-impl Hasher for IdentityHasher {
-    fn finish(&self) -> u64 {
-        self.value
-    }
-
-    fn write(&mut self, bytes: &[u8]) {
-        // u64のバイト列をそのまま使用（恒等写像）
-        if bytes.len() >= 8 {
-            self.value = u64::from_ne_bytes([
-                bytes[0], bytes[1], bytes[2], bytes[3],
-                bytes[4], bytes[5], bytes[6], bytes[7]
-            ]);
-        }
-    }
-
-    fn write_u64(&mut self, i: u64) {
-        // phase-2のハッシュ関数を適用する場合
-        let mut key = i;
-        key = (key ^ (key >> 30)) ^ 0xbf58476d1ce4e5b9;
-        key = (key ^ (key >> 27)) ^ 0x94d049bb133111eb;
-        key ^= key >> 31;
-        self.value = key;
-    }
+pub struct SolverTable {
+	entries: Vec<SolverEntry>,
+	mask: u64,
 }
 
-#[derive(Default)]
-pub struct MyHash;
+// 置換表の値、最終石差はlower以上upper以下
+// 確からしさが100%未満の値は選択的読み切りで打ち切った枝を含む
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolverBound {
+	pub lower: i8,
+	pub upper: i8,
+	pub mv: u8,
+	pub confidence: u8,
+}
 
-impl BuildHasher for MyHash {
-    type Hasher = IdentityHasher;
+impl SolverBound {
+	// 勝敗の読み切りの結果(1: 勝ち, 0: 引き分け, -1: 負け)
+	fn from_wld((res, mv): (i8, u8)) -> Self {
+		let (lower, upper) = match res {
+			1 => (1, 64),
+			0 => (0, 0),
+			_ => (-64, -1),
+		};
+		SolverBound { lower, upper, mv, confidence: 100 }
+	}
 
-    fn build_hasher(&self) -> Self::Hasher {
-        IdentityHasher::default()
-    }
+	// 勝敗が決まっていれば勝敗の読み切りの結果にする、負けの手は64
+	fn to_wld(self) -> Option<(i8, u8)> {
+		if self.confidence < 100 {
+			None
+		} else if self.lower > 0 {
+			Some((1, self.mv))
+		} else if self.upper < 0 {
+			Some((-1, 64))
+		} else if self.lower == 0 && self.upper == 0 {
+			Some((0, self.mv))
+		} else {
+			None
+		}
+	}
 }
 
-// カスタムハッシャーを使用するHashMap型
-type Cache = HashMap<(u64, u64), (i8, u8), MyHash>;
+impl SolverTable {
+	pub fn new(bits: u32) -> Self {
+		SolverTable {
+			entries: (0..1usize << bits).map(|_| SolverEntry::default()).collect(),
+			mask: (1 << bits) - 1,
+		}
+	}
+
+	fn hash(board: &Board) -> u64 {
+		let mut key = board.my_board ^ board.opponent_board.rotate_left(32).wrapping_mul(0x9e3779b97f4a7c15);
+		key = (key ^ (key >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		key = (key ^ (key >> 27)).wrapping_mul(0x94d049bb133111eb);
+		key ^ (key >> 31)
+	}
 
-static CACHE_SOLVER: LazyLock<std::sync::Mutex<Cache>> = LazyLock::new(|| {
-    std::sync::Mutex::new(HashMap::with_hasher(MyHash))
-});
+	pub fn get(&self, board: &Board) -> Option<SolverBound> {
+		let entry = &self.entries[(Self::hash(board) & self.mask) as usize];
+		let data = entry.data.load(Ordering::Relaxed);
+		if data == 0
+			|| entry.my_board.load(Ordering::Relaxed) ^ data != board.my_board
+			|| entry.opponent_board.load(Ordering::Relaxed) ^ data != board.opponent_board {
+			return None;
+		}
+		Some(SolverBound {
+			lower: (data >> 16) as u8 as i8,
+			upper: (data >> 8) as u8 as i8,
+			mv: data as u8,
+			confidence: (data >> 24) as u8,
+		})
+	}
+
+	// 常に上書きする
+	// 上位ビットにハッシュ値を入れておき、別々の書き込みが混ざったエントリを照合で弾けるようにする
+	// 確からしさは1以上なので、書いたエントリのデータは0にならない
+	pub fn insert(&self, board: &Board, bound: SolverBound) {
+		let key = Self::hash(board);
+		let entry = &self.entries[(key & self.mask) as usize];
+		let data = (key & !0xffffffff) | (bound.confidence as u64) << 24
+			| (bound.lower as u8 as u64) << 16 | (bound.upper as u8 as u64) << 8 | bound.mv as u64;
+		entry.my_board.store(board.my_board ^ data, Ordering::Relaxed);
+		entry.opponent_board.store(board.opponent_board ^ data, Ordering::Relaxed);
+		entry.data.store(data, Ordering::Relaxed);
+	}
 
-pub fn get_cache() -> std::sync::MutexGuard<'static, Cache> {
-    CACHE_SOLVER.lock().unwrap()
+	pub fn clear(&self) {
+		for entry in &self.entries {
+			entry.data.store(0, Ordering::Relaxed);
+		}
+	}
 }
 
-pub fn solve(board: &Board, time_manager: &TimeManager) -> (i8, u8) {
-	let moves = board.get_valid_moves();
-	unsafe {
-		return _solve(board, moves, time_manager);
+// threadsは読むスレッド数(エンジンの設定solver_threadsから決める)、2以上ならルートで手を分けて並列に読む
+// WebAssembly版はスレッドを使わない
+pub fn solve(board: &Board, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> (i8, u8) {
+	#[cfg(target_arch = "wasm32")]
+	let _ = threads;
+	#[cfg(not(target_arch = "wasm32"))]
+	if threads > 1 {
+		return solve_parallel(board, table, time_manager, threads);
 	}
+	_solve(board, board.get_valid_moves(), table, time_manager)
 }
 
-fn _solve(board: &Board, moves: u64, time_manager: &TimeManager) -> (i8, u8) {
-	if let Some(p) = get_cache().get(&(board.my_board, board.opponent_board)) {
-		return *p;
+fn _solve(board: &Board, moves: u64, table: &SolverTable, time_manager: &TimeManager) -> (i8, u8) {
+	if let Some(p) = table.get(board).and_then(SolverBound::to_wld) {
+		return p;
 	}
 
 	if time_manager.should_stop() {
         return (-2, 64);
     }
+	time_manager.count_solver_node();
 
     if moves == 0 {
         let mut t = board.clone();
@@ -81,59 +151,44 @@ fn _solve(board: &Board, moves: u64, time_manager: &TimeManager) -> (i8, u8) {
             let my_count = t.my_board.count_ones();
             let op_count = t.opponent_board.count_ones();
             if my_count > op_count {
-                get_cache().insert((board.my_board, board.opponent_board), (1, 64));
+                table.insert(board, SolverBound::from_wld((1, 64)));
                 return (1, 64);
             }
             else if my_count < op_count {
-                get_cache().insert((board.my_board, board.opponent_board), (-1, 64));
+                table.insert(board, SolverBound::from_wld((-1, 64)));
                 return (-1, 64);
             }
             else {
-                get_cache().insert((board.my_board, board.opponent_board), (0, 64));
+                table.insert(board, SolverBound::from_wld((0, 64)));
                 return (0, 64);
             }
         }
-        let (res, _) = _solve(&t, new_moves, time_manager);
+        let (res, _) = _solve(&t, new_moves, table, time_manager);
+		if res == -2 {
+			return (-2, 64);
+		}
 
-		get_cache().insert((board.my_board, board.opponent_board), (-res, 64));
+		table.insert(board, SolverBound::from_wld((-res, 64)));
         return (-res, 64);
     }
 
-	let count_stones = board.my_board.count_ones() + board.opponent_board.count_ones();
-	let mut ordered_moves: Vec<(u8, u8, Board, u64)> = {
-		let mut vec = Vec::new();
-		let mut left = moves;
-		while left != 0 {
-			let m = left.trailing_zeros() as u8;
-			left &= !(1 << m);
-			let mut t = board.clone();
-			t.do_move(m);
-			t.change_turn();
-			let next_moves = t.get_valid_moves();
-			let eval = next_moves.count_ones() as u8;
-			vec.push((eval, m, t, next_moves));
-		}
-		vec.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-		vec
-	};
-	
+	let ordered_moves = order_moves(board, moves);
+	let child_empties = 63 - board.get_turn() as u32;
 	let mut best_move = 64;
-	for (_, mv, next_board, next_moves) in ordered_moves {
-		let (res, _res_move) = {
-			// 61で1個開き
-			if count_stones <= 59 {
-				_solve(&next_board, next_moves, time_manager)
-			}
-			else {
-				_solve_leaf(&next_board, next_moves, time_manager)
-			}
-		};
+	// 子局面は盤面を写さず、打って読んだ後に戻す
+	let mut next_board = *board;
+	for (_, mv, next_moves) in ordered_moves {
+		let undo = next_board.make_move(mv);
+		next_board.change_turn();
+		let res = solve_child(&next_board, next_moves, child_empties, table, time_manager);
+		next_board.change_turn();
+		next_board.unmake_move(undo);
 		match res {
 			1 => {
 				continue;
 			}
 			-1 => {
-				get_cache().insert((board.my_board, board.opponent_board), (1, mv));
+				table.insert(board, SolverBound::from_wld((1, mv)));
 				return (1, mv);
 			}
 			0 => {
@@ -147,160 +202,759 @@ fn _solve(board: &Board, moves: u64, time_manager: &TimeManager) -> (i8, u8) {
 	}
 
 	if best_move == 64 {
-		get_cache().insert((board.my_board, board.opponent_board), (-1, 64));
+		table.insert(board, SolverBound::from_wld((-1, 64)));
 		(-1, 64)
 	}
 	else {
-		get_cache().insert((board.my_board, board.opponent_board), (0, best_move));
+		table.insert(board, SolverBound::from_wld((0, best_move)));
 		(0, best_move)
 	}
 }
 
+// 子局面の勝敗(子の手番側から見た値)
+fn solve_child(next_board: &Board, next_moves: u64, child_empties: u32, table: &SolverTable, time_manager: &TimeManager) -> i8 {
+	if let Some(res) = stability_result(next_board) {
+		res
+	}
+	// 残り4マス以下は専用の読み切り
+	else if child_empties > LAST_EMPTIES {
+		_solve(next_board, next_moves, table, time_manager).0
+	}
+	else {
+		solve_last(next_board, -1, 1).signum()
+	}
+}
 
-fn _solve_leaf(board: &Board, moves: u64, time_manager: &TimeManager) -> (i8, u8) {
-	if time_manager.should_stop() {
-        return (-2, 64);
-    }
+// 相手の着手可能数が少ない順、同程度なら奇数領域の手を先に並べる
+// (優先度, 手, 子局面の着手可能位置)
+fn order_moves(board: &Board, moves: u64) -> Vec<(u32, u8, u64)> {
+	let odd = parity::odd_region_squares(!(board.my_board | board.opponent_board));
+	let mut ordered_moves = Vec::new();
+	let mut t = *board;
+	let mut left = moves;
+	while left != 0 {
+		let m = left.trailing_zeros() as u8;
+		left &= left - 1;
+		let undo = t.make_move(m);
+		t.change_turn();
+		let next_moves = t.get_valid_moves();
+		t.change_turn();
+		t.unmake_move(undo);
+		ordered_moves.push((endgame_move_key(next_moves, m, odd), m, next_moves));
+	}
+	ordered_moves.sort_by_key(|a| a.0);
+	ordered_moves
+}
 
-    if moves == 0 {
-        let mut t = board.clone();
-        t.change_turn();
-		let new_moves = t.get_valid_moves();
-        if new_moves == 0 {
-            let my_count = t.my_board.count_ones();
-            let op_count = t.opponent_board.count_ones();
-            if my_count > op_count {
-                return (1, 64);
-            }
-            else if my_count < op_count {
-                return (-1, 64);
-            }
-            else {
-                return (0, 64);
-            }
-        }
-        let (res, _) = _solve(&t, new_moves, time_manager);
+// mvを打って相手の手番にした子局面(並列読みのルートでスレッドごとに作る)
+#[cfg(not(target_arch = "wasm32"))]
+fn child_board(board: &Board, mv: u8) -> Board {
+	let mut t = *board;
+	t.make_move(mv);
+	t.change_turn();
+	t
+}
 
-        return (-res, 64);
-    }
+// 完全読みの手の並び替えの優先度(小さいほど先に読む)
+// 相手の着手可能数が少ない手(fastest-first)を優先し、同程度なら奇数領域(odd)の手を先にする
+fn endgame_move_key(next_moves: u64, mv: u8, odd: u64) -> u32 {
+	next_moves.count_ones() * 2 + ((odd >> mv) & 1 == 0) as u32
+}
 
-	let count_stones = board.my_board.count_ones() + board.opponent_board.count_ones();
-	let mut ordered_moves: Vec<(u8, Board, u64)> = {
-		let mut vec = Vec::new();
-		let mut left = moves;
-		while left != 0 {
-			let m = left.trailing_zeros() as u8;
-			left &= !(1 << m);
-			let mut t = board.clone();
-			t.do_move(m);
-			t.change_turn();
-			let next_moves = t.get_valid_moves();
-			vec.push((m, t, next_moves));
-		}
-		vec.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-		vec
-	};
+// どちらかの確定石が過半数なら勝敗が決まる
+fn stability_result(board: &Board) -> Option<i8> {
+	if board.my_board.count_ones() > 32 && database::get_stable(board).count_ones() > 32 {
+		return Some(1);
+	}
+	if board.opponent_board.count_ones() > 32 {
+		let mut t = board.clone();
+		t.change_turn();
+		if database::get_stable(&t).count_ones() > 32 {
+			return Some(-1);
+		}
+	}
+	None
+}
 
-	
-	let mut best_move = 64;
-	for (mv, next_board, next_moves) in ordered_moves {
-		let (res, _res_move) = {
-			// 61で1個開き
-			if count_stones <= 61 {
-				_solve_leaf(&next_board, next_moves, time_manager)
+// 残り4マス以下の読み切り
+// 着手リストの生成や盤面の複製をせず、database::get_flipperで返る石を直接求める
+const LAST_EMPTIES: u32 = 4;
+
+// 返せない場合の値(どの石差よりも小さい)
+const NO_MOVE_SCORE: i8 = -127;
+
+#[inline]
+fn flip(p: u64, o: u64, x: u8) -> u64 {
+	database::get_flipper(&Board { my_board: p, opponent_board: o }, x as usize)
+}
+
+#[inline]
+fn disc_diff(p: u64, o: u64) -> i8 {
+	p.count_ones() as i8 - o.count_ones() as i8
+}
+
+// 残り1マス
+fn last_1(p: u64, o: u64, x: u8) -> i8 {
+	let f = flip(p, o, x);
+	if f != 0 {
+		return disc_diff(p | 1 << x | f, o ^ f);
+	}
+	// パスして相手が打つ
+	let f = flip(o, p, x);
+	if f != 0 {
+		return disc_diff(p ^ f, o | 1 << x | f);
+	}
+	disc_diff(p, o)
+}
+
+// 残り2マス
+fn last_2(p: u64, o: u64, alpha: i8, beta: i8, x1: u8, x2: u8, passed: bool) -> i8 {
+	let mut best = NO_MOVE_SCORE;
+	let f = flip(p, o, x1);
+	if f != 0 {
+		best = -last_1(o ^ f, p | 1 << x1 | f, x2);
+		if best >= beta {
+			return best;
+		}
+	}
+	let f = flip(p, o, x2);
+	if f != 0 {
+		best = best.max(-last_1(o ^ f, p | 1 << x2 | f, x1));
+	}
+
+	if best == NO_MOVE_SCORE {
+		if passed {
+			return disc_diff(p, o);
+		}
+		return -last_2(o, p, -beta, -alpha, x1, x2, true);
+	}
+	best
+}
+
+// 残り3マス
+fn last_3(p: u64, o: u64, alpha: i8, beta: i8, x: [u8; 3], passed: bool) -> i8 {
+	let mut alpha = alpha;
+	let mut best = NO_MOVE_SCORE;
+	for (m, rest) in [(x[0], [x[1], x[2]]), (x[1], [x[0], x[2]]), (x[2], [x[0], x[1]])] {
+		let f = flip(p, o, m);
+		if f == 0 {
+			continue;
+		}
+		let v = -last_2(o ^ f, p | 1 << m | f, -beta, -alpha, rest[0], rest[1], false);
+		if v > best {
+			best = v;
+			if v >= beta {
+				return v;
 			}
-			else {
-				_solve_leaf_one(&next_board, next_moves)
+			alpha = alpha.max(v);
+		}
+	}
+
+	if best == NO_MOVE_SCORE {
+		if passed {
+			return disc_diff(p, o);
+		}
+		return -last_3(o, p, -beta, -alpha, x, true);
+	}
+	best
+}
+
+// 残り4マス
+fn last_4(p: u64, o: u64, alpha: i8, beta: i8, x: [u8; 4], passed: bool) -> i8 {
+	let mut alpha = alpha;
+	let mut best = NO_MOVE_SCORE;
+	for i in 0..4 {
+		let m = x[i];
+		let f = flip(p, o, m);
+		if f == 0 {
+			continue;
+		}
+		let mut rest = [0u8; 3];
+		let mut k = 0;
+		for (j, &y) in x.iter().enumerate() {
+			if j != i {
+				rest[k] = y;
+				k += 1;
 			}
-		};
-		match res {
-			1 => {
-				continue;
+		}
+		let v = -last_3(o ^ f, p | 1 << m | f, -beta, -alpha, rest, false);
+		if v > best {
+			best = v;
+			if v >= beta {
+				return v;
 			}
-			-1 => {
-				return (1, mv);
+			alpha = alpha.max(v);
+		}
+	}
+
+	if best == NO_MOVE_SCORE {
+		if passed {
+			return disc_diff(p, o);
+		}
+		return -last_4(o, p, -beta, -alpha, x, true);
+	}
+	best
+}
+
+// 空きマスがLAST_EMPTIES以下の局面を読み切り、手番側から見た最終石差を返す
+// fail-softなので、窓の外の値は限界値になる
+pub fn solve_last(board: &Board, alpha: i8, beta: i8) -> i8 {
+	let empties = !(board.my_board | board.opponent_board);
+	// 象限の偶奇で奇数の側のマスを先に並べる
+	let odd = parity::odd_quadrant_squares(empties);
+	let mut x = [0u8; 4];
+	let mut n = 0;
+	for squares in [empties & odd, empties & !odd] {
+		let mut left = squares;
+		while left != 0 {
+			debug_assert!(n < LAST_EMPTIES as usize, "solve_last called with more than {} empties", LAST_EMPTIES);
+			x[n] = left.trailing_zeros() as u8;
+			left &= left - 1;
+			n += 1;
+		}
+	}
+
+	let (p, o) = (board.my_board, board.opponent_board);
+	match n {
+		0 => disc_diff(p, o),
+		1 => last_1(p, o, x[0]),
+		2 => last_2(p, o, alpha, beta, x[0], x[1], false),
+		3 => last_3(p, o, alpha, beta, [x[0], x[1], x[2]], false),
+		_ => last_4(p, o, alpha, beta, x, false),
+	}
+}
+
+// 選択的読み切りの段階 (確からしさ%, ProbCutで打ち切る予測値からのσ)
+// Edaxの段階に合わせる、100%は完全読み
+pub const SELECTIVITY_LEVELS: [(u8, f32); 6] = [
+	(73, 1.1),
+	(87, 1.5),
+	(95, 2.0),
+	(98, 2.6),
+	(99, 3.3),
+	(100, f32::INFINITY),
+];
+
+// 読み切りの段階、confidenceは確からしさ(%)
+// cutは打ち切りの予測に使うProbCutの係数と予測値からのσ(Noneなら打ち切らない完全読み)
+#[derive(Clone, Copy)]
+struct Level<'a> {
+	confidence: u8,
+	cut: Option<(&'a ProbCut, f32)>,
+}
+
+const EXACT_LEVEL: Level<'static> = Level { confidence: 100, cut: None };
+
+// 石差まで読み切る完全読み
+// 手番側から見た最終石差と最善手を返す、時間切れの場合はNone
+pub fn solve_exact(board: &Board, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> Option<(i8, u8)> {
+	solve_level(board, EXACT_LEVEL, table, time_manager, threads)
+}
+
+fn solve_level(board: &Board, level: Level, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> Option<(i8, u8)> {
+	#[cfg(target_arch = "wasm32")]
+	let _ = threads;
+	#[cfg(not(target_arch = "wasm32"))]
+	if threads > 1 {
+		return solve_exact_parallel(board, level, table, time_manager, threads);
+	}
+	_solve_exact(board, board.get_valid_moves(), -64, 64, level, table, time_manager)
+}
+
+// 読み切りの結果、confidenceは結果が正しい確からしさ(%)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EndgameResult {
+	pub score: i8,
+	pub mv: u8,
+	pub confidence: u8,
+}
+
+// 確からしさconfidence(%)の段階で石差まで読み切る、時間切れの場合はNone
+// 100%未満では浅い探索で最終石差が窓の外に出るとprobcutの係数で予測できる枝を打ち切る
+pub fn solve_selective(board: &Board, confidence: u8, probcut: &ProbCut, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> Option<EndgameResult> {
+	let level = SELECTIVITY_LEVELS.iter()
+		.find(|&&(c, _)| c >= confidence)
+		.map(|&(confidence, sigma)| Level { confidence, cut: sigma.is_finite().then_some((probcut, sigma)) })
+		.unwrap_or(EXACT_LEVEL);
+	let (score, mv) = solve_level(board, level, table, time_manager, threads)?;
+	Some(EndgameResult { score, mv, confidence: level.confidence })
+}
+
+// 確からしさの低い段階から順に読み切り、時間内に終わった最も高い段階の結果を返す
+// 時間切れで中断しても直前の段階の結果が使える
+// 前の段階の置換表の値(最善手と、確からしさの高い段階で読んだ範囲)を使うので、段階ごとに初めから読み直すことはない
+pub fn solve_progressive(board: &Board, probcut: &ProbCut, table: &SolverTable, time_manager: &TimeManager, threads: usize, mut on_level: impl FnMut(&EndgameResult)) -> Option<EndgameResult> {
+	let mut best = None;
+	for &(confidence, _) in SELECTIVITY_LEVELS.iter() {
+		match solve_selective(board, confidence, probcut, table, time_manager, threads) {
+			Some(result) => {
+				on_level(&result);
+				best = Some(result);
 			}
-			0 => {
-				best_move = mv;
+			None => break,
+		}
+	}
+	best
+}
+
+// 選択的読み切りの予測に使う浅い探索
+// 読み切りの速さを落とさないよう置換表を使わず、評価関数の設定によらず線形評価関数で読む
+pub fn shallow_search(board: &Board, alpha: f32, beta: f32, depth: u8) -> f32 {
+	if depth == 0 {
+		return LINEAR_EVAL.eval(board);
+	}
+	let moves = board.get_valid_moves();
+	if moves == 0 {
+		let mut t = board.clone();
+		t.change_turn();
+		if t.get_valid_moves() == 0 {
+			return disc_diff(board.my_board, board.opponent_board) as f32;
+		}
+		return -shallow_search(&t, -beta, -alpha, depth);
+	}
+
+	let mut alpha = alpha;
+	let mut best_score = f32::NEG_INFINITY;
+	let mut t = *board;
+	let mut left = moves;
+	while left != 0 {
+		let m = left.trailing_zeros() as u8;
+		left &= left - 1;
+		let undo = t.make_move(m);
+		t.change_turn();
+		let score = -shallow_search(&t, -beta, -alpha, depth - 1);
+		t.change_turn();
+		t.unmake_move(undo);
+		best_score = best_score.max(score);
+		alpha = alpha.max(score);
+		if alpha >= beta {
+			break;
+		}
+	}
+	best_score
+}
+
+// levelは選択的読み切りの段階
+fn _solve_exact(board: &Board, moves: u64, alpha: i8, beta: i8, level: Level, table: &SolverTable, time_manager: &TimeManager) -> Option<(i8, u8)> {
+	if time_manager.should_stop() {
+		return None;
+	}
+	time_manager.count_solver_node();
+
+	// 同じ段階以上で読んだ範囲は窓を狭めるのに使い、段階によらず置換表の手を先に読む
+	let (mut alpha, mut beta) = (alpha, beta);
+	let mut table_move = 64;
+	if let Some(entry) = table.get(board) {
+		if entry.confidence >= level.confidence {
+			if entry.lower >= beta || entry.lower == entry.upper {
+				return Some((entry.lower, entry.mv));
 			}
-			-2 => {
-				return (-2, 64);
+			if entry.upper <= alpha {
+				return Some((entry.upper, entry.mv));
 			}
-			_ => unreachable!(),
+			alpha = alpha.max(entry.lower);
+			beta = beta.min(entry.upper);
 		}
+		table_move = entry.mv;
 	}
 
-	if best_move == 64 {
-		(-1, 64)
+	// 相手の確定石がs個なら最終石差は64 - 2s以下
+	// 相手の石の数で上限を見積もり、枝刈りできる可能性があるときだけ確定石を数える
+	if 64 - 2 * (board.opponent_board.count_ones() as i8) <= alpha {
+		let mut t = board.clone();
+		t.change_turn();
+		let upper = 64 - 2 * database::get_stable(&t).count_ones() as i8;
+		if upper <= alpha {
+			return Some((upper, 64));
+		}
 	}
-	else {
-		(0, best_move)
+
+	if moves == 0 {
+		let mut t = board.clone();
+		t.change_turn();
+		let new_moves = t.get_valid_moves();
+		if new_moves == 0 {
+			let diff = board.my_board.count_ones() as i8 - board.opponent_board.count_ones() as i8;
+			return Some((diff, 64));
+		}
+		let (res, _) = _solve_exact(&t, new_moves, -beta, -alpha, level, table, time_manager)?;
+		return Some((-res, 64));
 	}
-}
 
+	let ordered_moves = order_moves_with(board, moves, table_move);
 
-fn _solve_leaf_one(board: &Board, moves: u64) -> (i8, u8) {
-	fn judge(board: &Board) -> i8 {
-		let my_count = board.my_board.count_ones();
-		let op_count = board.opponent_board.count_ones();
-		if my_count > op_count {
-			return 1;
+	let alpha_start = alpha;
+	let mut best_score = -65;
+	let mut best_move = 64;
+	let mut next_board = *board;
+	for (_, mv, next_moves) in ordered_moves {
+		let undo = next_board.make_move(mv);
+		next_board.change_turn();
+		let score = solve_exact_child(&next_board, next_moves, -beta, -alpha, level, table, time_manager);
+		next_board.change_turn();
+		next_board.unmake_move(undo);
+		let score = -score?;
+		if score > best_score {
+			best_score = score;
+			best_move = mv;
 		}
-		else if my_count < op_count {
-			return -1;
+		alpha = alpha.max(score);
+		if alpha >= beta {
+			break;
 		}
-		else {
-			return 0;
+	}
+
+	table.insert(board, exact_bound(best_score, best_move, alpha_start, beta, level.confidence));
+	Some((best_score, best_move))
+}
+
+// fail-softの値を窓と比べて置換表に書く範囲にする
+fn exact_bound(score: i8, mv: u8, alpha: i8, beta: i8, confidence: u8) -> SolverBound {
+	let (lower, upper) = if score <= alpha {
+		(-64, score)
+	} else if score >= beta {
+		(score, 64)
+	} else {
+		(score, score)
+	};
+	SolverBound { lower, upper, mv, confidence }
+}
+
+// 置換表の手を先頭にした並び
+fn order_moves_with(board: &Board, moves: u64, table_move: u8) -> Vec<(u32, u8, u64)> {
+	let mut ordered_moves = order_moves(board, moves);
+	if let Some(i) = ordered_moves.iter().position(|&(_, mv, _)| mv == table_move) {
+		ordered_moves[..=i].rotate_right(1);
+	}
+	ordered_moves
+}
+
+fn solve_exact_child(next_board: &Board, next_moves: u64, alpha: i8, beta: i8, level: Level, table: &SolverTable, time_manager: &TimeManager) -> Option<i8> {
+	let child_empties = 64 - next_board.get_turn() as u32;
+	if child_empties <= LAST_EMPTIES {
+		return Some(solve_last(next_board, alpha, beta));
+	}
+
+	// 選択的読み切り
+	// 浅い探索で最終石差が窓の外に出ると予測できれば打ち切る(石差の限界の側は試さない)
+	if let Some((probcut, selectivity)) = level.cut.filter(|_| child_empties >= probcut::ENDGAME_MIN_EMPTIES) {
+		if let Some((lower, upper)) = probcut.endgame_bounds(child_empties as usize, alpha as f32, beta as f32, selectivity) {
+			if beta < 64 && shallow_search(next_board, upper - 1.0, upper, probcut::ENDGAME_SHALLOW_DEPTH) >= upper {
+				return Some(beta);
+			}
+			if alpha > -64 && shallow_search(next_board, lower, lower + 1.0, probcut::ENDGAME_SHALLOW_DEPTH) <= lower {
+				return Some(alpha);
+			}
 		}
 	}
+	Some(_solve_exact(next_board, next_moves, alpha, beta, level, table, time_manager)?.0)
+}
 
-	if moves != 0 {
-		let mut b = board.clone();
-		let mv = moves.trailing_zeros() as u8;
-		b.do_move(mv);
-		return (judge(&b), mv);
+// 並列読み(ネイティブ版のみ)
+// ルートで分割し、先頭の手を1スレッドで読んだ後、残りの手を各スレッドが順に取り合って読む
+// 置換表はスレッド間で共有する
+// どの手を選ぶかは逐次版と同じ規則で決めるので、スレッド数によらず逐次版と同じ結果になる
+
+// 勝敗の並列読み
+// 逐次版と同じく、勝ちなら並び順で最初の勝ちの手、引き分けなら最後の引き分けの手を返す
+#[cfg(not(target_arch = "wasm32"))]
+pub fn solve_parallel(board: &Board, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> (i8, u8) {
+	if let Some(p) = table.get(board).and_then(SolverBound::to_wld) {
+		return p;
 	}
-	else {
-		let mut b = board.clone();
-		b.change_turn();
-		// need refactor
-		let new_move = b.get_valid_moves();
-		if new_move == 0 {
-			return (judge(board), 64);
+	let moves = board.get_valid_moves();
+	if moves == 0 || threads <= 1 {
+		return _solve(board, moves, table, time_manager);
+	}
+
+	let ordered_moves = order_moves(board, moves);
+	let child_empties = 63 - board.get_turn() as u32;
+	// 2は未探索(勝ちの手より後ろは読まない)
+	let results: Vec<AtomicI8> = ordered_moves.iter().map(|_| AtomicI8::new(2)).collect();
+	let (_, mv, next_moves) = &ordered_moves[0];
+	let first = solve_child(&child_board(board, *mv), *next_moves, child_empties, table, time_manager);
+	if first == -2 {
+		return (-2, 64);
+	}
+	if first == -1 {
+		table.insert(board, SolverBound::from_wld((1, *mv)));
+		return (1, *mv);
+	}
+	results[0].store(first, Ordering::Relaxed);
+
+	let next = AtomicUsize::new(1);
+	let win_index = AtomicUsize::new(usize::MAX);
+	std::thread::scope(|scope| {
+		for _ in 0..threads.min(ordered_moves.len() - 1) {
+			scope.spawn(|| loop {
+				let i = next.fetch_add(1, Ordering::Relaxed);
+				if i >= ordered_moves.len() || i > win_index.load(Ordering::Relaxed) {
+					break;
+				}
+				let (_, mv, next_moves) = &ordered_moves[i];
+				let res = solve_child(&child_board(board, *mv), *next_moves, child_empties, table, time_manager);
+				results[i].store(res, Ordering::Relaxed);
+				if res == -1 {
+					win_index.fetch_min(i, Ordering::Relaxed);
+				}
+				if res == -2 {
+					break;
+				}
+			});
 		}
-		let mv = new_move.trailing_zeros() as u8;
-		b.do_move(mv);
-		return (-judge(&b), mv);
+	});
+
+	let results: Vec<i8> = results.into_iter().map(|r| r.into_inner()).collect();
+	// 最初の勝ちの手より前は全て読み終わっている
+	let win = results.iter().position(|&r| r == -1);
+	let searched = &results[..win.map_or(results.len(), |w| w + 1)];
+	if searched.contains(&-2) {
+		return (-2, 64);
+	}
+	let result = if let Some(w) = win {
+		(1, ordered_moves[w].1)
 	}
+	else if let Some(d) = searched.iter().rposition(|&r| r == 0) {
+		(0, ordered_moves[d].1)
+	}
+	else {
+		(-1, 64)
+	};
+	table.insert(board, SolverBound::from_wld(result));
+	result
 }
 
+// 石差の並列読み(選択的読み切りの段階も同じ)
+// 逐次版と同じく、最善値の手のうち並び順で最初の手を返す
+// 最善値と手の順番を1つの値にまとめて共有し、読む手が最善手より前なら同点も区別できるようにαを1下げる
+#[cfg(not(target_arch = "wasm32"))]
+fn solve_exact_parallel(board: &Board, level: Level, table: &SolverTable, time_manager: &TimeManager, threads: usize) -> Option<(i8, u8)> {
+	let moves = board.get_valid_moves();
+	if moves == 0 || threads <= 1 {
+		return _solve_exact(board, moves, -64, 64, level, table, time_manager);
+	}
 
+	// 値が大きいほど良い(石差が同じなら並び順が前の手)
+	fn pack(score: i8, index: usize) -> i32 {
+		(score as i32) << 8 | (255 - index as i32)
+	}
+	fn unpack(key: i32) -> (i8, usize) {
+		((key >> 8) as i8, 255 - (key & 0xff) as usize)
+	}
+
+	let mut table_move = 64;
+	if let Some(entry) = table.get(board) {
+		if entry.confidence >= level.confidence && entry.lower == entry.upper {
+			return Some((entry.lower, entry.mv));
+		}
+		table_move = entry.mv;
+	}
 
-fn str_to_board(s: &String) -> Option<Board> {
-    let mut my_board = 0u64;
-    let mut opponent_board = 0u64;
+	let ordered_moves = order_moves_with(board, moves, table_move);
+	let (_, mv, next_moves) = &ordered_moves[0];
+	let first = -solve_exact_child(&child_board(board, *mv), *next_moves, -64, 64, level, table, time_manager)?;
 
-    for i in 0..64 {
-        let c = s.chars().nth(i)?;
-		// 'X'が手番側、
-		// 'O'が相手側
-		// 自分の実装と逆のため、注意!
-        match c {
-            'X' => my_board |= 1 << i,
-            'O' => opponent_board |= 1 << i,
-            _ => continue,
-        }
-    }
+	let best = AtomicI32::new(pack(first, 0));
+	let next = AtomicUsize::new(1);
+	let timed_out = AtomicBool::new(false);
+	std::thread::scope(|scope| {
+		for _ in 0..threads.min(ordered_moves.len() - 1) {
+			scope.spawn(|| loop {
+				let i = next.fetch_add(1, Ordering::Relaxed);
+				if i >= ordered_moves.len() || timed_out.load(Ordering::Relaxed) {
+					break;
+				}
+				let (best_score, best_index) = unpack(best.load(Ordering::Relaxed));
+				let alpha = if best_index < i { best_score } else { best_score - 1 };
+				let (_, mv, next_moves) = &ordered_moves[i];
+				match solve_exact_child(&child_board(board, *mv), *next_moves, -64, -alpha, level, table, time_manager) {
+					Some(res) if -res > alpha => {
+						best.fetch_max(pack(-res, i), Ordering::Relaxed);
+					}
+					Some(_) => {}
+					None => {
+						timed_out.store(true, Ordering::Relaxed);
+						break;
+					}
+				}
+			});
+		}
+	});
 
-	Some(Board {
-		my_board,
-		opponent_board,
-	})
+	if timed_out.into_inner() {
+		return None;
+	}
+	let (best_score, best_index) = unpack(best.into_inner());
+	let result = (best_score, ordered_moves[best_index].1);
+	table.insert(board, SolverBound { lower: best_score, upper: best_score, mv: result.1, confidence: level.confidence });
+	Some(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::notation;
+
+	// cargo test --release bench_solver -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn bench_solver() {
+		database::initialize_tables();
+		for s in [
+			"-O--XO----O-OXO-XXOOOOOOXXOOXXOOXXOXOOX--XOXOO---OOXX-O-OOO-X---",
+			"XO-O---XOO-O-XXXXOXOXOOX-OXOOXOX-OXOOOOOO-XXOOOO---XOOOOXXXXXXXX",
+			"---O-X--O-OOXX--XOXOXXXO-XOOOXX-XOXOXO-XOXXXXXX-XOXXOO--XXXX-OOO",
+			"-O--------OXXXX-OXXOXXXX-OXOOOO-OOXXOOO-OXXOOOOOO-XXOOO-O-XXXXX-",
+			"XXO-----OXOX----OXXO-XO-OXOXOO--OXOXOO--OOOOXXXXOOOOOOO---OOOOOO",
+			"---OOOOO---XXOOO-O--XOXOOOOOOXO---XOOXO-OXOOOOO-X-OOOOO---OOOXXX",
+			"---O--X--OOOOOXO---XOXX-OOXXOOXX-O-OOXXXXOOOXXXX-OOOOOO--O-XOO-O",
+			"XXX-OX-OX-XXXXXXOOOXOOXX-OXOXOX-OXOOOX--OOOOOOX-OOOO----O-X-----",
+			"OXX------XOXXX-XXXXOX-X-OXOXOXOO-OXXXOOOO-XXOOXO--OXOOOO-OX--O--",
+		] {
+			let board = notation::parse_board(s).unwrap();
+			let time_manager = TimeManager::new(u64::MAX / 2);
+			let table = SolverTable::new(SOLVER_TABLE_BITS);
+			for threads in [1, 2, 4, 8] {
+				table.clear();
+				let start = std::time::Instant::now();
+				let (res, mv) = solve_parallel(&board, &table, &time_manager, threads);
+				let wld = start.elapsed();
+				table.clear();
+				let start = std::time::Instant::now();
+				let (score, exact_mv) = solve_exact_parallel(&board, EXACT_LEVEL, &table, &time_manager, threads).unwrap();
+				eprintln!("{} empties, {} threads: WLD {:?} ({}, {}), exact {:?} ({}, {})",
+					64 - board.get_turn(), threads, wld, res, mv, start.elapsed(), score, exact_mv);
+			}
+		}
+	}
+
+	#[test]
+	fn test_solve_parallel() {
+		database::initialize_tables();
+		let time_manager = TimeManager::new(u64::MAX / 2);
+		for s in [
+			"OOO-OO-X-OXOOOXXXXOOOOXXXXOXXOOX-OXXOOOOO-XOOOOO--XX-OOX-OX--OOO",
+			"XO-O---XOO-O-XXXXOXOXOOX-OXOOXOX-OXOOOOOO-XXOOOO---XOOOOXXXXXXXX",
+			"---O-X--O-OOXX--XOXOXXXO-XOOOXX-XOXOXO-XOXXXXXX-XOXXOO--XXXX-OOO",
+		] {
+			let board = notation::parse_board(s).unwrap();
+			let table = SolverTable::new(SOLVER_TABLE_BITS);
+			let expected = _solve(&board, board.get_valid_moves(), &table, &time_manager);
+			table.clear();
+			let expected_exact = _solve_exact(&board, board.get_valid_moves(), -64, 64, EXACT_LEVEL, &table, &time_manager);
+			for threads in [2, 3, 8] {
+				table.clear();
+				assert_eq!(solve_parallel(&board, &table, &time_manager, threads), expected, "{}", s);
+				table.clear();
+				assert_eq!(solve_exact_parallel(&board, EXACT_LEVEL, &table, &time_manager, threads), expected_exact, "{}", s);
+			}
+		}
+	}
+
+	#[test]
+	fn test_solve_last() {
+		use rand::{Rng, SeedableRng};
+
+		// 盤面を複製しながら全ての手を読む素朴な実装
+		fn reference(board: &Board) -> i8 {
+			let moves = board.get_valid_moves();
+			if moves == 0 {
+				let mut t = board.clone();
+				t.change_turn();
+				if t.get_valid_moves() == 0 {
+					return disc_diff(board.my_board, board.opponent_board);
+				}
+				return -reference(&t);
+			}
+			(0..64u8).filter(|&m| (moves >> m) & 1 != 0).map(|m| {
+				let mut t = board.clone();
+				t.do_move(m);
+				t.change_turn();
+				-reference(&t)
+			}).max().unwrap()
+		}
+
+		database::initialize_tables();
+		let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+		let mut checked = 0;
+		while checked < 300 {
+			// 空きマスが4以下になるまでランダムに打つ
+			let mut board = Board { my_board: 0x0000000810000000, opponent_board: 0x0000001008000000 };
+			let target = rng.gen_range(0..=LAST_EMPTIES) as usize;
+			while 64 - board.get_turn() > target {
+				let mut moves = board.get_valid_moves();
+				if moves == 0 {
+					board.change_turn();
+					moves = board.get_valid_moves();
+					if moves == 0 {
+						break;
+					}
+				}
+				let n = rng.gen_range(0..moves.count_ones()) as usize;
+				board.do_move((0..64u8).filter(|&m| (moves >> m) & 1 != 0).nth(n).unwrap());
+				board.change_turn();
+			}
+			if 64 - board.get_turn() > target {
+				continue;
+			}
+
+			let expected = reference(&board);
+			assert_eq!(solve_last(&board, -64, 64), expected);
+			// 窓の外は限界値
+			assert_eq!(solve_last(&board, -1, 1).signum(), expected.signum());
+			assert!(solve_last(&board, expected, expected + 1) <= expected);
+			checked += 1;
+		}
+	}
+
+	#[test]
+	fn test_solve_exact() {
+		database::initialize_tables();
+		let time_manager = TimeManager::new(u64::MAX / 2);
+		let table = SolverTable::new(SOLVER_TABLE_BITS);
+		// (局面, 手番側から見た最終石差)
+		for (s, expected) in [
+			("OOO-OO-X-OXOOOXXXXOOOOXXXXOXXOOX-OXXOOOOO-XOOOOO--XX-OOX-OX--OOO", 2),
+			("XO-O---XOO-O-XXXXOXOXOOX-OXOOXOX-OXOOOOOO-XXOOOO---XOOOOXXXXXXXX", -2),
+		] {
+			let board = notation::parse_board(s).unwrap();
+			let (res, mv) = solve_exact(&board, &table, &time_manager, 1).unwrap();
+			assert_eq!(res, expected, "{}", s);
+			assert!(board.get_valid_moves() & (1 << mv) != 0);
+			// 2回目は置換表の値で終わる
+			let nodes = time_manager.get_solver_nodes();
+			assert_eq!(solve_exact(&board, &table, &time_manager, 1), Some((res, mv)));
+			assert!(time_manager.get_solver_nodes() - nodes <= 1);
+		}
+	}
+
+	#[test]
+	fn test_solve_selective() {
+		database::initialize_tables();
+		let time_manager = TimeManager::new(u64::MAX / 2);
+		let board = notation::parse_board("---O-X--O-OOXX--XOXOXXXO-XOOOXX-XOXOXO-XOXXXXXX-XOXXOO--XXXX-OOO").unwrap();
+		let table = SolverTable::new(SOLVER_TABLE_BITS);
+		let exact = solve_exact(&board, &table, &time_manager, 1).unwrap();
+		let exact_nodes = time_manager.get_solver_nodes();
+
+		// 低い段階の置換表の値は、高い段階では最善手の並び替えにだけ使う
+		table.clear();
+		let mut levels = Vec::new();
+		let mut level_nodes = Vec::new();
+		let result = solve_progressive(&board, &probcut::PROBCUT, &table, &time_manager, 1, |r| {
+			assert!(board.get_valid_moves() & (1 << r.mv) != 0);
+			levels.push(r.confidence);
+			level_nodes.push(time_manager.get_solver_nodes());
+		}).unwrap();
+		assert_eq!(levels, SELECTIVITY_LEVELS.iter().map(|&(c, _)| c).collect_vec());
+		// 100%の段階は完全読みと同じ
+		assert_eq!(result, EndgameResult { score: exact.0, mv: exact.1, confidence: 100 });
+		// 前の段階の置換表を使うので、最後の段階は初めから完全読みするより少なく読む
+		assert!(level_nodes[5] - level_nodes[4] < exact_nodes, "{:?} {}", level_nodes, exact_nodes);
+		// 時間切れなら結果なし
+		table.clear();
+		assert!(solve_selective(&board, 73, &probcut::PROBCUT, &table, &TimeManager::new(0), 1).is_none());
+	}
 
     #[test]
     fn test_solver() {
@@ -317,9 +971,9 @@ mod tests {
 
 		fn test(s: &'static str){
 			let time_manager = TimeManager::new(100000);
-			let board = str_to_board(&s.to_string()).unwrap();
+			let board = notation::parse_board(s).unwrap();
 			let start = std::time::Instant::now();
-			let (res, mv) = solve(&board, &time_manager);
+			let (res, mv) = solve(&board, &SolverTable::new(SOLVER_TABLE_BITS), &time_manager, 1);
 			let duration = start.elapsed();
 			eprintln!("Solve took: {:?}", duration);
 			eprintln!("Result: {}, Move: {}", res, mv);
@@ -335,8 +989,8 @@ mod tests {
 		// test("XXX-OX-OX-XXXXXXOOOXOOXX-OXOXOX-OXOOOX--OOOOOOX-OOOO----O-X-----"); //18 -
 		// test("OXX------XOXXX-XXXXOX-X-OXOXOXOO-OXXXOOOO-XXOOXO--OXOOOO-OX--O--"); //18 +
 		// test("OXX------XOXXX-XXXXOX-X-OXOXOXOO-OXXXOOOO-XXOOXO--OXOOOO-OX--O--"); //18 +
-		test("✓··X····O·XXX···OOXXXX✓OOOOXOOOOOOXOXXOOOOOOOXOOXOOOO✓X✓OO✓XO✓··");
-		test("XXXXXXX·XXXXXO✓OXOXOXOOOXOOXOOOOXOXOOOOOXXXOOOOOXOXOOOO✓OOOOOOOO"); //18 -
+		test("---X----O-XXX---OOXXXX-OOOOXOOOOOOXOXXOOOOOOOXOOXOOOO-X-OO-XO---");
+		test("XXXXXXX-XXXXXO-OXOXOXOOOXOOXOOOOXOXOOOOOXXXOOOOOXOXOOOO-OOOOOOOO"); //18 -
     }
 }
 
@@ -450,4 +1104,51 @@ Result: 1, Move: 63
 Solve took: 1.584µs
 Result: 1, Move: 63
 
-*/
\ No newline at end of file
+bench_solver (release), WLD / exact:
+before last 1-4 solvers:
+20 empties: 46.80s / 40.83s
+12 empties: 16.2ms / 13.4ms
+16 empties: 222ms / 161ms
+17 empties: 1.58s / 1.27s
+18 empties: 2.15s / 1.28s
+18 empties: 1.84s / 2.22s
+18 empties: 3.99s / 5.64s
+18 empties: 421ms / 888ms
+18 empties: 2.28s / 9.54s
+
+with last 1-4 solvers:
+20 empties: 21.21s / 14.19s
+12 empties: 6.9ms / 5.2ms
+16 empties: 93ms / 59ms
+17 empties: 656ms / 354ms
+18 empties: 899ms / 410ms
+18 empties: 866ms / 930ms
+18 empties: 1.90s / 1.79s
+18 empties: 175ms / 228ms
+18 empties: 1.02s / 3.45s
+
+
+parallel solver (split at root), WLD / exact by threads 1 / 2 / 4 / 8:
+(1コアの環境で計測したため速くはならない、スレッド数を増やしたときの探索量の増え方の目安)
+(複数コアでの速さは測れていないので、設定solver_threadsの既定は1)
+20 empties: 20.1s / 21.5s / 25.6s / 24.7s  |  19.3s / 23.0s / 30.3s / 26.8s
+12 empties: 4.4ms / 4.0ms / 3.9ms / 3.9ms  |  3.5ms / 3.6ms / 3.6ms / 3.7ms
+16 empties: 68ms / 75ms / 71ms / 71ms  |  52ms / 53ms / 54ms / 53ms
+17 empties: 567ms / 573ms / 627ms / 641ms  |  435ms / 467ms / 482ms / 457ms
+18 empties: 897ms / 921ms / 868ms / 906ms  |  461ms / 510ms / 614ms / 612ms
+18 empties: 735ms / 748ms / 762ms / 755ms  |  926ms / 944ms / 1.05s / 994ms
+18 empties: 1.66s / 1.58s / 1.39s / 1.54s  |  1.69s / 1.52s / 1.51s / 2.11s
+18 empties: 234ms / 232ms / 223ms / 206ms  |  277ms / 280ms / 297ms / 307ms
+18 empties: 1.08s / 935ms / 905ms / 875ms  |  3.77s / 3.82s / 4.07s / 3.95s
+
+exact with the solver table (bounds and best moves), 1 thread, before / after:
+12 empties: 4.5ms / 4.1ms
+16 empties: 43ms / 50ms
+17 empties: 304ms / 301ms
+18 empties: 390ms / 282ms
+18 empties: 723ms / 548ms
+18 empties: 1.37s / 1.04s
+18 empties: 180ms / 159ms
+18 empties: 2.04s / 1.92s
+
+*/
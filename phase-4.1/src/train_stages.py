@@ -0,0 +1,194 @@
+# train_stages.py
+# 空きマス数ごとのステージに分けてPatternNNを学習し、ステージごとのONNXモデルを書き出す
+#
+#   cargo run --release -- generate-dataset --games 20000 --out dataset.txt
+#   python src/train_stages.py dataset.txt --stages 6
+#   python src/extract_weights.py
+#
+# ステージの区切りは eval_stage.rs の StageLayout::stage_of と同じ
+import argparse
+import json
+import os
+
+import numpy as np
+import torch
+import torch.nn as nn
+
+# パターン定義（pattern.rsと同じ）
+PATTERNS = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 9, 14],
+    [0, 8, 9, 16, 24, 32, 40, 48, 49, 56],
+    [7, 14, 15, 23, 31, 39, 47, 54, 55, 63],
+    [49, 54, 56, 57, 58, 59, 60, 61, 62, 63],
+    [8, 9, 10, 11, 12, 13, 14, 15],
+    [48, 49, 50, 51, 52, 53, 54, 55],
+    [1, 9, 17, 25, 33, 41, 49, 57],
+    [6, 14, 22, 30, 38, 46, 54, 62],
+    [16, 17, 18, 19, 20, 21, 22, 23],
+    [40, 41, 42, 43, 44, 45, 46, 47],
+    [2, 10, 18, 26, 34, 42, 50, 58],
+    [5, 13, 21, 29, 37, 45, 53, 61],
+    [24, 25, 26, 27, 28, 29, 30, 31],
+    [32, 33, 34, 35, 36, 37, 38, 39],
+    [3, 11, 19, 27, 35, 43, 51, 59],
+    [4, 12, 20, 28, 36, 44, 52, 60],
+    [0, 1, 2, 3, 8, 9, 10, 16, 17, 24],
+    [4, 5, 6, 7, 13, 14, 15, 22, 23, 31],
+    [32, 40, 41, 48, 49, 50, 56, 57, 58, 59],
+    [39, 46, 47, 53, 54, 55, 60, 61, 62, 63],
+    [4, 9, 11, 14, 18, 25, 32, 49],
+    [14, 31, 38, 45, 49, 52, 54, 59],
+    [3, 9, 12, 14, 21, 30, 39, 54],
+    [9, 24, 33, 42, 49, 51, 54, 60],
+    [5, 6, 7, 12, 19, 26, 33, 40, 48, 56],
+    [7, 15, 23, 30, 37, 44, 51, 56, 57, 58],
+    [0, 1, 2, 11, 20, 29, 38, 47, 55, 63],
+    [0, 8, 16, 25, 34, 43, 52, 61, 62, 63],
+    [6, 7, 13, 20, 27, 34, 41, 48, 56],
+    [7, 15, 22, 29, 36, 43, 50, 56, 57],
+    [0, 1, 10, 19, 28, 37, 46, 55, 63],
+    [0, 8, 17, 26, 35, 44, 53, 62, 63],
+    [0, 1, 8, 9, 18, 27, 36, 45, 54, 63],
+    [0, 9, 18, 27, 36, 45, 54, 55, 62, 63],
+    [6, 7, 14, 15, 21, 28, 35, 42, 49, 56],
+    [7, 14, 21, 28, 35, 42, 48, 49, 56, 57],
+    [0, 1, 2, 8, 9, 10, 16, 17, 18],
+    [5, 6, 7, 13, 14, 15, 21, 22, 23],
+    [40, 41, 42, 48, 49, 50, 56, 57, 58],
+    [45, 46, 47, 53, 54, 55, 61, 62, 63],
+    [0, 1, 2, 3, 4, 8, 9, 16, 24, 32],
+    [3, 4, 5, 6, 7, 14, 15, 23, 31, 39],
+    [24, 32, 40, 48, 49, 56, 57, 58, 59, 60],
+    [31, 39, 47, 54, 55, 59, 60, 61, 62, 63],
+    [0, 2, 3, 4, 5, 7, 10, 11, 12, 13],
+    [0, 16, 17, 24, 25, 32, 33, 40, 41, 56],
+    [7, 22, 23, 30, 31, 38, 39, 46, 47, 63],
+    [50, 51, 52, 53, 56, 58, 59, 60, 61, 63],
+    [0, 1, 8, 9, 10, 11, 17, 18, 25, 27],
+    [6, 7, 12, 13, 14, 15, 21, 22, 28, 30],
+    [33, 35, 41, 42, 48, 49, 50, 51, 56, 57],
+    [36, 38, 45, 46, 52, 53, 54, 55, 62, 63],
+]
+
+# パターン長 -> 埋め込み層 (eval_wasm.rsと同じ対応)
+EMBEDDING_OF_LENGTH = {8: 0, 9: 1, 10: 2}
+
+
+class PatternNN(nn.Module):
+    def __init__(self):
+        super().__init__()
+        self.embedding_layers = nn.ModuleList([nn.Embedding(3 ** n, 8) for n in (8, 9, 10)])
+        self.dense_layers = nn.Sequential(
+            nn.Linear(len(PATTERNS) * 8, 128),
+            nn.ReLU(),
+            nn.Linear(128, 1),
+        )
+
+    def forward(self, x):
+        embedded = [
+            self.embedding_layers[EMBEDDING_OF_LENGTH[len(p)]](x[:, i])
+            for i, p in enumerate(PATTERNS)
+        ]
+        return self.dense_layers(torch.cat(embedded, dim=1)).squeeze(1)
+
+
+def stage_of(empties, stage_count):
+    return min(min(empties, 60) * stage_count // 61, stage_count - 1)
+
+
+def pattern_indices(my_board, opponent_board):
+    # 自分の石=1, 相手の石=2, 空=0 の3進数
+    indices = []
+    for pattern in PATTERNS:
+        index = 0
+        power = 1
+        for pos in pattern:
+            if (my_board >> pos) & 1:
+                index += power
+            elif (opponent_board >> pos) & 1:
+                index += 2 * power
+            power *= 3
+        indices.append(index)
+    return indices
+
+
+def load_dataset(path):
+    # generate-datasetの出力: "my_board opponent_board disc_diff"
+    # Board::evalはNNの出力を符号反転して使うため、目標値は手番でない側から見た石差
+    xs, ys, empties = [], [], []
+    with open(path) as f:
+        for line in f:
+            my_hex, op_hex, diff = line.split()
+            my_board, opponent_board = int(my_hex, 16), int(op_hex, 16)
+            xs.append(pattern_indices(my_board, opponent_board))
+            ys.append(-float(diff))
+            empties.append(64 - bin(my_board | opponent_board).count('1'))
+    return np.array(xs, dtype=np.int64), np.array(ys, dtype=np.float32), np.array(empties)
+
+
+def train_stage(x, y, epochs, batch_size, lr, init=None):
+    model = PatternNN()
+    if init is not None:
+        model.load_state_dict(init.state_dict())
+    optimizer = torch.optim.Adam(model.parameters(), lr=lr)
+    x, y = torch.from_numpy(x), torch.from_numpy(y)
+    for epoch in range(epochs):
+        perm = torch.randperm(len(x))
+        total = 0.0
+        for i in range(0, len(x), batch_size):
+            idx = perm[i:i + batch_size]
+            optimizer.zero_grad()
+            loss = nn.functional.mse_loss(model(x[idx]), y[idx])
+            loss.backward()
+            optimizer.step()
+            total += loss.item() * len(idx)
+        print(f"  epoch {epoch + 1}: mse={total / len(x):.3f}")
+    return model
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("dataset")
+    parser.add_argument("--stages", type=int, default=6)
+    parser.add_argument("--interpolate", action="store_true")
+    parser.add_argument("--overlap", type=int, default=2, help="隣のステージから借りる空きマス数")
+    parser.add_argument("--epochs", type=int, default=10)
+    parser.add_argument("--batch-size", type=int, default=1024)
+    parser.add_argument("--lr", type=float, default=1e-3)
+    parser.add_argument("--out-dir", default="src")
+    parser.add_argument("--name", default="othello_model_pattern_alt")
+    args = parser.parse_args()
+
+    x, y, empties = load_dataset(args.dataset)
+
+    models = []
+    previous = None
+    # 終盤側から学習し、前のステージの重みを初期値にする
+    for s in range(args.stages):
+        lo = min(e for e in range(61) if stage_of(e, args.stages) == s)
+        hi = max(e for e in range(61) if stage_of(e, args.stages) == s)
+        mask = (empties >= lo - args.overlap) & (empties <= hi + args.overlap)
+        print(f"stage {s}: empties {lo}-{hi}, {mask.sum()} positions")
+        model = train_stage(x[mask], y[mask], args.epochs, args.batch_size, args.lr, previous)
+        previous = model
+
+        filename = f"{args.name}.stage{s}.onnx" if args.stages > 1 else f"{args.name}.onnx"
+        model.eval()
+        torch.onnx.export(
+            model,
+            torch.zeros((1, len(PATTERNS)), dtype=torch.int64),
+            os.path.join(args.out_dir, filename),
+            input_names=["input"],
+            output_names=["output"],
+            do_constant_folding=False,
+        )
+        models.append(filename)
+
+    manifest = {"interpolate": args.interpolate, "models": models}
+    with open(os.path.join(args.out_dir, f"{args.name}.stages.json"), "w") as f:
+        json.dump(manifest, f, indent=2)
+    print(f"Saved {len(models)} stage models")
+
+
+if __name__ == "__main__":
+    main()
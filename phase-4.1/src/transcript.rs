@@ -0,0 +1,152 @@
+/*
+棋譜(f5d6c3...)の読み書きと再生
+マスの名前を続けて書き、大文字と小文字は区別しない、手の間の空白は読み飛ばす
+パスは"pass"または"pa"と書くが、打てる手がないときは自動でパスするので省略してもよい
+再生では一手ずつ合法か確かめ、最初の不正な手を返す
+*/
+
+use crate::game::{parse_square, square_name, Color, Game, Position, PASS};
+
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum TranscriptError {
+    // offsetは棋譜の何文字目か(0から)
+    #[error("invalid move '{text}' at character {offset}")]
+    InvalidMove { offset: usize, text: String },
+    // plyは棋譜の何手目か(1から、書かれたパスを含む)
+    #[error("illegal move {mv} at move {ply}")]
+    IllegalMove { ply: usize, mv: String },
+}
+
+// 棋譜を手の列にする(パスはPASS)
+pub fn parse(s: &str) -> Result<Vec<u8>, TranscriptError> {
+    let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let mut moves = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let rest: String = chars[i..].iter().take(4).collect();
+        let len = if rest == "pass" { 4 } else { 2 };
+        let token: String = chars[i..].iter().take(len).collect();
+        let mv = if token == "pa" || token == "pass" { Some(PASS) } else { parse_square(&token).filter(|&m| m < 64) };
+        match mv {
+            Some(mv) => moves.push(mv),
+            None => return Err(TranscriptError::InvalidMove { offset: i, text: token }),
+        }
+        i += len;
+    }
+    Ok(moves)
+}
+
+// 手の列を棋譜にする、explicit_passesならパスも"pass"と書く
+pub fn write(moves: &[u8], explicit_passes: bool) -> String {
+    moves.iter()
+        .filter(|&&mv| explicit_passes || mv != PASS)
+        .map(|&mv| square_name(mv))
+        .collect()
+}
+
+// startから手の列を打った対局
+// 書かれたパスは、その手番が打てずに自動でパスした場合だけ認める
+pub fn replay(start: Position, moves: &[u8]) -> Result<Game, TranscriptError> {
+    let mut game = Game::from_position(start);
+    // 直前に自動でパスしたか(始めの局面で打てない場合を含む)
+    let mut passed = game.side_to_move() != start.to_move;
+    for (i, &mv) in moves.iter().enumerate() {
+        let illegal = || TranscriptError::IllegalMove { ply: i + 1, mv: square_name(mv) };
+        if mv == PASS {
+            if !passed {
+                return Err(illegal());
+            }
+            passed = false;
+            continue;
+        }
+        passed = game.play(mv).map_err(|_| illegal())?;
+    }
+    Ok(game)
+}
+
+// 初期局面から棋譜を再生する
+pub fn load(s: &str) -> Result<Game, TranscriptError> {
+    replay(Position::initial(), &parse(s)?)
+}
+
+// 黒から見た石差
+// 終局していれば空きマスは勝った側に数える
+pub fn final_score(game: &Game) -> i32 {
+    let black = game.count(Color::Black) as i32;
+    let white = game.count(Color::White) as i32;
+    let diff = black - white;
+    if !game.is_over() {
+        return diff;
+    }
+    let empties = 64 - black - white;
+    match diff.signum() {
+        1 => diff + empties,
+        -1 => diff - empties,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::game::GameState;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_transcript() {
+        database::initialize_tables();
+        assert_eq!(parse("F5d6 C3"), Ok(vec![37, 43, 18]));
+        assert_eq!(parse("f5PAd6pass"), Ok(vec![37, PASS, 43, PASS]));
+        assert_eq!(parse("f5d9"), Err(TranscriptError::InvalidMove { offset: 2, text: "d9".to_string() }));
+        assert_eq!(parse("f5d"), Err(TranscriptError::InvalidMove { offset: 2, text: "d".to_string() }));
+
+        let game = load("f5d6c3").unwrap();
+        assert_eq!((game.ply(), game.side_to_move()), (3, Color::White));
+        // 最初の不正な手を返す(a1は打てない)
+        assert_eq!(load("f5d6a1c3").unwrap_err(), TranscriptError::IllegalMove { ply: 3, mv: "a1".to_string() });
+        // 打てる手があるのにパスはできない
+        assert_eq!(load("f5pass").unwrap_err(), TranscriptError::IllegalMove { ply: 2, mv: "pass".to_string() });
+        // 白がe1でf1を返すと黒は打てずにパスし、白がe3で全滅させる
+        let start = Position { black: 0x1020, white: 0x0040, to_move: Color::White };
+        for s in ["e1e3", "E1 PASS E3", "e1pae3"] {
+            let game = replay(start, &parse(s).unwrap()).unwrap();
+            assert_eq!((game.state(), final_score(&game)), (GameState::WhiteWin, -64), "{}", s);
+        }
+        assert_eq!(replay(start, &parse("e1passpass").unwrap()).unwrap_err(), TranscriptError::IllegalMove { ply: 3, mv: "pass".to_string() });
+
+        // ランダムな対局を書いて読み直すと同じになる(パスを書いても書かなくても)
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..50 {
+            let mut game = Game::new();
+            while !game.is_over() {
+                let moves = game.legal_moves();
+                let n = rng.gen_range(0..moves.count_ones()) as usize;
+                game.play((0..64u8).filter(|&m| moves >> m & 1 != 0).nth(n).unwrap()).unwrap();
+            }
+            let moves: Vec<u8> = game.moves().iter().map(|r| r.mv).collect();
+            for explicit_passes in [false, true] {
+                let s = write(&moves, explicit_passes);
+                let replayed = replay(Position::initial(), &parse(&s).unwrap()).unwrap();
+                assert_eq!(replayed.moves(), game.moves(), "{}", s);
+                assert_eq!(final_score(&replayed), final_score(&game));
+            }
+            assert_eq!(game.transcript(), write(&moves, false));
+
+            let score = final_score(&game);
+            let expected = match game.state() {
+                GameState::BlackWin => score > 0,
+                GameState::WhiteWin => score < 0,
+                _ => score == 0,
+            };
+            assert!(expected, "{:?} {}", game.state(), score);
+            assert_eq!(score.abs() % 2, 0);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+/*
+WTHOR(.wtb)の対局データベースの読み込み
+16バイトのヘッダの後に68バイトの対局が並ぶ(数値はリトルエンディアン)
+  ヘッダ  作成日(世紀, 年, 月, 日), 対局数(4), 名前の数(2), 対局の年(2), 盤の大きさ(0か8), 一人用なら1, 理論値の深さ, 予備
+  対局    大会の番号(2), 黒の番号(2), 白の番号(2), 黒の石数, 黒の理論値の石数, 手(60)
+手は 行*10+列(a1が11, h1が18, h8が88)で、パスは書かず、途中で終わったら後を0で埋める
+黒の石数は終局で空きマスを勝った側に数えたもの
+対局者(.jou)と大会(.trn)は同じヘッダの後に0で終わる名前(20バイトと26バイト)が番号の順に並ぶ
+対局は読むだけでは確かめず、再生(replay)したときに不正な手や石数の違う壊れた対局をエラーにする
+*/
+
+use crate::database::{self, Book};
+use crate::game::{Color, Game, Position};
+use crate::play::Board;
+use crate::transcript::{self, TranscriptError};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WthorError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unsupported board size: {0}")]
+    UnsupportedBoardSize(u8),
+    // plyは何手目か(1から)
+    #[error("invalid move byte {byte} at move {ply}")]
+    InvalidMove { ply: usize, byte: u8 },
+    #[error(transparent)]
+    Transcript(#[from] TranscriptError),
+    #[error("black score {recorded} does not match the final position ({actual})")]
+    ScoreMismatch { recorded: u8, actual: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WthorHeader {
+    // 作成日(年, 月, 日)
+    pub created: (u16, u8, u8),
+    // 対局の数(.wtb)
+    pub games: u32,
+    // 名前の数(.jou, .trn)
+    pub names: u16,
+    pub year: u16,
+    pub board_size: u8,
+    pub solitaire: bool,
+    // 理論値を何手目から読み切ったか
+    pub depth: u8,
+}
+
+impl WthorHeader {
+    pub const SIZE: usize = 16;
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self, WthorError> {
+        let mut b = [0u8; Self::SIZE];
+        reader.read_exact(&mut b)?;
+        let header = Self {
+            created: (b[0] as u16 * 100 + b[1] as u16, b[2], b[3]),
+            games: u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+            names: u16::from_le_bytes([b[8], b[9]]),
+            year: u16::from_le_bytes([b[10], b[11]]),
+            board_size: b[12],
+            solitaire: b[13] == 1,
+            depth: b[14],
+        };
+        // 0は8x8
+        if !matches!(header.board_size, 0 | 8) {
+            return Err(WthorError::UnsupportedBoardSize(header.board_size));
+        }
+        Ok(header)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WthorGame {
+    pub tournament: u16,
+    pub black: u16,
+    pub white: u16,
+    pub black_score: u8,
+    pub theoretical_score: u8,
+    // 書かれたままの手
+    pub moves: [u8; 60],
+}
+
+impl WthorGame {
+    pub const SIZE: usize = 68;
+
+    fn from_bytes(b: &[u8; Self::SIZE]) -> Self {
+        let mut moves = [0u8; 60];
+        moves.copy_from_slice(&b[8..]);
+        Self {
+            tournament: u16::from_le_bytes([b[0], b[1]]),
+            black: u16::from_le_bytes([b[2], b[3]]),
+            white: u16::from_le_bytes([b[4], b[5]]),
+            black_score: b[6],
+            theoretical_score: b[7],
+            moves,
+        }
+    }
+
+    // 手のマスの列(0から後は読まない)
+    pub fn squares(&self) -> Result<Vec<u8>, WthorError> {
+        self.moves.iter()
+            .take_while(|&&byte| byte != 0)
+            .enumerate()
+            .map(|(i, &byte)| {
+                let (row, col) = (byte / 10, byte % 10);
+                if (1..=8).contains(&row) && (1..=8).contains(&col) {
+                    Ok((row - 1) * 8 + col - 1)
+                } else {
+                    Err(WthorError::InvalidMove { ply: i + 1, byte })
+                }
+            })
+            .collect()
+    }
+
+    // 棋譜(f5d6...)
+    pub fn transcript(&self) -> Result<String, WthorError> {
+        Ok(transcript::write(&self.squares()?, false))
+    }
+
+    // 初期局面から再生した対局
+    // 不正な手があるか、終局しているのに黒の石数が合わなければ壊れた対局としてエラーにする
+    pub fn replay(&self) -> Result<Game, WthorError> {
+        let game = transcript::replay(Position::initial(), &self.squares()?)?;
+        // 途中で終わった対局(時間切れなど)は石数を確かめない
+        if game.is_over() {
+            let actual = ((64 + transcript::final_score(&game)) / 2) as u8;
+            if actual != self.black_score {
+                return Err(WthorError::ScoreMismatch { recorded: self.black_score, actual });
+            }
+        }
+        Ok(game)
+    }
+
+    // 黒から見た記録の石差
+    // 途中で終わった対局(時間切れなど)も記録された黒の石数から求める
+    pub fn score(&self) -> i32 {
+        2 * self.black_score as i32 - 64
+    }
+
+    // (打つ前の手番側から見た盤面, 打った手, 手番側から見た最終石差)の列
+    // 石差は記録の石差(score)、パスしかない局面は含めない
+    pub fn positions(&self) -> Result<Vec<(Board, u8, i8)>, WthorError> {
+        let game = self.replay()?;
+        let score = self.score() as i8;
+        let mut board = Position::initial().board();
+        let mut positions = Vec::new();
+        for record in game.moves() {
+            if !record.is_pass() {
+                positions.push((board, record.mv, if record.color == Color::Black { score } else { -score }));
+                board.do_move(record.mv);
+            }
+            board.change_turn();
+        }
+        Ok(positions)
+    }
+}
+
+// ヘッダを読んだ後、対局を一つずつ返す
+// 読み込みに失敗したらそのエラーを返して終わる
+pub struct WthorReader<R> {
+    reader: R,
+    header: WthorHeader,
+    remaining: u32,
+}
+
+impl<R: Read> WthorReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, WthorError> {
+        let header = WthorHeader::read(&mut reader)?;
+        Ok(Self { reader, header, remaining: header.games })
+    }
+
+    pub fn header(&self) -> &WthorHeader {
+        &self.header
+    }
+}
+
+impl<R: Read> Iterator for WthorReader<R> {
+    type Item = Result<WthorGame, WthorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut b = [0u8; WthorGame::SIZE];
+        if let Err(e) = self.reader.read_exact(&mut b) {
+            self.remaining = 0;
+            return Some(Err(e.into()));
+        }
+        self.remaining -= 1;
+        Some(Ok(WthorGame::from_bytes(&b)))
+    }
+}
+
+// .wtbファイルを開く
+pub fn open<P: AsRef<Path>>(path: P) -> Result<WthorReader<BufReader<File>>, WthorError> {
+    WthorReader::new(BufReader::new(File::open(path)?))
+}
+
+// 名前はLatin-1で、0から後は読まない
+fn read_names<R: Read>(mut reader: R, size: usize) -> Result<Vec<String>, WthorError> {
+    let header = WthorHeader::read(&mut reader)?;
+    let mut buffer = vec![0u8; size];
+    let mut names = Vec::with_capacity(header.names as usize);
+    for _ in 0..header.names {
+        reader.read_exact(&mut buffer)?;
+        let name: String = buffer.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect();
+        names.push(name.trim().to_string());
+    }
+    Ok(names)
+}
+
+// 対局者の名前(.jou)、WthorGameのblack, whiteで引く
+pub fn read_players<R: Read>(reader: R) -> Result<Vec<String>, WthorError> {
+    read_names(reader, 20)
+}
+
+// 大会の名前(.trn)、WthorGameのtournamentで引く
+pub fn read_tournaments<R: Read>(reader: R) -> Result<Vec<String>, WthorError> {
+    read_names(reader, 26)
+}
+
+// 対局(positionsの列)から定石を作る
+// max_plies手目までの局面で、min_games局以上打たれた手のうち手番側から見た平均石差が最もよい手を選ぶ
+// 対称な局面は同じ向きに揃えて数える(定石は対称な局面も引ける)
+pub fn build_book(games: &[Vec<(Board, u8, i8)>], max_plies: usize, min_games: u32) -> Book {
+    // 局面ごとに、手 -> (局数, 石差の合計)
+    let mut stats: HashMap<Board, HashMap<u8, (u32, i64)>> = HashMap::new();
+    for positions in games {
+        for &(board, mv, score) in positions.iter().take(max_plies) {
+            let (board, mv) = database::canonical_move(&board, mv);
+            let entry = stats.entry(board).or_default().entry(mv).or_default();
+            entry.0 += 1;
+            entry.1 += score as i64;
+        }
+    }
+
+    let mut book = Book::default();
+    for (board, moves) in &stats {
+        let best = moves.iter()
+            .filter(|(_, &(count, _))| count >= min_games)
+            // 平均が同じなら多く打たれた手、それも同じなら小さいマス
+            .max_by(|(m1, &(c1, s1)), (m2, &(c2, s2))| {
+                (s1 * c2 as i64).cmp(&(s2 * c1 as i64)).then(c1.cmp(&c2)).then(m2.cmp(m1))
+            });
+        if let Some((&mv, _)) = best {
+            book.insert(board, mv);
+        }
+    }
+    book
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::game::PASS;
+    use rand::{Rng, SeedableRng};
+
+    fn header(games: u32, names: u16) -> Vec<u8> {
+        let mut b = vec![20, 24, 10, 19];
+        b.extend(games.to_le_bytes());
+        b.extend(names.to_le_bytes());
+        b.extend(2024u16.to_le_bytes());
+        b.extend([8, 0, 22, 0]);
+        b
+    }
+
+    fn record(game: &Game, black_score: u8) -> Vec<u8> {
+        let mut b = vec![1, 0, 2, 0, 3, 0, black_score, black_score];
+        let moves = game.moves().iter().filter(|r| r.mv != PASS).map(|r| (r.mv / 8 + 1) * 10 + r.mv % 8 + 1);
+        b.extend(moves.chain(std::iter::repeat(0)).take(60));
+        b
+    }
+
+    #[test]
+    fn test_wthor() {
+        database::initialize_tables();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut games = Vec::new();
+        for _ in 0..20 {
+            let mut game = Game::new();
+            while !game.is_over() {
+                let moves = game.legal_moves();
+                let n = rng.gen_range(0..moves.count_ones()) as usize;
+                game.play((0..64u8).filter(|&m| moves >> m & 1 != 0).nth(n).unwrap()).unwrap();
+            }
+            games.push(game);
+        }
+
+        let mut bytes = header(games.len() as u32 + 4, 0);
+        for game in &games {
+            bytes.extend(record(game, ((64 + transcript::final_score(game)) / 2) as u8));
+        }
+        // 途中で終わった対局は記録の石数を結果にする
+        let unfinished = transcript::load("f5d6").unwrap();
+        bytes.extend(record(&unfinished, 40));
+        // 壊れた対局(石数が違う、不正な手、読めない手)
+        bytes.extend(record(&games[0], 65));
+        let mut illegal = record(&games[0], 32);
+        illegal[8 + 1] = 11;
+        bytes.extend(illegal);
+        let mut invalid = record(&games[0], 32);
+        invalid[8 + 2] = 99;
+        bytes.extend(invalid);
+
+        let reader = WthorReader::new(&bytes[..]).unwrap();
+        assert_eq!((reader.header().created, reader.header().year, reader.header().depth), ((2024, 10, 19), 2024, 22));
+        let records: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(records.len(), games.len() + 4);
+        for (record, game) in records.iter().zip(&games) {
+            assert_eq!((record.tournament, record.black, record.white), (1, 2, 3));
+            assert_eq!(record.transcript().unwrap(), game.transcript());
+            assert_eq!(record.replay().unwrap().moves(), game.moves());
+            // 盤面に打ち直すと終局の石数になる
+            let positions = record.positions().unwrap();
+            let (mut board, mv, _) = *positions.last().unwrap();
+            board.do_move(mv);
+            assert_eq!(board.my_board.count_ones() + board.opponent_board.count_ones(), game.count(Color::Black) + game.count(Color::White));
+        }
+        let n = games.len();
+        let scores: Vec<i8> = records[n].positions().unwrap().iter().map(|&(_, _, score)| score).collect();
+        assert_eq!((records[n].score(), scores), (16, vec![16, -16]));
+        assert!(matches!(records[n + 1].replay(), Err(WthorError::ScoreMismatch { recorded: 65, .. })));
+        assert!(matches!(records[n + 2].replay(), Err(WthorError::Transcript(TranscriptError::IllegalMove { ply: 2, .. }))));
+        assert!(matches!(records[n + 3].replay(), Err(WthorError::InvalidMove { ply: 3, byte: 99 })));
+
+        // 途中で切れたファイル
+        let truncated: Vec<_> = WthorReader::new(&bytes[..bytes.len() - 1]).unwrap().collect();
+        assert!(matches!(truncated.last(), Some(Err(WthorError::Io(_)))));
+        assert!(matches!(WthorReader::new(&header(0, 0)[..12]), Err(WthorError::Io(_))));
+
+        // 名前
+        let mut names = header(0, 2);
+        names.extend(b"Tastet Marc\0\0\0\0\0\0\0\0\0");
+        names.extend(b"Caspard Herv\xe9\0\0\0\0\0\0\0");
+        assert_eq!(read_players(&names[..]).unwrap(), vec!["Tastet Marc", "Caspard Hervé"]);
+
+        // 定石を作って書き出し、読み直しても同じ手を返す
+        let positions: Vec<_> = records[..n].iter().map(|r| r.positions().unwrap()).collect();
+        let book = build_book(&positions, 10, 1);
+        let book = Book::from_bytes(&book.to_bytes());
+        assert!(!book.is_empty());
+        for p in &positions {
+            for &(board, _, _) in p.iter().take(10) {
+                let mv = book.lookup(&board).unwrap();
+                assert!(board.get_valid_moves() >> mv & 1 != 0);
+            }
+        }
+
+        // 対称な対局はまとめて数え、どの向きでも対応する手を返す
+        // f5d6c3とe6f4c3は対角線で対称、f5f6は1局だけ
+        let positions: Vec<_> = ["f5d6c3", "e6f4c3", "f5f6"].iter().map(|s| {
+            let game = transcript::load(s).unwrap();
+            let black_score = if *s == "f5f6" { 64 } else { 40 };
+            WthorGame::from_bytes(&record(&game, black_score).try_into().unwrap()).positions().unwrap()
+        }).collect();
+        let book = build_book(&positions, 3, 2);
+        let after = |s: &str| transcript::load(s).unwrap().board();
+        assert_eq!(book.lookup(&after("f5")), Some(43));
+        assert_eq!(book.lookup(&after("e6")), Some(29));
+        assert_eq!(book.lookup(&after("f5d6")), Some(18));
+        assert_eq!(book.lookup(&after("e6f4")), Some(18));
+        assert_eq!(book.lookup(&after("d3")), Some(34));
+        assert!(book.lookup(&after("f5f6")).is_none());
+    }
+}
@@ -1,7 +1,7 @@
 /* eslint-env es2020 */
 import './App.css';
-import React, {useState, useEffect} from 'react';
-import {GameEngine} from './GameEngine';
+import React, {useState, useEffect, useRef} from 'react';
+import {GameEngine, squareName} from './GameEngine';
 
 let wasmModule = null;
 
@@ -27,23 +27,17 @@ const wasmPromise = (async () => {
 })();
 
 // 石の描画
-function RenderStone({type, isValidMove, isLastMove}) {
-    if (type === 1) {
+// flipOrderは直前の手で返った順(置いたマスからの距離)、返っていなければundefined
+function RenderStone({type, isValidMove, isLastMove, flipOrder, flipKey}) {
+    if (type === 'Black' || type === 'White') {
+        const flipping = flipOrder !== undefined;
         return (
             <div
-                className="BlackStone"
-                style={{
-					boxShadow: isLastMove ? '6px 4px 4px #033d14ff' : 'none',
-                }}
-            />
-        );
-    }
-    if (type === 2) {
-        return (
-            <div
-                className="WhiteStone"
+                key={flipping ? flipKey : 'stone'}
+                className={`${type}Stone${flipping ? ' FlippingStone' : ''}`}
                 style={{
 					boxShadow: isLastMove ? '6px 4px 4px #033d14ff' : 'none',
+					animationDelay: flipping ? `${(flipOrder - 1) * 60}ms` : undefined,
                 }}
             />
         );
@@ -60,7 +54,7 @@ function RenderStone({type, isValidMove, isLastMove}) {
 function GameInfo({gameEngine}) {
     const blackCount = gameEngine.getBlackStoneCount();
     const whiteCount = gameEngine.getWhiteStoneCount();
-    const currentPlayer = gameEngine.getCurrentTurn() % 2 === 0 ? '●' : '○';
+    const currentPlayer = gameEngine.getCurrentPlayer() ? '○' : '●';
 
     return (
         <div className="GameInfo">
@@ -89,8 +83,9 @@ function GameInfo({gameEngine}) {
 }
 
 // ゲーム操作ボタンコンポーネント
-function GameControls({gameEngine, onReset, onUndo, onRedo, onShowMenu, onDownloadLog}) {
+function GameControls({gameEngine, onReset, onUndo, onRedo, onShowMenu, onDownloadLog, onLoadLog, onSaveGgf, onOpenGgf}) {
     const canUndo = gameEngine.undoable();
+    const fileInput = useRef(null);
     const canRedo = gameEngine.redoable();
 
     return (
@@ -136,6 +131,40 @@ function GameControls({gameEngine, onReset, onUndo, onRedo, onShowMenu, onDownlo
             >
                 RECORD
             </button>
+
+            {/* 棋譜読み込み */}
+            <button
+                onClick={onLoadLog}
+                className="ButtonRecord"
+            >
+                LOAD
+            </button>
+
+            {/* GGFの保存と読み込み */}
+            <button
+                onClick={onSaveGgf}
+                className="ButtonRecord"
+            >
+                SAVE
+            </button>
+            <button
+                onClick={() => fileInput.current.click()}
+                className="ButtonRecord"
+            >
+                OPEN
+            </button>
+            <input
+                ref={fileInput}
+                type="file"
+                accept=".ggf,text/plain"
+                style={{ display: 'none' }}
+                onChange={e => {
+                    if (e.target.files.length > 0) {
+                        onOpenGgf(e.target.files[0]);
+                    }
+                    e.target.value = '';
+                }}
+            />
         </div>
     );
 }
@@ -155,11 +184,12 @@ function App() {
 
     useEffect(() => {
         let isMounted = true;
+        let engine = null;
 
         wasmPromise.then(success => {
             if (isMounted) {
                 if (success) {
-                    const engine = new GameEngine(wasmModule);
+                    engine = new GameEngine(wasmModule);
                     engine.setPlayerMode('human', 'human');
                     setGameEngine(engine);
                     setWasmLoaded(true);
@@ -173,6 +203,8 @@ function App() {
 
         return () => {
             isMounted = false;
+            // 盤面と黒白のエンジンのメモリを解放する
+            engine?.dispose();
         };
     }, []);
 
@@ -190,11 +222,11 @@ function App() {
             const aiMoveTimer = setTimeout(() => {
                 try {
 					const level = gameEngine.getCurrentPlayer() ? whiteAiLevel : blackAiLevel;
-                    const result = gameEngine.getAIMove(1000, level);
-					let row = Math.floor(result / 8);
-					let col = result % 8;
-					console.log(`AI recommends move: (${row}, ${col})`);
-					gameEngine.makeMove(row, col);
+                    const move = gameEngine.getAIMove(1000, level);
+					console.log(`AI recommends move: ${move}`);
+					if (move !== null) {
+						gameEngine.playMove(move);
+					}
 
                     forceUpdate({});
                 }
@@ -491,13 +523,61 @@ function App() {
 		}
     };
 
+	// 棋譜読み込み機能(クリップボードの棋譜を再生し、検討のためAIは止める)
+    const handleLoadLog = () => {
+		navigator.clipboard.readText().then(text => {
+			const result = gameEngine.loadKif(text);
+			if (result.success) {
+				setIsAiThinking(false);
+				setIsEditing(true);
+				setPassMessage('');
+				forceUpdate({});
+			} else {
+				alert(`棋譜を読み込めませんでした: ${result.message}`);
+			}
+		}).catch(err => {
+			console.error('クリップボードの読み込みに失敗しました:', err);
+		});
+    };
+
+	// GGFの棋譜をファイルとしてダウンロード
+    const handleSaveGgf = () => {
+		const blob = new Blob([gameEngine.getGgf() + '\n'], {type: 'text/plain'});
+		const url = URL.createObjectURL(blob);
+		const link = document.createElement('a');
+		link.href = url;
+		link.download = 'game.ggf';
+		link.click();
+		URL.revokeObjectURL(url);
+    };
+
+	// GGFのファイルを読み込む(棋譜の読み込みと同じくAIは止める)
+    const handleOpenGgf = (file) => {
+		file.text().then(text => {
+			const result = gameEngine.loadGgf(text);
+			if (result.success) {
+				setIsAiThinking(false);
+				setIsEditing(true);
+				setPassMessage('');
+				forceUpdate({});
+			} else {
+				alert(`GGFを読み込めませんでした: ${result.message}`);
+			}
+		}).catch(err => {
+			console.error('ファイルの読み込みに失敗しました:', err);
+		});
+    };
+
     // ボードの描画
     const renderBoard = () => {
         const size = 8;
         const cellSize = 40;
         const borderSize = 2;
         const gameFinished = gameEngine.isGameFinished();
-        const validMovesStr = gameEngine.getValidMoves();
+        const validMoves = gameEngine.getValidMoves();
+        const lastMove = gameEngine.getLastMove();
+        const flipOrder = gameEngine.getLastFlipOrder();
+        const flipKey = `flip-${gameEngine.getCurrentTurn()}`;
         const isPassActive = passMessage !== '';
         const currentPlayerType = gameEngine.getCurrentPlayerType();
 
@@ -509,8 +589,8 @@ function App() {
                             <tr key={row}>
                                 {[...Array(size)].map((_, col) => {
                                     const cellType = gameEngine.getCell(row, col);
-                                    const isValidMove = !gameFinished && !isPassActive && !showMenu && !isAiThinking && currentPlayerType === 'human' && validMovesStr[row * 8 + col] === '1';
-									const isLastMove = (gameEngine.getLastMove() === row * 8 + col);
+                                    const isValidMove = !gameFinished && !isPassActive && !showMenu && !isAiThinking && currentPlayerType === 'human' && validMoves.includes(squareName(row, col));
+									const isLastMove = (lastMove === squareName(row, col));
 
                                     return (
                                         <td
@@ -526,7 +606,7 @@ function App() {
                                             }}
                                             onClick={() => handleCellClick(row, col)}
                                         >
-                                            <RenderStone type={cellType} isValidMove={isValidMove} isLastMove={isLastMove} />
+                                            <RenderStone type={cellType} isValidMove={isValidMove} isLastMove={isLastMove} flipOrder={flipOrder[squareName(row, col)]} flipKey={flipKey} />
                                         </td>
                                     );
                                 })}
@@ -582,8 +662,8 @@ function App() {
                             White: {gameEngine.getWhiteStoneCount()} <br />
                             Black: {gameEngine.getBlackStoneCount()} <br />
                             <br />
-                            {gameEngine.getGameState() === 'BlackWin' ? 'Black Win' : 
-                                gameEngine.getGameState() === 'WhiteWin' ? 'White Win' : 'Draw'}
+                            {gameEngine.getGameState()?.state === 'BlackWin' ? 'Black Win' :
+                                gameEngine.getGameState()?.state === 'WhiteWin' ? 'White Win' : 'Draw'}
                         </div>
                     </div>
                 )}
@@ -616,6 +696,9 @@ function App() {
 					onRedo={handleRedo}
 					onShowMenu={handleShowMenu}
 					onDownloadLog={handleDownloadLog}
+					onLoadLog={handleLoadLog}
+					onSaveGgf={handleSaveGgf}
+					onOpenGgf={handleOpenGgf}
 				/>
 			</div>
 		</div>
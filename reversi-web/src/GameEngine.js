@@ -1,82 +1,140 @@
+// 行と列(0〜7)からマスの名前('a1'〜'h8')を作る
+export function squareName(row, col) {
+	return String.fromCharCode(97 + col) + (row + 1);
+}
+
 export class GameEngine {
 	constructor(wasmModule) {
 		this.wasmModule = wasmModule;
-		this.initialBoard = '...........................WB......BW...........................';
-		this.BoardHistory = [];
-		this.CurrentBoardIdx = 0;
+		// 盤面・手番・手の列・パス・待ったはRustのGameが持つ
+		this.game = null;
 		this.playerModes = {
 			black : 'human',
 			white : 'human'
 		};
+		// AIの探索エンジンは黒白で別々に持つ(置換表や評価関数の設定を共有しない)
+		// エンジンは置換表などで大きいので、AIの手番で初めて作り、リセットと破棄で解放する
+		this.aiEngines = {
+			black : null,
+			white : null
+		};
+		// 黒白のエンジンに設定した値({名前: 値})、作り直したエンジンにも設定する
+		this.engineOptions = {
+			black : {},
+			white : {}
+		};
 		this.reset();
 	}
 
-	// ゲームをリセット
+	// 探索エンジンを作って設定を写す、作れなければnull(既定のエンジンを使う)
+	createEngine(player) {
+		try {
+			const engine = new this.wasmModule.Engine();
+			for (const [name, value] of Object.entries(this.engineOptions[player])) {
+				engine.set_option(name, value);
+			}
+			return engine;
+		}
+		catch (error) {
+			console.error('Error creating engine:', error);
+			return null;
+		}
+	}
+
+	// 黒白のエンジンを解放する
+	freeEngines() {
+		for (const player of ['black', 'white']) {
+			this.aiEngines[player]?.free();
+			this.aiEngines[player] = null;
+		}
+	}
+
+	// 手番のプレイヤーの探索エンジン(AIでなければ既定のエンジン)
+	getPlayerEngine() {
+		const player = this.getCurrentPlayer() ? 'white' : 'black';
+		if (this.playerModes[player] !== 'ai') {
+			return this.wasmModule;
+		}
+		this.aiEngines[player] ??= this.createEngine(player);
+		return this.aiEngines[player] ?? this.wasmModule;
+	}
+
+	// 既定のエンジンと、作ってある黒白のエンジン
+	getAllEngines() {
+		return [this.wasmModule, this.aiEngines.black, this.aiEngines.white].filter(engine => engine !== null);
+	}
+
+	// ゲームをリセット(黒白のエンジンも解放し、次の対局では空の置換表から読む)
 	reset() {
-		this.boardStr = this.initialBoard;
-		this.BoardHistory = [{board: this.initialBoard, turn: 0}];
-		this.CurrentBoardIdx = 0;
-		this.turn = 0;
+		this.game?.free();
+		this.game = new this.wasmModule.Game();
+		this.freeEngines();
+		this.searchStatsHistory = [];
+	}
+
+	// 盤面とエンジンを解放する(これ以降は使えない)
+	dispose() {
+		this.game?.free();
+		this.game = null;
+		this.freeEngines();
 	}
 
-	// 現在の盤面状態を取得
+	// 現在の盤面状態を取得（B: 黒, W: 白, .: 空き）
 	getCurrentBoard() {
-		return this.boardStr;
+		return this.game.board();
 	}
 
-	// 現在のターン数を取得
+	// 現在のターン数を取得（パスを含む）
 	getCurrentTurn() {
-		return this.turn;
+		return this.game.ply();
 	}
 
+	// 人間にした側のエンジンは解放する
 	setPlayerMode(BlackMode, WhiteMode){
 		this.playerModes = {
 			black : BlackMode,
 			white : WhiteMode
 		};
+		for (const player of ['black', 'white']) {
+			if (this.playerModes[player] !== 'ai') {
+				this.aiEngines[player]?.free();
+				this.aiEngines[player] = null;
+			}
+		}
 	}
 
 	// 現在のプレイヤー（false: 黒, true: 白）
 	getCurrentPlayer() {
-		return this.turn % 2 !== 0;
+		return this.game.current_player();
 	}
 
 	getCurrentPlayerType() {
-		return this.turn % 2 === 0 ? this.playerModes.black : this.playerModes.white;
+		return this.getCurrentPlayer() ? this.playerModes.white : this.playerModes.black;
 	}
 
 	getOpponentPlayerType() {
-		return this.turn % 2 === 0 ? this.playerModes.white : this.playerModes.black;
+		return this.getCurrentPlayer() ? this.playerModes.black : this.playerModes.white;
 	}
 
-	// 指定座標の石の状態を取得（0:空, 1:黒石, 2:白石）
+	// 指定座標の石を取得（'Black', 'White', 空きならnull）
 	getCell(row, col) {
-		const pos = row * 8 + col;
-		const char = this.boardStr[pos];
-		if (char === 'B') return 1;
-		if (char === 'W') return 2;
-		return 0;
+		return this.game.disc(squareName(row, col)) ?? null;
 	}
 
 	// 黒石の数を取得
 	getBlackStoneCount() {
-		return (this.boardStr.match(/B/g) || []).length;
+		return this.game.black_count();
 	}
 
 	// 白石の数を取得
 	getWhiteStoneCount() {
-		return (this.boardStr.match(/W/g) || []).length;
+		return this.game.white_count();
 	}
 
 	// 指定座標が合法手かどうかを判定
 	isValidMove(row, col) {
 		try {
-			const place = row * 8 + col;
-
-			if (this.boardStr[place] !== '.') return false;
-
-			const validMovesStr = this.wasmModule.get_valid_moves(this.boardStr, this.getCurrentPlayer());
-			return validMovesStr[place] === '1';
+			return this.game.is_legal(squareName(row, col));
 		}
 		catch (error) {
 			console.error('Error in isValidMove:', error);
@@ -84,75 +142,75 @@ export class GameEngine {
 		}
 	}
 
-	// 有効手のビットマスク文字列を取得
+	// 合法手のマスの配列を取得（['d3', 'c4', ...]）
 	getValidMoves() {
 		try {
-			return this.wasmModule.get_valid_moves(this.boardStr, this.getCurrentPlayer());
+			return this.game.legal_moves();
 		}
 		catch (error) {
 			console.error('Error getting valid moves:', error);
-			return '0'.repeat(64);
+			return [];
 		}
 	}
 
-	// 直前の盤面に戻す
-	undoBoard() {
-		if (this.CurrentBoardIdx > 0) {
-			this.CurrentBoardIdx -= 1;
-			this.boardStr = this.BoardHistory[this.CurrentBoardIdx].board;
-			this.turn = this.BoardHistory[this.CurrentBoardIdx].turn;
+	// 確定石のマスの配列を取得（黒白両方）
+	getStableDiscs() {
+		try {
+			return this.wasmModule.get_stable_discs(this.getCurrentBoard());
+		}
+		catch (error) {
+			console.error('Error getting stable discs:', error);
+			return [];
 		}
 	}
 
+	// 空きマスの領域と偶奇を取得
+	// [{squares: ['a1', 'b1', ...], odd: true}, ...]
+	getParityRegions() {
+		try {
+			return this.wasmModule.get_parity_regions(this.getCurrentBoard());
+		}
+		catch (error) {
+			console.error('Error getting parity regions:', error);
+			return [];
+		}
+	}
+
+	// 直前の盤面に戻す（パスも一緒に戻す）
+	undoBoard() {
+		this.game.undo();
+	}
+
 	// 直前の盤面に戻せるか
 	undoable() {
-		return this.CurrentBoardIdx > 0;
+		return this.game.can_undo();
 	}
 
 	// 直後の盤面に進める
 	redoBoard() {
-		if (this.CurrentBoardIdx < this.BoardHistory.length - 1) {
-			this.CurrentBoardIdx += 1;
-			this.boardStr = this.BoardHistory[this.CurrentBoardIdx].board;
-			this.turn = this.BoardHistory[this.CurrentBoardIdx].turn;
-		}
+		this.game.redo();
 	}
 
 	// 直後の盤面に進めるか
 	redoable() {
-		return this.CurrentBoardIdx < this.BoardHistory.length - 1;
+		return this.game.can_redo();
 	}
 
-	// 手を実行
+	// 手を実行（相手が打てなければ自動でパスする）
+	// 戻した後に別の手を打つと、そこから先の手は消える
 	makeMove(row, col) {
 		if (!this.isValidMove(row, col)) {
 			return {success: false, message: 'Invalid move'};
 		}
+		return this.playMove(squareName(row, col));
+	}
 
+	// マスの名前('f5'など)で手を実行
+	playMove(square) {
 		try {
-			const pos = row * 8 + col;
 			const currentPlayer = this.getCurrentPlayer();
-
-			const updatedBoardStr = this.wasmModule.update_board(this.boardStr, pos, !currentPlayer);
-			this.boardStr = updatedBoardStr;
-			this.turn += 1;
-
-			const passMessage = this.handlePass();
-
-			if (this.BoardHistory.length !== this.CurrentBoardIdx + 1){
-				if (this.BoardHistory[this.CurrentBoardIdx + 1].board === updatedBoardStr){
-					this.CurrentBoardIdx += 1;
-				}
-				else {
-					this.BoardHistory = this.BoardHistory.slice(0, this.CurrentBoardIdx + 1);
-					this.BoardHistory.push({board: updatedBoardStr, turn: this.turn});
-					this.CurrentBoardIdx += 1;
-				}
-			}
-			else{
-				this.BoardHistory.push({board: updatedBoardStr, turn: this.turn});
-				this.CurrentBoardIdx += 1;
-			}
+			const passed = this.game.play(square);
+			const passMessage = passed ? `${currentPlayer ? 'Black' : 'White'} passed` : '';
 
 			return {
 				success: true,
@@ -162,92 +220,254 @@ export class GameEngine {
 		}
 		catch (error) {
 			console.error('Error during move:', error);
-			return {success: false, message: 'Error'};
-		}
-	}
-
-	// パス処理
-	handlePass() {
-		let passMessage = '';
-		const nextPlayer = this.getCurrentPlayer();
-
-		if (this.wasmModule.is_pass(this.boardStr, nextPlayer)) {
-			const playerName = nextPlayer ? 'White' : 'Black';
-			passMessage = `${playerName} passed`;
-			this.turn += 1;
+			return {success: false, message: error.message};
 		}
-		return passMessage;
 	}
 
 	// ゲーム状態を取得
-	/*
-	WhiteWin
-	BlackWin
-	Draw
-	Ongoing
-	Error
-	*/
+	// {state, black, white, reason}
+	// stateは'BlackWin', 'WhiteWin', 'Draw', 'Ongoing'、取得できなければnull
 	getGameState() {
 		try {
-			return this.wasmModule.get_result(this.boardStr);
+			return this.game.result();
 		}
 		catch (error) {
 			console.error('Error getting game state:', error);
-			return 'Error';
+			return null;
 		}
 	}
 
+	// 終局の理由（BoardFull: 盤面が埋まった, Wipeout: 片方の石がなくなった, NoMoves: 両者とも打てない）
+	// 終わっていなければnull
+	getEndReason() {
+		return this.getGameState()?.reason ?? null;
+	}
+
 	// ゲームが終了しているかどうか
 	isGameFinished() {
-		const state = this.getGameState();
-		return state !== 'Ongoing';
+		const result = this.getGameState();
+		return result === null || result.state !== 'Ongoing';
 	}
 
-	// AI手を取得
+	// AI手を取得（'f5'などのマスの名前、パスなら'pass'、失敗したらnull）
 	getAIMove(timeMs = 1000, level = 10) {
 		const turn = this.getCurrentTurn();
 		const disturbance = (10 - level) * (10 - level) * 8 * Math.max(0.1, Math.sqrt(64 - turn) / 8);
 		try {
-			const result = this.wasmModule.get_ai_move(this.boardStr, this.getCurrentPlayer(), timeMs, disturbance);
+			const engine = this.getPlayerEngine();
+			const result = engine.get_ai_move(this.getCurrentBoard(), this.getCurrentPlayer(), timeMs, disturbance);
+			this.recordSearchStats(engine.get_search_stats(), result);
 
 			return result;
 		}
 		catch (error) {
 			console.error('Error getting AI move:', error);
-			return 64;
+			return null;
 		}
 	}
 
-	// 最後の手を取得
-	getLastMove(){
-		if (this.CurrentBoardIdx === 0) return null;
+	// 制限つきでAI手を取得
+	// limits: {max_depth, max_nodes, max_time_ms, solve_empties}(省略したものは制限しない)
+	// 深さとノード数だけで制限すると端末の速さによらず同じ手になる
+	getAIMoveWithLimits(limits, level = 10) {
+		const turn = this.getCurrentTurn();
+		const disturbance = (10 - level) * (10 - level) * 8 * Math.max(0.1, Math.sqrt(64 - turn) / 8);
+		try {
+			const engine = this.getPlayerEngine();
+			const result = engine.get_ai_move_with_limits(this.getCurrentBoard(), this.getCurrentPlayer(), limits, disturbance);
+			this.recordSearchStats(result.stats, result.mv);
+			return result.mv;
+		}
+		catch (error) {
+			console.error('Error getting AI move with limits:', error);
+			return null;
+		}
+	}
+
+	// 各合法手の評価を取得（石差・勝率）
+	// [{mv: 'f5', depth, score: {raw, disc_diff, win_prob}}, ...] の形で良い順に並ぶ
+	getHints(timeMs = 1000) {
+		try {
+			return this.wasmModule.get_hints(this.getCurrentBoard(), this.getCurrentPlayer(), timeMs);
+		}
+		catch (error) {
+			console.error('Error getting hints:', error);
+			return [];
+		}
+	}
+
+	// 終盤を読み切る、{score, mv, confidence}(confidenceは結果の確からしさ%)
+	// 時間内に最も低い段階も終わらなければnull
+	solveEndgame(timeMs = 1000) {
+		try {
+			return this.wasmModule.solve_endgame(this.getCurrentBoard(), this.getCurrentPlayer(), timeMs) ?? null;
+		}
+		catch (error) {
+			console.error('Error solving endgame:', error);
+			return null;
+		}
+	}
 
-		for (let i = 0; i < 64; i++) {
-			if ((this.BoardHistory[this.CurrentBoardIdx - 1].board[i] === '.') !== (this.BoardHistory[this.CurrentBoardIdx].board[i] === '.')) {
-				return i;
+	// 評価関数を切り替える（'network': 高精度, 'linear': 軽量で深く読める）
+	// playerに'black'か'white'を渡すとそのプレイヤーのAIだけ切り替える
+	setEvaluator(name, player = null) {
+		return this.setOption('evaluator', name, player);
+	}
+
+	// 選択的探索(Multi-ProbCut)の有無を切り替える、解析ではfalseにして全幅で読む
+	setSelectivity(enabled) {
+		this.setOption('selectivity', String(enabled));
+	}
+
+	// 名前で設定を変える(値は文字列)、playerを省けば全てのエンジン
+	// まだ作っていない黒白のエンジンには、作るときに設定する
+	// 変えられたらtrue
+	setOption(name, value, player = null) {
+		try {
+			const engines = player ? [this.aiEngines[player]].filter(engine => engine !== null) : this.getAllEngines();
+			engines.forEach(engine => engine.set_option(name, String(value)));
+			for (const p of player ? [player] : ['black', 'white']) {
+				this.engineOptions[p][name] = String(value);
 			}
+			return true;
+		}
+		catch (error) {
+			console.error('Error setting option:', error);
+			return false;
 		}
+	}
+
+	// 設定の一覧 [{name, type, min, max, values, default, description}]
+	getOptionList() {
+		try {
+			return this.wasmModule.get_option_list();
+		}
+		catch (error) {
+			console.error('Error getting option list:', error);
+			return [];
+		}
+	}
 
-		return null;
+	// 置換表の大きさ(MB)を変える
+	setHashSize(sizeMb) {
+		this.setOption('hash', sizeMb);
+	}
+
+	// 置換表を空にする
+	clearHash() {
+		try {
+			this.getAllEngines().forEach(engine => engine.clear_hash());
+		}
+		catch (error) {
+			console.error('Error clearing hash:', error);
+		}
+	}
+
+	// 手番のプレイヤーのAIの直前の探索の置換表の統計 {size_mb, probes, hits, hit_rate, fill}
+	getHashStats() {
+		try {
+			return this.getPlayerEngine().get_hash_stats();
+		}
+		catch (error) {
+			console.error('Error getting hash stats:', error);
+			return null;
+		}
+	}
+
+	// 探索の統計を手番と選んだ手と一緒に対局の記録に加える
+	recordSearchStats(stats, mv) {
+		this.searchStatsHistory.push({turn: this.getCurrentTurn(), player: this.getCurrentPlayer() ? 'white' : 'black', mv, ...stats});
+	}
+
+	// 直前のAIの探索の統計
+	// {nodes, helper_nodes, evals, tt_probes, tt_hits, tt_cutoffs, solver_nodes, depth, selective_depth, time_ms, nps, book, solver, selective_solver, solver_confidence, score}
+	getSearchStats() {
+		return this.searchStatsHistory.at(-1) ?? null;
+	}
+
+	// 対局中のAIの探索の統計を手の順に([{turn, player, mv, ...}])、グラフに使う
+	getSearchStatsHistory() {
+		return this.searchStatsHistory;
+	}
+
+	// 最後に石を置いたマスを取得（'f5'など、なければnull）
+	getLastMove(){
+		return this.game.last_move() ?? null;
+	}
+
+	// 最後に石を置いた手で返した石を、返す順の遅れ(置いたマスからの距離)ごとに取得
+	// {'e5': 1, 'e6': 1, 'e7': 2, ...}、なければ空
+	getLastFlipOrder(){
+		const order = {};
+		const flips = this.game.last_flips();
+		if (flips) {
+			for (const ray of Object.values(flips.directions)) {
+				ray.forEach((square, i) => order[square] = i + 1);
+			}
+		}
+		return order;
 	}
 
 	// 棋譜を取得
 	getKif(){
-		let kif = '';
-		for (let i = 0; i < this.CurrentBoardIdx; i++) {
-			let t = -1;
-			for (let j = 0; j < 64; j++) {
-				if ((this.BoardHistory[i].board[j] === '.') !== (this.BoardHistory[i + 1].board[j] === '.')) {
-					t = j;
-					break;
-				}
+		return this.game.transcript();
+	}
+
+	// 棋譜(f5d6...、大文字も可、パスは書いても省いてもよい)を初期局面から再生して続きから打つ
+	// 待ったで途中の局面を見返せる
+	// 読めない手や不正な手があれば盤面を変えずに {success: false, message} を返す
+	loadKif(kif){
+		try {
+			const game = this.wasmModule.Game.from_transcript(kif);
+			this.game.free();
+			this.game = game;
+			this.searchStatsHistory = [];
+			return {success: true};
+		}
+		catch (error) {
+			console.error('Error loading transcript:', error);
+			return {success: false, message: error.message};
+		}
+	}
+
+	// GGFの棋譜を取得(対局者は人間かAIか、日時は今)
+	// AIの手には探索の評価値(予想石差)と時間(秒)を書く、待ったの後に別の手を打った手には書かない
+	getGgf(){
+		const moves = this.game.moves();
+		const evals = moves.map(() => null);
+		const times = moves.map(() => null);
+		for (const stats of this.searchStatsHistory) {
+			if (stats.turn < moves.length && moves[stats.turn].mv === stats.mv) {
+				evals[stats.turn] = stats.score ?? null;
+				times[stats.turn] = stats.time_ms / 1000;
 			}
+		}
+		const name = mode => mode === 'ai' ? 'fl-reversi' : 'human';
+		const pad = n => String(n).padStart(2, '0');
+		const now = new Date();
+		const date = `${now.getFullYear()}.${pad(now.getMonth() + 1)}.${pad(now.getDate())}_${pad(now.getHours())}:${pad(now.getMinutes())}:${pad(now.getSeconds())}`;
+		return this.game.to_ggf({
+			black_player: name(this.playerModes.black),
+			white_player: name(this.playerModes.white),
+			date,
+			evals,
+			times,
+		});
+	}
 
-			if (t === -1) continue;
-			let x = Math.floor(t / 8);
-			let y = t % 8;
-			kif += `${String.fromCharCode(97 + y)}${x + 1}`;
+	// GGFの棋譜の最初の対局を開始局面から再生して続きから打つ(8x8以外の盤は読めない)
+	// 読めないものや不正な手があれば盤面を変えずに {success: false, message} を返す
+	loadGgf(text){
+		try {
+			const game = this.wasmModule.Game.from_ggf(text);
+			this.game.free();
+			this.game = game;
+			this.searchStatsHistory = [];
+			return {success: true};
+		}
+		catch (error) {
+			console.error('Error loading GGF:', error);
+			return {success: false, message: error.message};
 		}
-		return kif;
 	}
 }